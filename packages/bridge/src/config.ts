@@ -1,10 +1,26 @@
 import { readFileSync } from "fs"
+import type { MediaCacheConfig, MediaStrategy, OutboxConfig, SendRateLimitConfig, Transport } from "@laikabridge/matrix-qq-bridge-runtime"
 import YAML from "yaml"
+import type { AvatarEmojiConfig } from "./avatar-color"
 import { CONFIG_PATH } from "./workdir"
+interface TlsConfig {
+    caFile?: string
+    clientCert?: string
+    clientKey?: string
+}
 interface MiraiConfig {
     host: string
     verifyKey: string
     qq: number
+    headers?: Record<string, string>
+    tls?: TlsConfig
+    transport?: Transport
+    // Journal for messages sent while disconnected; defaults to outbox.sqlite3 in the workdir
+    outbox?: OutboxConfig
+    // Per-group and global send rate limits; the runtime schedules every outbound message
+    sendRateLimit?: SendRateLimitConfig
+    // Largest GIF sent to QQ, in KiB; animations are shrunk until they fit. Defaults to 3072
+    imageMaxSizeKb?: number
 }
 interface MatrixRegistration {
     path: string
@@ -46,7 +62,15 @@ interface Config {
     downloadImage: {
         baseurl: string,
         authorizationHeader: string,
+        // Tried in order; defaults to Sidecar then SegmentUrl
+        strategies?: MediaStrategy[],
+        // Directory LocalPath may read from
+        localRoot?: string,
+        // Largest file to download, in MiB; defaults to 20
+        maxSizeMb?: number,
     }
+    mediaCache?: MediaCacheConfig
+    avatarEmoji?: AvatarEmojiConfig
     //    rembgService: RembgConfig
 }
 
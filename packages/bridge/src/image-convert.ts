@@ -1,3 +1,4 @@
+import { convertImage, convertToGif, GifOptions, isAnimated } from "@laikabridge/matrix-qq-bridge-runtime";
 import concatStream from "concat-stream";
 import ffmpeg from "fluent-ffmpeg";
 import { Readable } from "node:stream";
@@ -29,6 +30,7 @@ export const SUPPORTED_MIMES = {
 } as const;
 export const SUPPORTED_MIME_LIST = Object.keys(SUPPORTED_MIMES) as Mime[];
 export type Mime = keyof typeof SUPPORTED_MIMES;
+export const QQ_IMAGE_MAX_SIZE_KB = 3 * 1024;
 export interface MimedImage {
     mime: Mime;
     data: Uint8Array;
@@ -60,9 +62,19 @@ export function withResolvers<T, E>() {
     return { promise, resolve, reject };
 
 }
-export async function convertTo(image: MimedImage, target: Mime): Promise<MimedImage> {
+export async function convertTo(image: MimedImage, target: Mime, gifOptions?: GifOptions): Promise<MimedImage> {
     const mimeInfo = SUPPORTED_MIMES[target];
     const srcMimeInfo = SUPPORTED_MIMES[image.mime];
+    // 静态图片在 runtime 里转换，不启动 ffmpeg
+    if (!srcMimeInfo.isAnimated && !mimeInfo.isAnimated) {
+        const converted = await convertImage(Buffer.from(image.data), target);
+        return { mime: converted.mime as Mime, data: converted.buffer };
+    }
+    // 动图转 GIF 也在 runtime 里做，每帧单独量化调色板
+    if (target == "image/gif" && image.mime.startsWith("image/")) {
+        const converted = await convertToGif(Buffer.from(image.data), gifOptions);
+        return { mime: target, data: converted.buffer };
+    }
     const stream = createPipe();
     const ffmpegFin = withResolvers();
     ffmpeg()
@@ -85,6 +97,9 @@ export async function convertTo(image: MimedImage, target: Mime): Promise<MimedI
 export abstract class Target {
     abstract preferredMime(animated: boolean): Mime;
     abstract compatibleMimes(): Mime[];
+    gifOptions(): GifOptions | undefined {
+        return undefined;
+    }
 
     async convert(image: MimedImage | Uint8Array): Promise<MimedImage> {
         if (image instanceof Uint8Array) {
@@ -92,13 +107,15 @@ export abstract class Target {
             return this.convert(mimedImage);
         }
         const mime = image.mime;
-        logger.debug({ mime, compatible: this.compatibleMimes() }, "Received mime.");
-        if (this.compatibleMimes().indexOf(mime) != -1) {
+        // 动态 WebP 和 APNG 的 mime 和静态图片一样，要看帧数才知道
+        const animated = SUPPORTED_MIMES[mime].isAnimated || await isAnimated(Buffer.from(image.data));
+        logger.debug({ mime, animated, compatible: this.compatibleMimes() }, "Received mime.");
+        if (this.compatibleMimes().indexOf(mime) != -1 && animated == SUPPORTED_MIMES[mime].isAnimated) {
             return image;
         }
-        const targetMime = this.preferredMime(SUPPORTED_MIMES[mime].isAnimated);
-        logger.debug({image: image.data.constructor.name, targetMime}, "Converting to preferred mime " + this.preferredMime(SUPPORTED_MIMES[mime].isAnimated));
-        const xs = await convertTo(image, targetMime);
+        const targetMime = this.preferredMime(animated);
+        logger.debug({image: image.data.constructor.name, targetMime}, "Converting to preferred mime " + targetMime);
+        const xs = await convertTo(image, targetMime, this.gifOptions());
         logger.debug("Converted!")
         return xs
     }
@@ -106,6 +123,13 @@ export abstract class Target {
 }
 
 export class QQTarget extends Target {
+    // 超过 QQ 的大小上限时 runtime 先丢帧再缩小
+    constructor(private maxSizeKb?: number) {
+        super();
+    }
+    gifOptions(): GifOptions {
+        return { maxSizeKb: this.maxSizeKb ?? QQ_IMAGE_MAX_SIZE_KB };
+    }
     preferredMime(animated: boolean): Mime {
         return animated ? "image/gif" : "image/png";
     }
@@ -122,8 +146,8 @@ export class MXTarget extends Target {
     }
 }
 
-export async function convertToQQ(buffer: Uint8Array) {
-    return (new QQTarget()).convert(buffer);
+export async function convertToQQ(buffer: Uint8Array, maxSizeKb?: number) {
+    return (new QQTarget(maxSizeKb)).convert(buffer);
 }
 export async function convertToMX(buffer: Uint8Array) {
     return (new MXTarget()).convert(buffer);
@@ -17,15 +17,15 @@ import fetch from "node-fetch";
 import { HTMLElement, type Node, TextNode, parse } from "node-html-parser";
 import { LocalStorage } from "./storage";
 import { SocksProxyAgent } from "socks-proxy-agent";
-import throttledQueue from "throttled-queue";
 import { readConfig } from "./config";
+import { imageInfo, isAnimated, mediaHash, PerceptualIndex, perceptualHash } from "@laikabridge/matrix-qq-bridge-runtime";
 import { MiraiOnebotAdaptor, MockForward } from "./onebot-client";
 import { MockMessageChain as MessageChain, MockGroupTarget as GroupTarget, MockGroupSender as GroupSender } from "./onebot-client";
 import { Plain, At, Image } from "./onebot-client";
 import { SUPPORTED_MIMES, convertToMX, convertToQQ, guessMime, withResolvers } from "./image-convert";
 import { mumbleBridgePlugin } from "./plugins/mumble/mumble-bridge";
 import { pluginGeminiMessage } from "./plugins/gemini/gemini";
-import { calcAvatarEmoji } from "./avatar-color";
+import { cachedAvatar, calcAvatarEmoji, configureAvatarEmoji } from "./avatar-color";
 import { fetchMXC } from "./mxc-fetch";
 
 import { logger } from "./logger";
@@ -38,11 +38,23 @@ import { DATABASE_PATH, workdir_relative } from "./workdir";
 //});
 
 const config = readConfig();
+configureAvatarEmoji(config.avatarEmoji);
 
 const localStorage = new LocalStorage(DATABASE_PATH);
 
 const BOT_UPDATE_VERSION = 1;
 
+// 看起来一样的静态图复用已有的上传，按转换后的 mime 和尺寸分开，免得 GIF 复用成 PNG、小图复用成大图
+// 尺寸五花八门，只留最近用过的这么多组，Map 的顺序就是使用顺序
+const similarImages = new Map<string, PerceptualIndex>();
+const SIMILAR_GROUPS = 64;
+const SIMILAR_DISTANCE = 2;
+// 一分钟内同一张图出现这么多次就记一条警告；动图只比第一帧，只用来计数
+const recentImages = new PerceptualIndex();
+const FLOOD_DISTANCE = 6;
+const FLOOD_WINDOW_MS = 60_000;
+const FLOOD_COUNT = 5;
+
 let agent: SocksProxyAgent | any | undefined = undefined;
 
 if (config.socksProxy.enable) {
@@ -63,16 +75,19 @@ if (config.socksProxy.enable) {
     };
 }
 
-const throttle = throttledQueue(1, 1000);
-
 const bot = new MiraiOnebotAdaptor({
     host: config.mirai.host,
     // mirai-api-http-2.x
     verifyKey: config.mirai.verifyKey,
     qq: config.mirai.qq,
+    headers: config.mirai.headers,
+    tls: config.mirai.tls,
+    transport: config.mirai.transport,
+    outbox: config.mirai.outbox,
+    sendRateLimit: config.mirai.sendRateLimit,
     enableWebsocket: false,
     wsOnly: false,
-}, config.downloadImage);
+}, config.downloadImage, config.mediaCache);
 
 // auth 认证(*)
 bot.onSignal("authed", () => {
@@ -297,6 +312,8 @@ new Cli({
                         mxurl?: string,
                     ): Promise<string> {
                         if (!mxurl) return "";
+                        const cached = cachedAvatar(mxurl);
+                        if (cached) return cached;
 
                         /*
                         const url = intent.matrixClient.mxcToHttp(mxurl);
@@ -304,7 +321,7 @@ new Cli({
                         const buffer = new Uint8Array(await req.arrayBuffer());
                         */
                         const buffer = await fetchMXC(adminIntent, mxurl);
-                        return calcAvatarEmoji(buffer);
+                        return calcAvatarEmoji(buffer, mxurl);
                     }
                     if (!prev_name_dict[event.room_id])
                         prev_name_dict[event.room_id] = {};
@@ -454,13 +471,11 @@ new Cli({
                                 const s = (event.content
                                     .formatted_body ?? event.content.body) as string;
                                 msgText = s;
-                                msg = await throttle(async () => {
-                                    return await bot.sendQuotedGroupMessage(
-                                        htmlToMsgChain(s),
-                                        qq_id,
-                                        l4[1],
-                                    );
-                                });
+                                msg = await bot.sendQuotedGroupMessage(
+                                    htmlToMsgChain(s),
+                                    qq_id,
+                                    l4[1],
+                                );
                             } else {
                                 const s = event.content.body as string;
                                 msgText = s;
@@ -471,13 +486,11 @@ new Cli({
                                 ) {
                                     lines = lines.splice(2);
                                 }
-                                msg = await throttle(async () => {
-                                    return await bot.sendQuotedGroupMessage(
-                                        `${name}: ${lines.join("\n")}`,
-                                        qq_id,
-                                        l4[1],
-                                    );
-                                });
+                                msg = await bot.sendQuotedGroupMessage(
+                                    `${name}: ${lines.join("\n")}`,
+                                    qq_id,
+                                    l4[1],
+                                );
                             }
                         } else {
                             if (
@@ -486,20 +499,16 @@ new Cli({
                                 const s = (event.content
                                     .formatted_body ?? event.content.body) as string;
                                 msgText = s;
-                                msg = await throttle(async () => {
-                                    return await bot.sendGroupMessage(
-                                        htmlToMsgChain(s),
-                                        qq_id,
-                                    );
-                                });
+                                msg = await bot.sendGroupMessage(
+                                    htmlToMsgChain(s),
+                                    qq_id,
+                                );
                             } else {
                                 msgText = event.content.body as string;
-                                msg = await throttle(async () => {
-                                    return await bot.sendGroupMessage(
-                                        `${name}: ${event.content.body}`,
-                                        qq_id,
-                                    );
-                                });
+                                msg = await bot.sendGroupMessage(
+                                    `${name}: ${event.content.body}`,
+                                    qq_id,
+                                );
                             }
                         }
                         const source: [string, string] = [
@@ -564,28 +573,24 @@ new Cli({
                                 },
                             };
                             if (l4) {
-                                msg = await throttle(async () => {
-                                    const image = await bot.uploadImage(
-                                        Buffer.from(buffer),
-                                        target,
-                                    );
-                                    return await bot.sendQuotedGroupMessage(
-                                        [Plain(`${name}:`), Image(image)],
-                                        qq_id,
-                                        l4[1],
-                                    );
-                                });
+                                const image = await bot.uploadImage(
+                                    Buffer.from(buffer),
+                                    target,
+                                );
+                                msg = await bot.sendQuotedGroupMessage(
+                                    [Plain(`${name}:`), Image(image)],
+                                    qq_id,
+                                    l4[1],
+                                );
                             } else {
-                                msg = await throttle(async () => {
-                                    const image = await bot.uploadImage(
-                                        Buffer.from(buffer),
-                                        target,
-                                    );
-                                    return await bot.sendGroupMessage(
-                                        [Plain(`${name}:`), Image(image)],
-                                        qq_id,
-                                    );
-                                });
+                                const image = await bot.uploadImage(
+                                    Buffer.from(buffer),
+                                    target,
+                                );
+                                msg = await bot.sendGroupMessage(
+                                    [Plain(`${name}:`), Image(image)],
+                                    qq_id,
+                                );
                             }
 
                             const source: [string, string] = [
@@ -613,7 +618,7 @@ new Cli({
                         try {
                             const buf = await fetchMXC(adminIntent, event.content.url as string);
                             const srcMime = event.content.mimetype as string;
-                            const converted = await convertToQQ(Buffer.from(buf));
+                            const converted = await convertToQQ(Buffer.from(buf), config.mirai.imageMaxSizeKb);
                             const imgbuf = converted.data;
                             const mime = converted.mime;
                             let msg;
@@ -627,28 +632,24 @@ new Cli({
                                 },
                             };
                             if (l4) {
-                                msg = await throttle(async () => {
-                                    const image = await bot.uploadImage(
-                                        Buffer.from(imgbuf),
-                                        target,
-                                    );
-                                    return await bot.sendQuotedGroupMessage(
-                                        [Plain(`${name}:`), Image(image, mime)],
-                                        qq_id,
-                                        l4[1],
-                                    );
-                                });
+                                const image = await bot.uploadImage(
+                                    Buffer.from(imgbuf),
+                                    target,
+                                );
+                                msg = await bot.sendQuotedGroupMessage(
+                                    [Plain(`${name}:`), Image(image, mime)],
+                                    qq_id,
+                                    l4[1],
+                                );
                             } else {
-                                msg = await throttle(async () => {
-                                    const image = await bot.uploadImage(
-                                        Buffer.from(imgbuf),
-                                        target,
-                                    );
-                                    return await bot.sendGroupMessage(
-                                        [Plain(`${name}:`), Image(image, mime)],
-                                        qq_id,
-                                    );
-                                });
+                                const image = await bot.uploadImage(
+                                    Buffer.from(imgbuf),
+                                    target,
+                                );
+                                msg = await bot.sendGroupMessage(
+                                    [Plain(`${name}:`), Image(image, mime)],
+                                    qq_id,
+                                );
                             }
 
                             const source: [string, string] = [
@@ -671,7 +672,7 @@ new Cli({
                          */
                         const buf = await fetchMXC(adminIntent, event.content.url as string);
                         const srcMime = event.content.mimetype as string;
-                        const converted = await convertToQQ(buf);
+                        const converted = await convertToQQ(buf, config.mirai.imageMaxSizeKb);
                         const imgbuf = converted.data;
                         const mime = converted.mime;
                         try {
@@ -686,28 +687,24 @@ new Cli({
                                 },
                             };
                             if (l4) {
-                                msg = await throttle(async () => {
-                                    const image = await bot.uploadImage(
-                                        Buffer.from(imgbuf),
-                                        target,
-                                    );
-                                    return await bot.sendQuotedGroupMessage(
-                                        [Plain(`${name}:`), Image(image, mime)],
-                                        qq_id,
-                                        l4[1],
-                                    );
-                                });
+                                const image = await bot.uploadImage(
+                                    Buffer.from(imgbuf),
+                                    target,
+                                );
+                                msg = await bot.sendQuotedGroupMessage(
+                                    [Plain(`${name}:`), Image(image, mime)],
+                                    qq_id,
+                                    l4[1],
+                                );
                             } else {
-                                msg = await throttle(async () => {
-                                    const image = await bot.uploadImage(
-                                        Buffer.from(imgbuf),
-                                        target,
-                                    );
-                                    return await bot.sendGroupMessage(
-                                        [Plain(`${name}:`), Image(image, mime)],
-                                        qq_id,
-                                    );
-                                });
+                                const image = await bot.uploadImage(
+                                    Buffer.from(imgbuf),
+                                    target,
+                                );
+                                msg = await bot.sendGroupMessage(
+                                    [Plain(`${name}:`), Image(image, mime)],
+                                    qq_id,
+                                );
                             }
 
                             const source: [string, string] = [
@@ -1096,21 +1093,85 @@ new Cli({
                     try {
                         logger.info({ imageId }, "Fetching Image");
                         // create tempfile
-                        const imageBuffer = await bot.bot.downloadImage(imageId);
+                        const image = await bot.bot.downloadImage(imageId);
                         //const img = await fetch(url, { agent });
-                        const buffer = imageBuffer;
+                        const buffer = image.buffer;
                         const converted = await convertToMX(buffer);
-                        const content = await intent.uploadContent(
-                            Buffer.from(converted.data)
-                        );
+                        // 同样的表情包上传过就直接用原来的 mxc
+                        const hash = bot.bot.cachedImageHash(imageId) ?? mediaHash(buffer);
+                        let content = bot.bot.lookupMxc(hash);
                         const mimeInfo = SUPPORTED_MIMES[converted.mime];
+                        const info: Record<string, unknown> = {
+                            mimetype: converted.mime,
+                        };
+                        // 尺寸、缩略图和 blurhash 只是锦上添花，算不出来也照常发
+                        const details = mimeInfo.matrixMsgType == "m.image"
+                            ? await imageInfo(Buffer.from(converted.data)).catch((err) => {
+                                logger.warn({ imageId, err }, "Failed to compute image info");
+                                return undefined;
+                            })
+                            : undefined;
+                        // 重新压缩过的同一张图字节不同，按感知哈希再找一次；动图只看第一帧，不复用
+                        const phash = await perceptualHash(Buffer.from(converted.data)).then((x) => x.phash, () => undefined);
+                        const animated = await isAnimated(Buffer.from(converted.data)).catch(() => true);
+                        let similar: PerceptualIndex | undefined;
+                        if (phash && details && !animated) {
+                            const similarKey = `${converted.mime} ${details.width}x${details.height}`;
+                            similar = similarImages.get(similarKey) ?? new PerceptualIndex();
+                            similarImages.delete(similarKey);
+                            similarImages.set(similarKey, similar);
+                            if (similarImages.size > SIMILAR_GROUPS) {
+                                similarImages.delete(similarImages.keys().next().value!);
+                            }
+                        }
+                        if (!content && phash && similar) {
+                            content = similar.nearest(phash, SIMILAR_DISTANCE)?.key ?? null;
+                        }
+                        if (!content) {
+                            content = await intent.uploadContent(
+                                Buffer.from(converted.data)
+                            );
+                        }
+                        bot.bot.rememberMxc(hash, content);
+                        if (phash) {
+                            similar?.insert(phash, content);
+                            recentImages.insert(phash, content);
+                            const count = recentImages.countRecent(phash, FLOOD_DISTANCE, FLOOD_WINDOW_MS);
+                            if (count >= FLOOD_COUNT) {
+                                logger.warn({ group_id, imageId, count }, "Same image repeated in a short time");
+                            }
+                        }
+                        if (details) {
+                            try {
+                                const { width, height, size, thumbnail, blurhash } = details;
+                                const thumbnailHash = mediaHash(thumbnail.buffer);
+                                let thumbnailUrl = bot.bot.lookupMxc(thumbnailHash);
+                                if (!thumbnailUrl) {
+                                    thumbnailUrl = await intent.uploadContent(thumbnail.buffer, thumbnail.mime);
+                                    bot.bot.rememberMxc(thumbnailHash, thumbnailUrl);
+                                }
+                                Object.assign(info, {
+                                    w: width,
+                                    h: height,
+                                    size,
+                                    thumbnail_url: thumbnailUrl,
+                                    thumbnail_info: {
+                                        mimetype: thumbnail.mime,
+                                        w: thumbnail.width,
+                                        h: thumbnail.height,
+                                        size: thumbnail.buffer.length,
+                                    },
+                                    "xyz.amorgan.blurhash": blurhash,
+                                });
+                            } catch (err) {
+                                logger.warn({ imageId, err }, "Failed to upload thumbnail");
+                            }
+                        }
                         const { event_id } = await intent.sendMessage(mx_id, {
                             msgtype: mimeInfo.matrixMsgType,
                             url: content,
                             body: `QQ图片.${mimeInfo.format}`,
-                            info: {
-                                mimetype: converted.mime,
-                            },
+                            info,
                         });
 
                         await addMatrix2QQMsgMapping(event_id, qqsource);
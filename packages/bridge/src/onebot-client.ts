@@ -1,7 +1,8 @@
 import { EventEmitter } from "node:events";
 
-import { initialize, Mockv2MessageChain, QqBotEndpoint } from "@laikabridge/matrix-qq-bridge-runtime";
+import { DownloadImageEndpoint, initialize, MediaCacheConfig, Mockv2MessageChain, OutboxConfig, QqBotEndpoint, SendGroupMsgResp, SendRateLimitConfig, TlsConfig, Transport } from "@laikabridge/matrix-qq-bridge-runtime";
 import { logger } from "./logger";
+import { workdir_relative } from "./workdir";
 
 type image = Buffer;
 
@@ -58,6 +59,12 @@ function convertOutbound(msg: MockMessageChain[]): Mockv2MessageChain[] {
     return messages;
 }
 
+type QueuedResult = { messageId: string } | { error: Error };
+
+function outboxPath(path = "./outbox.sqlite3") {
+    return path === ":memory:" ? path : workdir_relative(path);
+}
+
 initialize();
 export class MiraiOnebotAdaptor {
     //ctx: Context;
@@ -76,19 +83,28 @@ export class MiraiOnebotAdaptor {
         host: string;
         verifyKey: string;
         qq: number;
+        headers?: Record<string, string>;
+        tls?: TlsConfig;
+        transport?: Transport;
+        outbox?: OutboxConfig;
+        sendRateLimit?: SendRateLimitConfig;
         enableWebsocket: boolean;
         wsOnly: boolean;
-    }, downloadConfig: {
-        baseurl: string,
-        authorizationHeader: string,
-    }) {
+    }, downloadConfig: DownloadImageEndpoint, mediaCache?: MediaCacheConfig) {
 
         this.ev = new EventEmitter();
 
         this.bot = new QqBotEndpoint({
             addr: config.host,
             accessToken: config.verifyKey,
-            downloadImage: downloadConfig
+            headers: config.headers,
+            tls: config.tls,
+            transport: config.transport,
+            qq: `${config.qq}`,
+            downloadImage: downloadConfig,
+            mediaCache,
+            outbox: { ...config.outbox, path: outboxPath(config.outbox?.path) },
+            sendRateLimit: config.sendRateLimit,
         });
 
         this.bot.registerCallback(async (_, ev) => {
@@ -115,6 +131,11 @@ export class MiraiOnebotAdaptor {
                     messageChain: convertInbound(ev.message)
                 }
                 this.emit("message", msg2);
+            } else if (ev.type === "OutboundDelivered") {
+                this.settleQueued(ev.localId, { messageId: ev.messageId });
+            } else if (ev.type === "OutboundFailed") {
+                logger.error(`Queued message ${ev.localId} was not delivered: ${ev.code} ${ev.error}`);
+                this.settleQueued(ev.localId, { error: new Error(`${ev.code}: ${ev.error}`) });
             } else if (ev.type === "GroupMessageDeleted") {
                 logger.debug(ev);
                 this.emit("groupRecall", {
@@ -131,6 +152,33 @@ export class MiraiOnebotAdaptor {
 
     }
     connected = false;
+    // Sends that went to the outbox; resolve them once it reports delivery
+    queued = new Map<string, (result: QueuedResult) => void>();
+    settled = new Map<string, QueuedResult>();
+    settleQueued(localId: string, result: QueuedResult) {
+        const resolve = this.queued.get(localId);
+        if (resolve) {
+            this.queued.delete(localId);
+            resolve(result);
+        } else {
+            this.settled.set(localId, result);
+        }
+    }
+    async delivered(resp: SendGroupMsgResp): Promise<string> {
+        if (resp.messageId !== undefined) {
+            return resp.messageId;
+        }
+        const localId = resp.localId!;
+        logger.warn(`Message queued in outbox as ${localId}`);
+        const result = this.settled.get(localId) ?? await new Promise<QueuedResult>((resolve) => {
+            this.queued.set(localId, resolve);
+        });
+        this.settled.delete(localId);
+        if ("error" in result) {
+            throw result.error;
+        }
+        return result.messageId;
+    }
     onSignal(signal: "authed", f: () => void): void;
     onSignal(signal: "verified", f: () => void): void;
     onSignal(signal: string, f: Function): void {
@@ -147,20 +195,9 @@ export class MiraiOnebotAdaptor {
     onEvent(event: string, f: Function): void {
         this.ev.on(event, f as any);
     }
-    readyFlag = false;
     async listen(g: "group") {
-        this.bot.registerCallback((err, ev) => {
-            if (ev.type === "Connected") {
-                this.readyFlag = true;
-            }
-        });
         await this.bot.start();
-        while (1) {
-            await new Promise((resolve) => {
-                setTimeout(resolve, 1000);
-            });
-            if (this.readyFlag) break;
-        }
+        await this.bot.waitConnected();
         logger.info("Onebot ready.")
     }
     onMessage(
@@ -230,7 +267,7 @@ export class MiraiOnebotAdaptor {
         logger.info(chain, "sendQuotedGroupMessage");
 
         const message = await this.bot.sendGroupMessage(`${group}`, chain);
-        return { messageId: Number(message.messageId) };
+        return { messageId: Number(await this.delivered(message)) };
     }
     async sendGroupMessage(
         msg: MockMessageChain[] | string,
@@ -244,7 +281,7 @@ export class MiraiOnebotAdaptor {
         const message = await this.bot.sendGroupMessage(`${group}`, chain);
         logger.debug(message);
         //console.log("Sent")
-        return { messageId: Number(message.messageId) };
+        return { messageId: Number(await this.delivered(message)) };
     }
     async uploadImage(image: Buffer, target: MockGroupTarget): Promise<image> {
         return image;
@@ -1,44 +1,377 @@
 /* auto-generated by NAPI-RS */
 /* eslint-disable */
+/**
+ * `for await (const ev of endpoint.events())` 用的事件流。`terminate` 引起的 `Closed` 产出后迭代结束；
+ * 断线、自动重连时的 `Closed` 不会结束迭代。连接池合并后的流一直开着。
+ */
+export declare class EventStream {
+  [Symbol.asyncIterator](): AsyncGenerator<SequencedEvent, void, void>
+}
+
+/**
+ * 按汉明距离查找相似图片的索引，只保留最近的 `capacity` 条，超出后丢掉最早的。
+ *
+ * 用来让看起来一样的表情包复用已有的上传，或者发现短时间内反复刷同一张图。
+ */
+export declare class PerceptualIndex {
+  constructor(capacity?: number | undefined | null)
+  /** 记下一张图。`key` 可以重复，每次出现都算一条，`count_recent` 靠这个统计刷图。 */
+  insert(hash: string, key: string): void
+  /** 距离不超过 `max_distance` 的最相似的一条，距离相同时取最近插入的。 */
+  nearest(hash: string, maxDistance: number): PerceptualMatch | null
+  /** 最近 `window_ms` 毫秒内插入过的相似图片的条数。 */
+  countRecent(hash: string, maxDistance: number, windowMs: number): number
+  get size(): number
+}
+
 export declare class QqBotEndpoint {
   constructor(config: QqBotConfig)
-  start(): Promise<void>
+  start(options?: CallOptions | undefined | null): Promise<void>
+  state(): EndpointState
+  health(): Health
+  /** 当前连接的 OneBot 实现和版本，连上之前是 `Unknown`。 */
+  dialect(): DialectInfo
+  /** 下载过的 QQ 图片的 SHA-256，没有启用缓存或没下载过时为空。 */
+  cachedImageHash(imageId: string): string | null
+  /** 这份媒体之前上传到 Matrix 得到的 `mxc://` URI。 */
+  lookupMxc(hash: string): string | null
+  rememberMxc(hash: string, mxc: string): void
+  /** 停止当前运行，等主循环退出、`Closed` 发出后才返回。之后可以再次 `start`。 */
   terminate(): Promise<void>
-  registerCallback(callback: ((err: Error | null, arg: Event) => any)): Promise<void>
-  getFriendList(): Promise<Array<[string, string]>>
-  getGroupMember(groupId: string, userId: string): Promise<GroupMemberInfo>
-  deleteMessage(messageId: string): Promise<void>
-  downloadImage(imageId: string): Promise<Buffer>
-  sendGroupMessage(groupId: string, message: Array<Mockv2MessageChain>): Promise<SendGroupMsgResp>
+  registerCallback(callback: ((err: Error | null, arg: SequencedEvent) => any), options?: SubscribeOptions | undefined | null): Promise<Subscription>
+  /** 事件总线的队列深度等指标，用来观察 JS 侧是否处理不过来。 */
+  eventBusMetrics(): EventBusMetrics
+  /** 以 `AsyncIterator` 的形式读取事件，`terminate` 之后产出 `Closed` 然后结束迭代。 */
+  events(filter?: EventFilter | undefined | null, replay?: ReplayOptions | undefined | null): EventStream
+  /**
+   * 连接成功并拿到登录信息后 resolve；还没连上就被终止时以 `NOT_CONNECTED` reject。
+   * 默认一直等下去，不套用查询类的超时。
+   */
+  waitConnected(options?: CallOptions | undefined | null): Promise<void>
+  getFriendList(options?: CallOptions | undefined | null): Promise<Array<[string, string]>>
+  getGroupMember(groupId: string, userId: string, options?: CallOptions | undefined | null): Promise<GroupMemberInfo>
+  deleteMessage(messageId: string, options?: CallOptions | undefined | null): Promise<void>
+  downloadImage(imageId: string, options?: CallOptions | undefined | null): Promise<DownloadedMedia>
+  /** 边下载边写进 `path`，返回的 `buffer` 为空。 */
+  downloadImageToFile(imageId: string, path: string, options?: CallOptions | undefined | null): Promise<DownloadedMedia>
+  sendGroupMessage(groupId: string, message: Array<Mockv2MessageChain>, priority?: SendPriority | undefined | null, options?: CallOptions | undefined | null): Promise<SendGroupMsgResp>
+  /**
+   * 写入出站日志后立即返回本地 id，投递结果通过 `OutboundDelivered` / `OutboundFailed` 事件通知。
+   * 断线期间的消息会在重连后按顺序重放。
+   */
+  enqueueGroupMessage(groupId: string, message: Array<Mockv2MessageChain>, priority?: SendPriority | undefined | null): Promise<string>
 }
 export type QQBotEndpoint = QqBotEndpoint
 
+/**
+ * 多个 QQ 账号的连接池，按 self id 管理。所有账号的事件合并到连接池自己的事件总线上，
+ * 序号也由连接池重新分配。
+ */
+export declare class QqBotPool {
+  /** 参数和 `QQBotConfig` 的 `eventBus`、`eventReplay` 含义相同，作用于连接池合并后的总线。 */
+  constructor(eventBus?: EventBusConfig | undefined | null, eventReplay?: number | undefined | null)
+  /** 连接一个账号，连上后返回它的 self id。同一个账号不能重复加入。 */
+  add(config: QqBotConfig, options?: CallOptions | undefined | null): Promise<string>
+  /** 断开并移出一个账号。 */
+  remove(selfId: string): Promise<void>
+  selfIds(): Array<string>
+  /** 取出某个账号的连接，用来调用连接池没有代理的方法。 */
+  get(selfId: string): QqBotEndpoint | null
+  /** 断开所有账号。 */
+  terminate(): Promise<void>
+  registerCallback(callback: ((err: Error | null, arg: SequencedEvent) => any), options?: SubscribeOptions | undefined | null): Subscription
+  /** 所有账号合并后的事件流。某个账号的 `Closed` 不会结束迭代，`for await` 需要自己 `break`。 */
+  events(filter?: EventFilter | undefined | null, replay?: ReplayOptions | undefined | null): EventStream
+  /** 重新拉取所有账号的群列表。 */
+  refreshGroups(): Promise<void>
+  /** 用所在群里的账号发送。不知道哪个账号在这个群时先刷新一次群列表。 */
+  sendGroupMessage(groupId: string, message: Array<Mockv2MessageChain>, priority?: SendPriority | undefined | null, options?: CallOptions | undefined | null): Promise<SendGroupMsgResp>
+}
+export type QQBotPool = QqBotPool
+
+/** `register_callback` 返回的订阅句柄。 */
+export declare class Subscription {
+  /** 停止投递。已经交给 JS 的事件仍会执行，之后不会再收到任何事件。 */
+  unsubscribe(): void
+  get active(): boolean
+  /** 因缓冲区溢出被丢弃的事件数。 */
+  get dropped(): number
+}
+
+/** 默认调用超时。发消息类（send_*、delete_msg、set_* 等）用 `send_ms`，其余用 `query_ms`。 */
+export interface ApiTimeoutConfig {
+  sendMs?: number
+  queryMs?: number
+}
+
+/**
+ * 用 CIEDE2000 挑出和头像主色最接近的 emoji，默认是九种圆形色块。
+ *
+ * 结果按 `key`（一般是头像的 mxc URI）缓存，没给时用内容的哈希。
+ */
+export declare function avatarEmoji(img: Uint8Array, palette?: Array<EmojiColor> | undefined | null, key?: string | undefined | null): string
+
+/** 查 `avatar_emoji` 算过的结果，命中时不用再下载头像。 */
+export declare function cachedAvatarEmoji(key: string, palette?: Array<EmojiColor> | undefined | null): string | null
+
+/**
+ * 头像的主色，各分量量化到 0-15。
+ *
+ * 从 `calc_palette` 的结果里挑占比和彩度都高的颜色，透明像素和纯色背景不算在内。
+ */
 export declare function calcDominantColor(img: Uint8Array): Array<number>
 
+/**
+ * 在 Lab 空间里用 k-means 提取最多 `k` 种主要颜色，按占比从大到小排列。
+ *
+ * 透明的像素不参与统计。
+ */
+export declare function calcPalette(img: Uint8Array, k: number, options?: PaletteOptions | undefined | null): Array<PaletteColor>
+
+/** 单次调用的选项，用法和 `fetch` 的 `signal` 类似。 */
+export interface CallOptions {
+  /** 覆盖默认超时。 */
+  timeoutMs?: number
+  signal?: AbortSignal
+}
+
+export interface ConvertedImage {
+  buffer: Buffer
+  mime: string
+  width: number
+  height: number
+}
+
+/** 转换静态图片，支持 JPEG/PNG/WebP/GIF/BMP。动图只保留第一帧。 */
+export declare function convertImage(buffer: Buffer, targetMime: string, options?: ConvertOptions | undefined | null): Promise<ConvertedImage>
+
+export interface ConvertOptions {
+  /** 长边超过这个值时等比缩小。 */
+  maxDimension?: number
+  /** JPEG 的质量，1-100，默认 85。WebP 只支持无损编码，不受影响。 */
+  quality?: number
+}
+
+/** 把动图（GIF、APNG、动态 WebP）转成 GIF，保留每帧的时长。静态图片转成单帧的 GIF。 */
+export declare function convertToGif(buffer: Buffer, options?: GifOptions | undefined | null): Promise<ConvertedImage>
+
+export declare enum DeliveryMode {
+  /** 收到就转发给 JS，回调处理慢会拖慢整个事件总线。 */
+  Blocking = 'Blocking',
+  /** 先放进订阅自己的缓冲区，JS 回调返回后再投递下一条；缓冲区满时按 `overflow` 丢弃。 */
+  Queued = 'Queued'
+}
+
+export interface DialectInfo {
+  implementation: Implementation
+  appName: string
+  appVersion: string
+  protocolVersion: string
+}
+
+/** `mime` 按文件头判断，认不出时为 `application/octet-stream`。 */
+export interface DownloadedMedia {
+  buffer: Buffer
+  /** 写进文件时为文件路径。 */
+  path?: string
+  mime: string
+  width?: number
+  height?: number
+  size: number
+}
+
 export interface DownloadImageEndpoint {
   baseurl: string
   authorizationHeader: string
+  /** 取图片的途径和顺序，默认 `["Sidecar", "SegmentUrl"]`。 */
+  strategies?: Array<MediaStrategy>
+  /** `LocalPath` 允许读取的目录。 */
+  localRoot?: string
+  /** 单个文件的大小上限，默认 20 MiB。 */
+  maxSizeMb?: number
+}
+
+export interface EmojiColor {
+  /** 选中时返回的文字，不一定是 emoji。 */
+  label: string
+  rgb: Array<number>
+}
+
+export declare function emojiPalette(shape: EmojiShape): Array<EmojiColor>
+
+/** 内置的色块 emoji，颜色取自 Twemoji。 */
+export declare enum EmojiShape {
+  Circle = 'Circle',
+  Square = 'Square'
 }
 
 export type Event =
   | { type: 'Connected', name: string, qq: string }
   | { type: 'GroupMessage', selfId: string, groupId: string, sender: GroupMemberInfo, message: Array<Mockv2MessageChain> }
   | { type: 'GroupMessageDeleted', groupId: string, selfId: string, messageId: string }
-  | { type: 'Closed' }
+  | { type: 'GroupMemberJoined', selfId: string, groupId: string, userId: string }
+  | { type: 'GroupMemberLeft', selfId: string, groupId: string, userId: string }
+  | { type: 'GroupMemberUpdated', selfId: string, groupId: string, userId: string, card?: string }
+  | { type: 'OutboundDelivered', localId: string, messageId: string }
+  | { type: 'OutboundFailed', localId: string, /** 与 `BridgeError` 的 `code` 一致，过期时为 `EXPIRED`。 */
+  code: string, error: string }
+  | { type: 'BotOnline', selfId: string }
+  | { type: 'BotOffline', selfId: string, reason: string }
+  | { type: 'Lagged', /**
+   * 订阅读得太慢，总线溢出丢掉了 `dropped` 条事件。只发给落后的那个订阅，不占序号。
+   *
+   * 读 OneBot 连接跟不上、连接那边丢了事件时也会发一条，这时发给所有订阅，占序号。
+   */
+  dropped: number }
+  | { type: 'Closed', /** 由 JS 调用 `terminate` 引起；断线、自动重连时为 `false`。 */
+  terminated: boolean }
+
+/** 连接的生命周期：Idle → Connecting → Running → Stopping → Stopped。`Stopped` 之后可以再次 `start`。 */
+export declare enum EndpointState {
+  Idle = 'Idle',
+  Connecting = 'Connecting',
+  Running = 'Running',
+  Stopping = 'Stopping',
+  Stopped = 'Stopped'
+}
+
+export interface EventBusConfig {
+  /** 总线容量，默认 1024。 */
+  capacity?: number
+  overflow?: EventOverflow
+  /** `SpillToDisk` 用的 SQLite 文件，为空时放在内存里。 */
+  spillPath?: string
+}
+
+export interface EventBusMetrics {
+  /** 总线里还没被所有订阅读走的事件数。 */
+  depth: number
+  capacity: number
+  /** 溢出到磁盘、还没放回总线的事件数。 */
+  spilled: number
+  /** `DropOldest` 模式下因溢出丢掉的事件总数。 */
+  dropped: number
+  published: number
+  subscribers: number
+}
+
+/** 事件过滤条件。各字段之间是“且”的关系；不带群号或 self id 的事件（如 `Connected`）不受对应条件限制。 */
+export interface EventFilter {
+  /** 事件类型，即 `Event` 的 `type` 字段，如 `GroupMessage`。 */
+  types?: Array<string>
+  groupIds?: Array<string>
+  selfId?: string
+}
+
+/** 总线写满时的处理方式。 */
+export declare enum EventOverflow {
+  /** 等最慢的订阅读走一条再发，OneBot 事件的读取也会跟着停下。 */
+  Block = 'Block',
+  /** 丢掉最旧的事件，落后的订阅会先收到一条 `Lagged`。 */
+  DropOldest = 'DropOldest',
+  /** 写满后先存到磁盘，有空位时再按顺序放回总线。 */
+  SpillToDisk = 'SpillToDisk'
+}
 
 export interface ForwardItem {
   senderName: string
   messageChain: Array<Mockv2MessageChain>
 }
 
+export interface GifOptions {
+  /** 长边超过这个值时等比缩小。 */
+  maxDimension?: number
+  /** 输出的大小上限，超过时先丢帧再缩小，直到放得下。 */
+  maxSizeKb?: number
+  /** 调色板量化的采样间隔，1-30，越小越好也越慢，默认 10。 */
+  speed?: number
+}
+
 export interface GroupMemberInfo {
   userId: string
   nick?: string
   name?: string
 }
 
+/** 连接健康状况。时间都是毫秒时间戳，还没有数据时为空。 */
+export interface Health {
+  state: EndpointState
+  lastHeartbeatAt?: number
+  /** OneBot 实现上报的心跳间隔。 */
+  heartbeatIntervalMs?: number
+  lastEventAt?: number
+  /** 最近一次 `get_status` 探测的往返时间，探测失败时为空。 */
+  rttMs?: number
+  /** 心跳里的 `status.online`。 */
+  online?: boolean
+  /** 心跳里的 `status.good`。 */
+  good?: boolean
+  /** 心跳已经超时。 */
+  stale: boolean
+}
+
+/** 发到 Matrix 时填 `info` 用的信息。 */
+export interface ImageInfo {
+  mime: string
+  width: number
+  height: number
+  size: number
+  animated: boolean
+  frameCount: number
+  /** 第一帧的缩略图，不透明时是 JPEG，否则是 PNG。 */
+  thumbnail: ConvertedImage
+  blurhash: string
+}
+
+/** 读取尺寸、类型和帧数，并生成缩略图和 blurhash。 */
+export declare function imageInfo(buffer: Buffer, options?: ImageInfoOptions | undefined | null): Promise<ImageInfo>
+
+export interface ImageInfoOptions {
+  /** 缩略图长边的上限，默认 320。 */
+  thumbnailDimension?: number
+}
+
+/** 连上的 OneBot 实现，按 `get_version_info` 的 `app_name` 识别。 */
+export declare enum Implementation {
+  NapCat = 'NapCat',
+  LLOneBot = 'LLOneBot',
+  Lagrange = 'Lagrange',
+  GoCqhttp = 'GoCqhttp',
+  /** 经 mirai-api-http 转换。 */
+  Mirai = 'Mirai',
+  /** 经 Satori 转换。 */
+  Satori = 'Satori',
+  /** 没认出来，或者不支持 `get_version_info`，按 NapCat 的行为处理。 */
+  Unknown = 'Unknown'
+}
+
 export declare function initialize(): boolean
 
+/** 是否有不止一帧。只解码到第二帧为止，静态的 PNG、WebP 只读文件头。 */
+export declare function isAnimated(buffer: Buffer): Promise<boolean>
+
+/** 按 SHA-256 存放下载和发出的媒体，重复的表情包不用再下载、上传。 */
+export interface MediaCacheConfig {
+  dir: string
+  /** 缓存文件总大小上限，默认 512 MiB。 */
+  maxSizeMb?: number
+}
+
+/** 媒体缓存使用的 SHA-256，十六进制小写。 */
+export declare function mediaHash(buffer: Uint8Array): string
+
+/** 取图片的一种途径。`downloadImage.strategies` 按顺序尝试，前面的失败了再试后面的。 */
+export declare enum MediaStrategy {
+  /** 直接下载收到消息时图片段里的 `url`。 */
+  SegmentUrl = 'SegmentUrl',
+  /** 读 `get_image` 返回的本地路径，实现和桥接共用一个卷时使用，需要设置 `localRoot`。 */
+  LocalPath = 'LocalPath',
+  /** 用 `get_file` 要 base64。 */
+  GetFile = 'GetFile',
+  /** `get_image` 之后从 `baseurl` 上的 HTTP 服务下载。 */
+  Sidecar = 'Sidecar'
+}
+
 export interface MemberInfo {
   nick: string
   name: string
@@ -55,16 +388,154 @@ export type Mockv2MessageChain =
   | { type: 'Unknown', placeholder: string }
   | { type: 'Error', message: string }
 
+/** 出站消息日志。 */
+export interface OutboxConfig {
+  /** SQLite 文件，默认是当前目录下的 `outbox.sqlite3`；`:memory:` 表示只保存在内存里。 */
+  path?: string
+  maxAttempts?: number
+  ttlMs?: number
+  retryBaseMs?: number
+  retryMaxMs?: number
+}
+
+export declare enum OverflowPolicy {
+  DropOldest = 'DropOldest',
+  DropNewest = 'DropNewest'
+}
+
+export interface PaletteColor {
+  rgb: Array<number>
+  /** `[L, a, b]` */
+  lab: Array<number>
+  /** 占参与统计的像素的比例，所有颜色加起来是 1。 */
+  weight: number
+}
+
+export interface PaletteOptions {
+  /** 去掉从边框连通过来的纯色背景，默认开启。整张图都是背景时不去掉。 */
+  ignoreBackground?: boolean
+}
+
+/** 最多 64 位的哈希（pHash 63 位），写成 16 位十六进制，JS 的数字装不下。 */
+export interface PerceptualHash {
+  /** 相邻像素的明暗关系，对缩放和重新压缩不敏感。 */
+  dhash: string
+  /** 低频 DCT 系数和中位数的关系，对调色、加水印更稳健。 */
+  phash: string
+}
+
+/** 计算图片的 dHash 和 pHash，动图只看第一帧。 */
+export declare function perceptualHash(buffer: Buffer): Promise<PerceptualHash>
+
+export interface PerceptualMatch {
+  key: string
+  /** 不同的位数，0 表示哈希完全相同。 */
+  distance: number
+}
+
 export declare function plus100(input: number): number
 
 export interface QqBotConfig {
+  /** `ws://`/`wss://` 开头的完整 URL，可以带路径和 query；也兼容旧的 `host:port`。 */
   addr: string
   accessToken: string
+  /** 握手时附加的请求头。 */
+  headers?: Record<string, string>
+  tls?: TlsConfig
+  /** 默认 `OneBot`。 */
+  transport?: Transport
+  /** mirai-api-http 绑定的 QQ 号。 */
+  qq?: string
   downloadImage: DownloadImageEndpoint
+  sendRateLimit?: SendRateLimitConfig
+  outbox?: OutboxConfig
+  apiTimeout?: ApiTimeoutConfig
+  /** 回放缓冲区保留的事件数，默认 256。 */
+  eventReplay?: number
+  eventBus?: EventBusConfig
+  watchdog?: WatchdogConfig
+  mediaCache?: MediaCacheConfig
+}
+
+/** 令牌桶：每 `interval_ms` 毫秒补充一条，最多连发 `burst` 条。 */
+export interface RateLimitConfig {
+  burst: number
+  intervalMs: number
+}
+
+/** 新订阅从哪里开始收事件。都不填时只收订阅之后的事件；`since_seq` 优先于 `last`。 */
+export interface ReplayOptions {
+  /** 先补发最近的 N 条。 */
+  last?: number
+  /** 补发序号大于它的所有事件。早于回放缓冲区的部分已经丢失，不会报错。 */
+  sinceSeq?: number
 }
 
 export interface SendGroupMsgResp {
-  messageId: string
+  /** 断线、或者这个群还有没送达的消息时，新消息进了出站日志，这里为空。 */
+  messageId?: string
+  /** 进了出站日志时的本地 id，之后的投递结果通过 `OutboundDelivered` / `OutboundFailed` 事件通知。 */
+  localId?: string
+}
+
+/**
+ * 带序号的事件，交给 JS 时序号作为 `seq` 属性挂在事件对象上。
+ *
+ * 序号从 1 开始；订阅落后时收到的 `Lagged` 不占序号，`seq` 为 0。
+ */
+export type SequencedEvent = Event & { seq: number }
+
+/** 出站消息优先级。管理员消息会插队到其他群的普通消息之前，但不会打乱同一个群内的顺序。 */
+export declare enum SendPriority {
+  Normal = 'Normal',
+  Admin = 'Admin'
+}
+
+export interface SendRateLimitConfig {
+  perGroup?: RateLimitConfig
+  global?: RateLimitConfig
+  maxInFlight?: number
+}
+
+export interface SubscribeOptions {
+  filter?: EventFilter
+  mode?: DeliveryMode
+  /** `Queued` 模式下的缓冲区大小，默认 256。 */
+  capacity?: number
+  overflow?: OverflowPolicy
+  replay?: ReplayOptions
 }
 
 export declare function testUint8Array(elem: Mockv2MessageChain): void
+
+/** `wss://` 连接用的证书，都是 PEM 文件路径。 */
+export interface TlsConfig {
+  /** 额外信任的 CA，和内置的根证书一起使用。 */
+  caFile?: string
+  /** 客户端证书，要和 `client_key` 一起设置。 */
+  clientCert?: string
+  clientKey?: string
+}
+
+export declare enum Transport {
+  /** OneBot v11 正向 WebSocket。 */
+  OneBot = 'OneBot',
+  /** mirai-api-http 2.x 的 WebSocket adapter。`accessToken` 填 verifyKey，还要填 `qq`。 */
+  Mirai = 'Mirai',
+  /**
+   * Satori 协议，地址填服务根路径（如 `ws://127.0.0.1:5140/satori`），`accessToken` 填 token，
+   * `qq` 选择用哪个登录，不填用第一个。
+   */
+  Satori = 'Satori'
+}
+
+export interface WatchdogConfig {
+  /** 连续这么多个心跳周期没收到心跳就认为连接已经失效，默认 3。 */
+  missedHeartbeats?: number
+  /** `get_status` 探测的间隔，默认 30 秒。 */
+  probeIntervalMs?: number
+  /** 实现没上报心跳间隔、或者不发心跳时按这个间隔判断，默认 30 秒。 */
+  heartbeatIntervalMs?: number
+  /** 连接失效后是否自动重连，默认开启。 */
+  autoReconnect?: boolean
+}
@@ -508,9 +508,33 @@ if (!nativeBinding) {
 }
 
 module.exports = nativeBinding
+module.exports.EventStream = nativeBinding.EventStream
+module.exports.PerceptualIndex = nativeBinding.PerceptualIndex
 module.exports.QqBotEndpoint = nativeBinding.QqBotEndpoint
 module.exports.QQBotEndpoint = nativeBinding.QQBotEndpoint
+module.exports.QqBotPool = nativeBinding.QqBotPool
+module.exports.QQBotPool = nativeBinding.QQBotPool
+module.exports.Subscription = nativeBinding.Subscription
+module.exports.avatarEmoji = nativeBinding.avatarEmoji
+module.exports.cachedAvatarEmoji = nativeBinding.cachedAvatarEmoji
 module.exports.calcDominantColor = nativeBinding.calcDominantColor
+module.exports.calcPalette = nativeBinding.calcPalette
+module.exports.convertImage = nativeBinding.convertImage
+module.exports.convertToGif = nativeBinding.convertToGif
+module.exports.DeliveryMode = nativeBinding.DeliveryMode
+module.exports.emojiPalette = nativeBinding.emojiPalette
+module.exports.EmojiShape = nativeBinding.EmojiShape
+module.exports.EndpointState = nativeBinding.EndpointState
+module.exports.EventOverflow = nativeBinding.EventOverflow
+module.exports.imageInfo = nativeBinding.imageInfo
+module.exports.Implementation = nativeBinding.Implementation
 module.exports.initialize = nativeBinding.initialize
+module.exports.isAnimated = nativeBinding.isAnimated
+module.exports.mediaHash = nativeBinding.mediaHash
+module.exports.MediaStrategy = nativeBinding.MediaStrategy
+module.exports.OverflowPolicy = nativeBinding.OverflowPolicy
+module.exports.perceptualHash = nativeBinding.perceptualHash
 module.exports.plus100 = nativeBinding.plus100
+module.exports.SendPriority = nativeBinding.SendPriority
 module.exports.testUint8Array = nativeBinding.testUint8Array
+module.exports.Transport = nativeBinding.Transport
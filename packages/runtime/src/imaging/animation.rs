@@ -0,0 +1,299 @@
+use std::io::Cursor;
+
+use anyhow::{Context, ensure};
+use image::{
+  AnimationDecoder, Frames, ImageFormat, RgbaImage,
+  codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+  imageops::{self, FilterType},
+};
+use napi::{bindgen_prelude::Buffer, tokio};
+use napi_derive::napi;
+
+use super::convert::{self, ConvertedImage};
+use crate::qqbot::error::{BridgeError, Coded};
+
+const DEFAULT_SPEED: i32 = 10;
+/// 丢帧时至少留下这么多帧，再少就只缩小尺寸。
+const MIN_FRAMES: usize = 8;
+/// 最多每隔几帧留一帧。
+const MAX_FRAME_STEP: usize = 4;
+const MIN_DIMENSION: u32 = 16;
+/// 解码后所有帧加起来的大小上限，每帧都是完整的 RGBA 画布。
+const MAX_DECODED_BYTES: u64 = 256 * 1024 * 1024;
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct GifOptions {
+  /// 长边超过这个值时等比缩小。
+  pub max_dimension: Option<u32>,
+  /// 输出的大小上限，超过时先丢帧再缩小，直到放得下。
+  pub max_size_kb: Option<u32>,
+  /// 调色板量化的采样间隔，1-30，越小越好也越慢，默认 10。
+  pub speed: Option<u32>,
+}
+
+/// 解码后的一帧，画布大小，已经和前面的帧合成好了。
+pub(crate) struct Frame {
+  pub image: RgbaImage,
+  pub delay_ms: u32,
+}
+
+fn invalid(err: image::ImageError) -> BridgeError {
+  BridgeError::InvalidArgument(format!("{err}"))
+}
+
+/// 动图的帧，不是动图时返回 `None`。
+fn frames(data: &[u8]) -> anyhow::Result<Option<Frames<'_>>> {
+  let cursor = Cursor::new(data);
+  Ok(match image::guess_format(data).map_err(invalid)? {
+    ImageFormat::Gif => Some(GifDecoder::new(cursor).map_err(invalid)?.into_frames()),
+    ImageFormat::WebP => {
+      let decoder = WebPDecoder::new(cursor).map_err(invalid)?;
+      decoder.has_animation().then(|| decoder.into_frames())
+    }
+    ImageFormat::Png => {
+      let decoder = PngDecoder::new(cursor).map_err(invalid)?;
+      match decoder.is_apng().map_err(invalid)? {
+        true => Some(decoder.apng().map_err(invalid)?.into_frames()),
+        false => None,
+      }
+    }
+    _ => None,
+  })
+}
+
+fn into_frame(frame: image::Frame) -> Frame {
+  let (numer, denom) = frame.delay().numer_denom_ms();
+  Frame {
+    delay_ms: numer / denom.max(1),
+    image: frame.into_buffer(),
+  }
+}
+
+/// 解码动图的所有帧，支持 GIF、APNG 和动态 WebP。静态图片当作只有一帧。
+pub(crate) fn decode_frames(data: &[u8]) -> anyhow::Result<Vec<Frame>> {
+  let Some(frames) = frames(data)? else {
+    let (image, _) = convert::decode(data)?;
+    return Ok(vec![Frame {
+      image: image.into_rgba8(),
+      delay_ms: 0,
+    }]);
+  };
+  let mut decoded = vec![];
+  let mut total = 0u64;
+  for frame in frames {
+    let frame = into_frame(frame.map_err(invalid)?);
+    total += frame.image.as_raw().len() as u64;
+    if total > MAX_DECODED_BYTES {
+      return Err(
+        BridgeError::TooLarge {
+          limit: MAX_DECODED_BYTES,
+        }
+        .into(),
+      );
+    }
+    decoded.push(frame);
+  }
+  ensure!(
+    !decoded.is_empty(),
+    BridgeError::InvalidArgument("animation has no frames".to_owned())
+  );
+  Ok(decoded)
+}
+
+/// 只留第一帧，其余的帧只数个数。GIF 只有一帧时也算静态图片。
+pub(crate) fn first_frame(data: &[u8]) -> anyhow::Result<(RgbaImage, u32)> {
+  let Some(mut frames) = frames(data)? else {
+    let (image, _) = convert::decode(data)?;
+    return Ok((image.into_rgba8(), 1));
+  };
+  let first = frames
+    .next()
+    .ok_or_else(|| BridgeError::InvalidArgument("animation has no frames".to_owned()))?
+    .map_err(invalid)?;
+  let mut count = 1;
+  for frame in frames {
+    frame.map_err(invalid)?;
+    count += 1;
+  }
+  Ok((into_frame(first).image, count))
+}
+
+fn animated(data: &[u8]) -> anyhow::Result<bool> {
+  let Some(frames) = frames(data)? else {
+    return Ok(false);
+  };
+  let mut count = 0;
+  for frame in frames.take(2) {
+    frame.map_err(invalid)?;
+    count += 1;
+  }
+  Ok(count > 1)
+}
+
+/// 是否有不止一帧。只解码到第二帧为止，静态的 PNG、WebP 只读文件头。
+#[napi(ts_return_type = "Promise<boolean>")]
+pub async fn is_animated(buffer: Buffer) -> Coded<bool> {
+  Coded::wrap(async move {
+    let data = buffer.to_vec();
+    tokio::task::spawn_blocking(move || animated(&data)).await?
+  })
+  .await
+}
+
+/// 每 `step` 帧留一帧，被丢掉的帧的时长加到留下的帧上。
+fn sample(frames: &[Frame], step: usize) -> impl Iterator<Item = (&RgbaImage, u32)> {
+  frames
+    .chunks(step)
+    .map(|chunk| (&chunk[0].image, chunk.iter().map(|x| x.delay_ms).sum()))
+}
+
+fn encode(
+  frames: &[Frame],
+  step: usize,
+  (width, height): (u32, u32),
+  speed: i32,
+) -> anyhow::Result<Vec<u8>> {
+  let mut buffer = vec![];
+  let mut encoder = gif::Encoder::new(&mut buffer, width as u16, height as u16, &[])?;
+  encoder.set_repeat(gif::Repeat::Infinite)?;
+  // GIF 的时长以 10ms 为单位，按累计时间取整，避免误差越积越多。
+  let mut elapsed_ms = 0u64;
+  for (image, delay_ms) in sample(frames, step) {
+    let mut pixels = if image.dimensions() == (width, height) {
+      image.clone().into_raw()
+    } else {
+      imageops::resize(image, width, height, FilterType::Triangle).into_raw()
+    };
+    // 每帧单独量化调色板。
+    let mut frame = gif::Frame::from_rgba_speed(width as u16, height as u16, &mut pixels, speed);
+    let start = (elapsed_ms + 5) / 10;
+    elapsed_ms += delay_ms as u64;
+    frame.delay = ((elapsed_ms + 5) / 10 - start)
+      .try_into()
+      .unwrap_or(u16::MAX);
+    // 每一帧都是完整的画布，透明的地方不能露出上一帧。
+    frame.dispose = gif::DisposalMethod::Background;
+    encoder.write_frame(&frame)?;
+  }
+  drop(encoder);
+  Ok(buffer)
+}
+
+fn scaled((width, height): (u32, u32), scale: f64) -> (u32, u32) {
+  let scale = |x: u32| ((x as f64 * scale).round() as u32).max(1);
+  (scale(width), scale(height))
+}
+
+/// 编码成 GIF。给了大小上限时，先隔帧丢弃，丢到 `MIN_FRAMES` 帧后再逐步缩小。
+pub(crate) fn encode_gif(frames: &[Frame], options: &GifOptions) -> anyhow::Result<ConvertedImage> {
+  let (width, height) = frames[0].image.dimensions();
+  let max_dimension = options
+    .max_dimension
+    .unwrap_or(u16::MAX as u32)
+    .min(u16::MAX as u32);
+  let mut scale = (max_dimension as f64 / width.max(height) as f64).min(1.0);
+  let speed = options
+    .speed
+    .map_or(DEFAULT_SPEED, |x| x.clamp(1, 30) as i32);
+  let limit = options.max_size_kb.map(|x| x as u64 * 1024);
+  let mut step = 1;
+  loop {
+    let dimensions = scaled((width, height), scale);
+    let buffer = encode(frames, step, dimensions, speed).context("Failed to encode GIF")?;
+    let Some(limit) = limit.filter(|&limit| buffer.len() as u64 > limit) else {
+      return Ok(ConvertedImage {
+        buffer: buffer.into(),
+        mime: "image/gif".to_owned(),
+        width: dimensions.0,
+        height: dimensions.1,
+      });
+    };
+    if step < MAX_FRAME_STEP && frames.len().div_ceil(step * 2) >= MIN_FRAMES {
+      step *= 2;
+      continue;
+    }
+    // 大小大致和面积成正比。
+    let ratio = limit as f64 / buffer.len() as f64;
+    scale *= (ratio.sqrt() * 0.95).clamp(0.5, 0.9);
+    let (next_width, next_height) = scaled((width, height), scale);
+    if next_width.min(next_height) < MIN_DIMENSION {
+      return Err(BridgeError::TooLarge { limit }.into());
+    }
+  }
+}
+
+/// 把动图（GIF、APNG、动态 WebP）转成 GIF，保留每帧的时长。静态图片转成单帧的 GIF。
+#[napi(ts_return_type = "Promise<ConvertedImage>")]
+pub async fn convert_to_gif(buffer: Buffer, options: Option<GifOptions>) -> Coded<ConvertedImage> {
+  Coded::wrap(async move {
+    let data = buffer.to_vec();
+    tokio::task::spawn_blocking(move || {
+      let frames = decode_frames(&data)?;
+      encode_gif(&frames, &options.unwrap_or_default())
+    })
+    .await?
+  })
+  .await
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{Rgba, RgbaImage};
+
+  use super::*;
+
+  /// `count` 帧的 GIF，每帧只有左上角 1×1，画布是 `width`×`height`。
+  fn gif(width: u16, height: u16, count: usize) -> Vec<u8> {
+    let mut buffer = vec![];
+    let mut encoder = gif::Encoder::new(&mut buffer, width, height, &[]).unwrap();
+    for i in 0..count {
+      let mut pixels = [i as u8, 0, 0, 255];
+      let mut frame = gif::Frame::from_rgba(1, 1, &mut pixels);
+      frame.delay = 7;
+      encoder.write_frame(&frame).unwrap();
+    }
+    drop(encoder);
+    buffer
+  }
+
+  #[test]
+  fn decodes_every_frame_with_delay() {
+    let frames = decode_frames(&gif(20, 10, 3)).unwrap();
+    assert_eq!(frames.len(), 3);
+    assert!(frames.iter().all(|x| x.image.dimensions() == (20, 10)));
+    assert!(frames.iter().all(|x| x.delay_ms == 70));
+  }
+
+  #[test]
+  fn rejects_animations_over_the_decoded_budget() {
+    // 文件只有几百字节，但每帧都要展开成 64 MiB 的画布。
+    let err = decode_frames(&gif(4096, 4096, 5)).err().unwrap();
+    assert!(matches!(
+      err.downcast_ref::<BridgeError>(),
+      Some(BridgeError::TooLarge { .. })
+    ));
+  }
+
+  #[test]
+  fn detects_animation() {
+    assert!(animated(&gif(4, 4, 2)).unwrap());
+    assert!(!animated(&gif(4, 4, 1)).unwrap());
+    let png = convert::encode(
+      &RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255])).into(),
+      ImageFormat::Png,
+      None,
+    )
+    .unwrap();
+    assert!(!animated(&png).unwrap());
+  }
+
+  #[test]
+  fn keeps_duration_when_dropping_frames() {
+    let frames = decode_frames(&gif(8, 8, 16)).unwrap();
+    let total = |step| sample(&frames, step).map(|(_, x)| x).sum::<u32>();
+    assert_eq!(total(1), 16 * 70);
+    assert_eq!(total(4), 16 * 70);
+    assert_eq!(sample(&frames, 4).count(), 4);
+  }
+}
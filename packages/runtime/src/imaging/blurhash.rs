@@ -0,0 +1,118 @@
+use std::f32::consts::PI;
+
+use image::RgbaImage;
+
+const BASE83: &[u8; 83] =
+  b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn base83(value: u32, length: u32, out: &mut String) {
+  for i in (0..length).rev() {
+    out.push(BASE83[(value / 83u32.pow(i) % 83) as usize] as char);
+  }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+  let v = value as f32 / 255.0;
+  if v <= 0.04045 {
+    v / 12.92
+  } else {
+    ((v + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+  let v = value.clamp(0.0, 1.0);
+  if v <= 0.0031308 {
+    (v * 12.92 * 255.0 + 0.5) as u32
+  } else {
+    ((1.055 * v.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+  }
+}
+
+fn sign_pow(value: f32, exp: f32) -> f32 {
+  value.abs().powf(exp).copysign(value)
+}
+
+/// 按 <https://github.com/woltapp/blurhash> 的算法编码，`components` 是横竖两个方向的分量数，各 1-9。
+///
+/// 透明的地方按白色算。图片应该先缩小，这里逐像素计算每个分量。
+pub fn encode(image: &RgbaImage, (cx, cy): (u32, u32)) -> String {
+  let (width, height) = image.dimensions();
+  let linear: Vec<[f32; 3]> = image
+    .pixels()
+    .map(|p| {
+      let alpha = p[3] as f32 / 255.0;
+      [0, 1, 2].map(|c| srgb_to_linear(p[c]) * alpha + (1.0 - alpha))
+    })
+    .collect();
+  let mut factors = Vec::with_capacity((cx * cy) as usize);
+  for j in 0..cy {
+    for i in 0..cx {
+      let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+      let mut factor = [0f32; 3];
+      for y in 0..height {
+        let basis_y = (PI * j as f32 * y as f32 / height as f32).cos();
+        for x in 0..width {
+          let basis = basis_y * (PI * i as f32 * x as f32 / width as f32).cos();
+          let pixel = linear[(y * width + x) as usize];
+          for (sum, value) in factor.iter_mut().zip(pixel) {
+            *sum += basis * value;
+          }
+        }
+      }
+      let scale = normalisation / (width * height) as f32;
+      factors.push(factor.map(|x| x * scale));
+    }
+  }
+
+  let mut hash = String::with_capacity(4 + 2 * factors.len());
+  base83((cx - 1) + (cy - 1) * 9, 1, &mut hash);
+  let (dc, ac) = factors.split_first().expect("at least one component");
+  let maximum = if ac.is_empty() {
+    base83(0, 1, &mut hash);
+    1.0
+  } else {
+    let actual = ac.iter().flatten().fold(0f32, |max, x| max.max(x.abs()));
+    let quantised = ((actual * 166.0 - 0.5).floor()).clamp(0.0, 82.0) as u32;
+    base83(quantised, 1, &mut hash);
+    (quantised + 1) as f32 / 166.0
+  };
+  let [r, g, b] = dc.map(linear_to_srgb);
+  base83((r << 16) + (g << 8) + b, 4, &mut hash);
+  for factor in ac {
+    let [r, g, b] =
+      factor.map(|x| ((sign_pow(x / maximum, 0.5) * 9.0 + 9.5).floor()).clamp(0.0, 18.0) as u32);
+    base83(r * 19 * 19 + g * 19 + b, 2, &mut hash);
+  }
+  hash
+}
+
+#[cfg(test)]
+mod tests {
+  use image::Rgba;
+
+  use super::*;
+
+  fn gradient() -> RgbaImage {
+    RgbaImage::from_fn(32, 24, |x, y| {
+      Rgba([(x * 8) as u8, (y * 10) as u8, ((x + y) * 4) as u8, 255])
+    })
+  }
+
+  #[test]
+  fn matches_the_reference_implementation() {
+    // 期望值由 woltapp 的 TypeScript 编码器算出。
+    let black = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+    assert_eq!(encode(&black, (4, 3)), "L00000fQfQfQfQfQfQfQfQfQfQfQ");
+    assert_eq!(encode(&gradient(), (4, 3)), "LxH27b2kwzX5mAWYjuf7gKfkfQfj");
+    assert_eq!(encode(&gradient(), (3, 4)), "TxH27b2kwzmAWYjugKfkfQn+Wojt");
+    assert_eq!(encode(&gradient(), (1, 1)), "00H27b");
+  }
+
+  #[test]
+  fn treats_transparency_as_white() {
+    let white = RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255]));
+    let transparent = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 0]));
+    assert_eq!(encode(&transparent, (4, 3)), encode(&white, (4, 3)));
+  }
+}
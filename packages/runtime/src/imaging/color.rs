@@ -0,0 +1,245 @@
+/// CIE L*a*b*，D65 白点。
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Lab {
+  pub l: f32,
+  pub a: f32,
+  pub b: f32,
+}
+
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+fn to_linear(value: u8) -> f32 {
+  let v = value as f32 / 255.0;
+  if v <= 0.04045 {
+    v / 12.92
+  } else {
+    ((v + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+fn from_linear(value: f32) -> u8 {
+  let v = value.clamp(0.0, 1.0);
+  let v = if v <= 0.0031308 {
+    v * 12.92
+  } else {
+    1.055 * v.powf(1.0 / 2.4) - 0.055
+  };
+  (v * 255.0).round() as u8
+}
+
+impl Lab {
+  pub fn from_rgb(rgb: [u8; 3]) -> Self {
+    let [r, g, b] = rgb.map(to_linear);
+    let xyz = [
+      r * 0.4124 + g * 0.3576 + b * 0.1805,
+      r * 0.2126 + g * 0.7152 + b * 0.0722,
+      r * 0.0193 + g * 0.1192 + b * 0.9505,
+    ];
+    let [x, y, z] = [0, 1, 2].map(|i| {
+      let t = xyz[i] / WHITE[i];
+      if t > 0.008856 {
+        t.cbrt()
+      } else {
+        7.787 * t + 16.0 / 116.0
+      }
+    });
+    Self {
+      l: 116.0 * y - 16.0,
+      a: 500.0 * (x - y),
+      b: 200.0 * (y - z),
+    }
+  }
+
+  pub fn to_rgb(self) -> [u8; 3] {
+    let y = (self.l + 16.0) / 116.0;
+    let f = [self.a / 500.0 + y, y, y - self.b / 200.0];
+    let [x, y, z] = [0, 1, 2].map(|i| {
+      let t = f[i];
+      let t = if t.powi(3) > 0.008856 {
+        t.powi(3)
+      } else {
+        (t - 16.0 / 116.0) / 7.787
+      };
+      t * WHITE[i]
+    });
+    [
+      x * 3.2406 + y * -1.5372 + z * -0.4986,
+      x * -0.9689 + y * 1.8758 + z * 0.0415,
+      x * 0.0557 + y * -0.2040 + z * 1.0570,
+    ]
+    .map(from_linear)
+  }
+
+  pub fn chroma(self) -> f32 {
+    self.a.hypot(self.b)
+  }
+
+  /// CIE76 色差的平方，聚类时用。
+  pub fn distance_squared(self, other: Self) -> f32 {
+    (self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2)
+  }
+
+  /// CIEDE2000 色差，kL = kC = kH = 1。
+  pub fn ciede2000(self, other: Self) -> f32 {
+    let (l1, a1, b1) = (self.l as f64, self.a as f64, self.b as f64);
+    let (l2, a2, b2) = (other.l as f64, other.a as f64, other.b as f64);
+    let c_mean = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let g = 0.5 * (1.0 - (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt());
+    let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |b: f64, a: f64| match (a, b) {
+      (0.0, 0.0) => 0.0,
+      _ => b.atan2(a).to_degrees().rem_euclid(360.0),
+    };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = match c1 * c2 {
+      0.0 => 0.0,
+      _ if (h2 - h1).abs() <= 180.0 => h2 - h1,
+      _ if h2 <= h1 => h2 - h1 + 360.0,
+      _ => h2 - h1 - 360.0,
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = match c1 * c2 {
+      0.0 => h1 + h2,
+      _ if (h1 - h2).abs() <= 180.0 => (h1 + h2) / 2.0,
+      _ if h1 + h2 < 360.0 => (h1 + h2 + 360.0) / 2.0,
+      _ => (h1 + h2 - 360.0) / 2.0,
+    };
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+      + 0.24 * (2.0 * h_mean).to_radians().cos()
+      + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+      - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+    (l * l + c * c + h * h + r_t * c * h).sqrt() as f32
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lab(l: f32, a: f32, b: f32) -> Lab {
+    Lab { l, a, b }
+  }
+
+  /// Sharma, Wu, Dalal (2005) 的测试数据，覆盖了色相跨 0°/180° 等边界情况。
+  const SHARMA: [([f32; 3], [f32; 3], f32); 34] = [
+    ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+    ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+    ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+    ([50.0, -1.3802, -84.2814], [50.0, 0.0, -82.7485], 1.0000),
+    ([50.0, -1.1848, -84.8006], [50.0, 0.0, -82.7485], 1.0000),
+    ([50.0, -0.9009, -85.5211], [50.0, 0.0, -82.7485], 1.0000),
+    ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+    ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+    ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0009], 7.1792),
+    ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0010], 7.1792),
+    ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0011], 7.2195),
+    ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0012], 7.2195),
+    ([50.0, -0.0010, 2.4900], [50.0, 0.0009, -2.4900], 4.8045),
+    ([50.0, -0.0010, 2.4900], [50.0, 0.0010, -2.4900], 4.8045),
+    ([50.0, -0.0010, 2.4900], [50.0, 0.0011, -2.4900], 4.7461),
+    ([50.0, 2.5, 0.0], [50.0, 0.0, -2.5], 4.3065),
+    ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+    ([50.0, 2.5, 0.0], [61.0, -5.0, 29.0], 22.8977),
+    ([50.0, 2.5, 0.0], [56.0, -27.0, -3.0], 31.9030),
+    ([50.0, 2.5, 0.0], [58.0, 24.0, 15.0], 19.4535),
+    ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0000),
+    ([50.0, 2.5, 0.0], [50.0, 3.2972, 0.0], 1.0000),
+    ([50.0, 2.5, 0.0], [50.0, 1.8634, 0.5757], 1.0000),
+    ([50.0, 2.5, 0.0], [50.0, 3.2592, 0.3350], 1.0000),
+    (
+      [60.2574, -34.0099, 36.2677],
+      [60.4626, -34.1751, 39.4387],
+      1.2644,
+    ),
+    (
+      [63.0109, -31.0961, -5.8663],
+      [62.8187, -29.7946, -4.0864],
+      1.2630,
+    ),
+    (
+      [61.2901, 3.7196, -5.3901],
+      [61.4292, 2.2480, -4.9620],
+      1.8731,
+    ),
+    (
+      [35.0831, -44.1164, 3.7933],
+      [35.0232, -40.0716, 1.5901],
+      1.8645,
+    ),
+    (
+      [22.7233, 20.0904, -46.6940],
+      [23.0331, 14.9730, -42.5619],
+      2.0373,
+    ),
+    (
+      [36.4612, 47.8580, 18.3852],
+      [36.2715, 50.5065, 21.2231],
+      1.4146,
+    ),
+    (
+      [90.8027, -2.0831, 1.4410],
+      [91.1528, -1.6435, 0.0447],
+      1.4441,
+    ),
+    (
+      [90.9257, -0.5406, -0.9208],
+      [88.6381, -0.8985, -0.7239],
+      1.5381,
+    ),
+    (
+      [6.7747, -0.2908, -2.4247],
+      [5.8714, -0.0985, -2.2286],
+      0.6377,
+    ),
+    (
+      [2.0776, 0.0795, -1.1350],
+      [0.9033, -0.0636, -0.5514],
+      0.9082,
+    ),
+  ];
+
+  #[test]
+  fn matches_sharma_test_data() {
+    for ([l1, a1, b1], [l2, a2, b2], expected) in SHARMA {
+      let (x, y) = (lab(l1, a1, b1), lab(l2, a2, b2));
+      let actual = x.ciede2000(y);
+      assert!(
+        (actual - expected).abs() < 1e-4,
+        "{x:?} {y:?}: {actual} != {expected}"
+      );
+      // 交换顺序结果不变。
+      assert!((y.ciede2000(x) - actual).abs() < 1e-5);
+    }
+  }
+
+  #[test]
+  fn round_trips_rgb() {
+    for rgb in [
+      [0, 0, 0],
+      [255, 255, 255],
+      [255, 0, 0],
+      [12, 200, 99],
+      [128, 64, 250],
+    ] {
+      assert_eq!(Lab::from_rgb(rgb).to_rgb(), rgb);
+    }
+    // 矩阵只有四位小数，白色的色度不是严格的 0。
+    let white = Lab::from_rgb([255, 255, 255]);
+    assert!((white.l - 100.0).abs() < 0.01 && white.chroma() < 0.05);
+  }
+}
@@ -0,0 +1,128 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use image::{
+  DynamicImage, ImageFormat, ImageReader,
+  codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+  imageops::FilterType,
+};
+use napi::{bindgen_prelude::Buffer, tokio};
+use napi_derive::napi;
+
+use crate::qqbot::error::{BridgeError, Coded};
+
+/// 静态图片能转成的格式。
+const TARGETS: [ImageFormat; 5] = [
+  ImageFormat::Jpeg,
+  ImageFormat::Png,
+  ImageFormat::WebP,
+  ImageFormat::Gif,
+  ImageFormat::Bmp,
+];
+
+const DEFAULT_QUALITY: u8 = 85;
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+  /// 长边超过这个值时等比缩小。
+  pub max_dimension: Option<u32>,
+  /// JPEG 的质量，1-100，默认 85。WebP 只支持无损编码，不受影响。
+  pub quality: Option<u32>,
+}
+
+#[napi(object)]
+pub struct ConvertedImage {
+  pub buffer: Buffer,
+  pub mime: String,
+  pub width: u32,
+  pub height: u32,
+}
+
+/// 解码图片，格式按文件头判断。动图只取第一帧。
+pub(crate) fn decode(data: &[u8]) -> anyhow::Result<(DynamicImage, ImageFormat)> {
+  let reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+  let format = reader
+    .format()
+    .ok_or_else(|| BridgeError::InvalidArgument("unrecognized image format".to_owned()))?;
+  let image = reader
+    .decode()
+    .map_err(|err| BridgeError::InvalidArgument(format!("cannot decode {format:?}: {err}")))?;
+  Ok((image, format))
+}
+
+pub(crate) fn target_format(mime: &str) -> anyhow::Result<ImageFormat> {
+  ImageFormat::from_mime_type(mime)
+    .filter(|format| TARGETS.contains(format))
+    .ok_or_else(|| BridgeError::InvalidArgument(format!("cannot convert to {mime}")).into())
+}
+
+pub(crate) fn fit(image: DynamicImage, max_dimension: Option<u32>) -> DynamicImage {
+  match max_dimension {
+    Some(max) if max > 0 && image.width().max(image.height()) > max => {
+      image.resize(max, max, FilterType::Lanczos3)
+    }
+    _ => image,
+  }
+}
+
+pub(crate) fn encode(
+  image: &DynamicImage,
+  format: ImageFormat,
+  quality: Option<u32>,
+) -> anyhow::Result<Vec<u8>> {
+  let mut buffer = Cursor::new(vec![]);
+  match format {
+    ImageFormat::Jpeg => {
+      let quality = quality.map_or(DEFAULT_QUALITY, |x| x.clamp(1, 100) as u8);
+      // JPEG 没有透明通道。
+      image
+        .to_rgb8()
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut buffer, quality))?;
+    }
+    ImageFormat::WebP => {
+      image
+        .to_rgba8()
+        .write_with_encoder(WebPEncoder::new_lossless(&mut buffer))?;
+    }
+    // GIF 和 BMP 的编码器只接受 8 位的像素。
+    ImageFormat::Gif | ImageFormat::Bmp => {
+      image.to_rgba8().write_to(&mut buffer, format)?;
+    }
+    _ => image.write_to(&mut buffer, format)?,
+  }
+  Ok(buffer.into_inner())
+}
+
+fn convert(
+  data: &[u8],
+  target_mime: &str,
+  options: ConvertOptions,
+) -> anyhow::Result<ConvertedImage> {
+  let format = target_format(target_mime)?;
+  let (image, _) = decode(data)?;
+  let image = fit(image, options.max_dimension);
+  let buffer = encode(&image, format, options.quality)
+    .with_context(|| format!("Failed to encode {format:?}"))?;
+  Ok(ConvertedImage {
+    buffer: buffer.into(),
+    mime: format.to_mime_type().to_owned(),
+    width: image.width(),
+    height: image.height(),
+  })
+}
+
+/// 转换静态图片，支持 JPEG/PNG/WebP/GIF/BMP。动图只保留第一帧。
+#[napi(ts_return_type = "Promise<ConvertedImage>")]
+pub async fn convert_image(
+  buffer: Buffer,
+  target_mime: String,
+  options: Option<ConvertOptions>,
+) -> Coded<ConvertedImage> {
+  Coded::wrap(async move {
+    let data = buffer.to_vec();
+    tokio::task::spawn_blocking(move || convert(&data, &target_mime, options.unwrap_or_default()))
+      .await?
+  })
+  .await
+}
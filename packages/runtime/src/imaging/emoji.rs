@@ -0,0 +1,111 @@
+use std::sync::{LazyLock, Mutex};
+
+use napi_derive::napi;
+
+use super::{color::Lab, palette};
+use crate::qqbot::{connection::Recent, error::BridgeError, media_cache::media_hash};
+
+/// 内置的色块 emoji，颜色取自 Twemoji。
+#[napi(string_enum)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EmojiShape {
+  #[default]
+  Circle,
+  Square,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct EmojiColor {
+  /// 选中时返回的文字，不一定是 emoji。
+  pub label: String,
+  pub rgb: Vec<u8>,
+}
+
+const COLORS: [([u8; 3], &str, &str); 9] = [
+  ([221, 46, 68], "🔴", "🟥"),
+  ([85, 172, 238], "🔵", "🟦"),
+  ([244, 144, 12], "🟠", "🟧"),
+  ([253, 203, 88], "🟡", "🟨"),
+  ([120, 177, 89], "🟢", "🟩"),
+  ([170, 142, 214], "🟣", "🟪"),
+  ([193, 105, 79], "🟤", "🟫"),
+  ([49, 55, 61], "⚫", "⬛"),
+  ([230, 231, 232], "⚪", "⬜"),
+];
+
+#[napi]
+pub fn emoji_palette(shape: EmojiShape) -> Vec<EmojiColor> {
+  COLORS
+    .iter()
+    .map(|&(rgb, circle, square)| EmojiColor {
+      label: match shape {
+        EmojiShape::Circle => circle,
+        EmojiShape::Square => square,
+      }
+      .to_owned(),
+      rgb: rgb.into(),
+    })
+    .collect()
+}
+
+/// 键是头像的 mxc URI 或内容哈希，加上调色板。
+static CACHE: LazyLock<Mutex<Recent<String, String>>> = LazyLock::new(|| Mutex::new(Recent::new()));
+
+fn cache_key(key: &str, palette: Option<&[EmojiColor]>) -> String {
+  match palette {
+    None => key.to_owned(),
+    Some(palette) => palette.iter().fold(key.to_owned(), |key, color| {
+      format!("{key}\n{}:{:?}", color.label, color.rgb)
+    }),
+  }
+}
+
+fn nearest(lab: Lab, palette: &[EmojiColor]) -> anyhow::Result<String> {
+  let mut best = None;
+  for color in palette {
+    let &[r, g, b] = color.rgb.as_slice() else {
+      return Err(
+        BridgeError::InvalidArgument(format!("rgb of {:?} must have 3 components", color.label))
+          .into(),
+      );
+    };
+    let distance = lab.ciede2000(Lab::from_rgb([r, g, b]));
+    if best.is_none_or(|(best, _)| distance < best) {
+      best = Some((distance, &color.label));
+    }
+  }
+  let (_, label) =
+    best.ok_or_else(|| BridgeError::InvalidArgument("emoji palette is empty".to_owned()))?;
+  Ok(label.clone())
+}
+
+/// 用 CIEDE2000 挑出和头像主色最接近的 emoji，默认是九种圆形色块。
+///
+/// 结果按 `key`（一般是头像的 mxc URI）缓存，没给时用内容的哈希。
+#[napi]
+pub fn avatar_emoji(
+  img: &[u8],
+  palette: Option<Vec<EmojiColor>>,
+  key: Option<String>,
+) -> anyhow::Result<String> {
+  let key = cache_key(&key.unwrap_or_else(|| media_hash(img)), palette.as_deref());
+  if let Some(emoji) = CACHE.lock().unwrap().get(&key) {
+    return Ok(emoji);
+  }
+  let palette = palette.unwrap_or_else(|| emoji_palette(EmojiShape::Circle));
+  // 解不出颜色时（比如全透明）按黑色处理。
+  let lab = palette::dominant(img)?.unwrap_or_default();
+  let emoji = nearest(lab, &palette)?;
+  CACHE.lock().unwrap().insert(key, emoji.clone());
+  Ok(emoji)
+}
+
+/// 查 `avatar_emoji` 算过的结果，命中时不用再下载头像。
+#[napi]
+pub fn cached_avatar_emoji(key: String, palette: Option<Vec<EmojiColor>>) -> Option<String> {
+  CACHE
+    .lock()
+    .unwrap()
+    .get(&cache_key(&key, palette.as_deref()))
+}
@@ -0,0 +1,84 @@
+use image::{DynamicImage, ImageFormat, imageops::FilterType};
+use napi::{bindgen_prelude::Buffer, tokio};
+use napi_derive::napi;
+
+use super::{
+  animation, blurhash,
+  convert::{self, ConvertedImage},
+  sniff::{self, UNKNOWN_MIME},
+};
+use crate::qqbot::error::Coded;
+
+const DEFAULT_THUMBNAIL_DIMENSION: u32 = 320;
+/// 算 blurhash 前先缩到这么大，结果只有几个分量，不需要更多像素。
+const BLURHASH_DIMENSION: u32 = 64;
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct ImageInfoOptions {
+  /// 缩略图长边的上限，默认 320。
+  pub thumbnail_dimension: Option<u32>,
+}
+
+/// 发到 Matrix 时填 `info` 用的信息。
+#[napi(object)]
+pub struct ImageInfo {
+  pub mime: String,
+  pub width: u32,
+  pub height: u32,
+  pub size: i64,
+  pub animated: bool,
+  pub frame_count: u32,
+  /// 第一帧的缩略图，不透明时是 JPEG，否则是 PNG。
+  pub thumbnail: ConvertedImage,
+  pub blurhash: String,
+}
+
+fn describe(data: &[u8], options: ImageInfoOptions) -> anyhow::Result<ImageInfo> {
+  let (frame, frame_count) = animation::first_frame(data)?;
+  let (width, height) = frame.dimensions();
+  let frame = DynamicImage::ImageRgba8(frame);
+
+  let thumbnail_dimension = options
+    .thumbnail_dimension
+    .unwrap_or(DEFAULT_THUMBNAIL_DIMENSION);
+  let thumbnail = convert::fit(frame.clone(), Some(thumbnail_dimension));
+  let opaque = thumbnail
+    .as_rgba8()
+    .is_none_or(|x| x.pixels().all(|p| p[3] == 255));
+  let format = if opaque {
+    ImageFormat::Jpeg
+  } else {
+    ImageFormat::Png
+  };
+
+  let small = frame.resize(BLURHASH_DIMENSION, BLURHASH_DIMENSION, FilterType::Triangle);
+  // 横图多给横向分量，竖图多给纵向分量。
+  let components = if width >= height { (4, 3) } else { (3, 4) };
+
+  Ok(ImageInfo {
+    mime: sniff::sniff_mime(data).unwrap_or(UNKNOWN_MIME).to_owned(),
+    width,
+    height,
+    size: data.len() as i64,
+    animated: frame_count > 1,
+    frame_count,
+    thumbnail: ConvertedImage {
+      buffer: convert::encode(&thumbnail, format, None)?.into(),
+      mime: format.to_mime_type().to_owned(),
+      width: thumbnail.width(),
+      height: thumbnail.height(),
+    },
+    blurhash: blurhash::encode(&small.to_rgba8(), components),
+  })
+}
+
+/// 读取尺寸、类型和帧数，并生成缩略图和 blurhash。
+#[napi(ts_return_type = "Promise<ImageInfo>")]
+pub async fn image_info(buffer: Buffer, options: Option<ImageInfoOptions>) -> Coded<ImageInfo> {
+  Coded::wrap(async move {
+    let data = buffer.to_vec();
+    tokio::task::spawn_blocking(move || describe(&data, options.unwrap_or_default())).await?
+  })
+  .await
+}
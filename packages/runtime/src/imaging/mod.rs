@@ -0,0 +1,9 @@
+pub mod animation;
+pub mod blurhash;
+pub mod color;
+pub mod convert;
+pub mod emoji;
+pub mod info;
+pub mod palette;
+pub mod phash;
+pub mod sniff;
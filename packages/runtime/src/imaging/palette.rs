@@ -0,0 +1,331 @@
+use std::collections::{HashMap, VecDeque};
+
+use image::RgbaImage;
+use napi_derive::napi;
+
+use super::{color::Lab, convert};
+
+/// 先缩到这么大再统计，头像用不着更多像素。
+const SAMPLE_DIMENSION: u32 = 128;
+/// 不透明度低于这个值的像素不参与统计。
+const ALPHA_THRESHOLD: u8 = 128;
+/// 边框上超过这个比例的像素颜色相近时，认为是纯色背景。
+const BACKGROUND_SHARE: f32 = 0.6;
+/// 和背景色的色差在这个范围内的像素算作背景。
+const BACKGROUND_DISTANCE: f32 = 10.0;
+const MAX_K: u32 = 16;
+/// 挑头像主色时聚成几类。
+const DOMINANT_K: u32 = 6;
+const MAX_ITERATIONS: usize = 20;
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct PaletteOptions {
+  /// 去掉从边框连通过来的纯色背景，默认开启。整张图都是背景时不去掉。
+  pub ignore_background: Option<bool>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct PaletteColor {
+  pub rgb: Vec<u8>,
+  /// `[L, a, b]`
+  pub lab: Vec<f64>,
+  /// 占参与统计的像素的比例，所有颜色加起来是 1。
+  pub weight: f64,
+}
+
+pub(crate) struct Cluster {
+  pub lab: Lab,
+  pub weight: f32,
+}
+
+impl From<&Cluster> for PaletteColor {
+  fn from(value: &Cluster) -> Self {
+    let Lab { l, a, b } = value.lab;
+    Self {
+      rgb: value.lab.to_rgb().into(),
+      lab: vec![l as f64, a as f64, b as f64],
+      weight: value.weight as f64,
+    }
+  }
+}
+
+fn mean(labs: &[Lab]) -> Lab {
+  let n = labs.len() as f32;
+  labs.iter().fold(Lab::default(), |sum, x| Lab {
+    l: sum.l + x.l / n,
+    a: sum.a + x.a / n,
+    b: sum.b + x.b / n,
+  })
+}
+
+/// 从边框开始，把和边框主色相近且连通的像素标成背景。边框颜色杂乱时返回 `None`。
+fn background_mask(image: &RgbaImage, labs: &[Option<Lab>]) -> Option<Vec<bool>> {
+  let (width, height) = image.dimensions();
+  let index = |x: u32, y: u32| (y * width + x) as usize;
+  let coarse = |i: usize| {
+    let p = &image.as_raw()[i * 4..];
+    [p[0] >> 4, p[1] >> 4, p[2] >> 4]
+  };
+  let border: Vec<usize> = (0..width)
+    .flat_map(|x| [index(x, 0), index(x, height - 1)])
+    .chain((0..height).flat_map(|y| [index(0, y), index(width - 1, y)]))
+    .collect();
+  // 边框上最常见的颜色，按 4 位量化后计数。
+  let mut counts = HashMap::new();
+  for &i in border.iter().filter(|&&i| labs[i].is_some()) {
+    *counts.entry(coarse(i)).or_insert(0usize) += 1;
+  }
+  let (key, count) = counts
+    .into_iter()
+    .max_by_key(|&(key, count)| (count, key))?;
+  if (count as f32) < border.len() as f32 * BACKGROUND_SHARE {
+    return None;
+  }
+  let background = mean(
+    &border
+      .iter()
+      .filter(|&&i| coarse(i) == key)
+      .filter_map(|&i| labs[i])
+      .collect::<Vec<_>>(),
+  );
+  let threshold = BACKGROUND_DISTANCE.powi(2);
+  let is_background =
+    |i: usize| labs[i].is_some_and(|lab| lab.distance_squared(background) <= threshold);
+
+  let mut mask = vec![false; labs.len()];
+  let mut queue = VecDeque::new();
+  for i in border {
+    if !mask[i] && is_background(i) {
+      mask[i] = true;
+      queue.push_back(i);
+    }
+  }
+  while let Some(i) = queue.pop_front() {
+    let (x, y) = (i as u32 % width, i as u32 / width);
+    let neighbours = [
+      (x > 0).then(|| index(x - 1, y)),
+      (x + 1 < width).then(|| index(x + 1, y)),
+      (y > 0).then(|| index(x, y - 1)),
+      (y + 1 < height).then(|| index(x, y + 1)),
+    ];
+    for j in neighbours.into_iter().flatten() {
+      if !mask[j] && is_background(j) {
+        mask[j] = true;
+        queue.push_back(j);
+      }
+    }
+  }
+  Some(mask)
+}
+
+/// 加权的 k-means。初始中心取粗量化后最多的 `k` 种颜色，结果是确定的。
+fn kmeans(points: &[(Lab, f32)], k: usize) -> Vec<Cluster> {
+  let mut bins: HashMap<[i32; 3], (Lab, f32)> = HashMap::new();
+  for &(lab, weight) in points {
+    let key = [lab.l / 12.5, lab.a / 25.0, lab.b / 25.0].map(|x| x.floor() as i32);
+    let (sum, total) = bins.entry(key).or_default();
+    sum.l += lab.l * weight;
+    sum.a += lab.a * weight;
+    sum.b += lab.b * weight;
+    *total += weight;
+  }
+  let mut bins: Vec<_> = bins.into_iter().collect();
+  bins.sort_unstable_by(|(ka, (_, a)), (kb, (_, b))| b.total_cmp(a).then(ka.cmp(kb)));
+  let mut centers: Vec<Lab> = bins
+    .iter()
+    .take(k)
+    .map(|(_, (sum, total))| Lab {
+      l: sum.l / total,
+      a: sum.a / total,
+      b: sum.b / total,
+    })
+    .collect();
+
+  let mut assignment = vec![usize::MAX; points.len()];
+  for _ in 0..MAX_ITERATIONS {
+    let mut changed = false;
+    for (slot, &(lab, _)) in assignment.iter_mut().zip(points) {
+      let nearest = (0..centers.len())
+        .min_by(|&a, &b| {
+          lab
+            .distance_squared(centers[a])
+            .total_cmp(&lab.distance_squared(centers[b]))
+        })
+        .unwrap_or_default();
+      changed |= *slot != nearest;
+      *slot = nearest;
+    }
+    if !changed {
+      break;
+    }
+    let mut sums = vec![(Lab::default(), 0f32); centers.len()];
+    for (&cluster, &(lab, weight)) in assignment.iter().zip(points) {
+      let (sum, total) = &mut sums[cluster];
+      sum.l += lab.l * weight;
+      sum.a += lab.a * weight;
+      sum.b += lab.b * weight;
+      *total += weight;
+    }
+    for (center, (sum, total)) in centers.iter_mut().zip(sums) {
+      if total > 0.0 {
+        *center = Lab {
+          l: sum.l / total,
+          a: sum.a / total,
+          b: sum.b / total,
+        };
+      }
+    }
+  }
+
+  let mut weights = vec![0f32; centers.len()];
+  for (&cluster, &(_, weight)) in assignment.iter().zip(points) {
+    weights[cluster] += weight;
+  }
+  let total: f32 = weights.iter().sum();
+  let mut clusters: Vec<_> = centers
+    .into_iter()
+    .zip(weights)
+    .filter(|(_, weight)| *weight > 0.0)
+    .map(|(lab, weight)| Cluster {
+      lab,
+      weight: weight / total,
+    })
+    .collect();
+  clusters.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+  clusters
+}
+
+/// 聚类结果按权重从大到小排列。
+pub(crate) fn palette(
+  img: &[u8],
+  k: u32,
+  options: &PaletteOptions,
+) -> anyhow::Result<Vec<Cluster>> {
+  let (image, _) = convert::decode(img)?;
+  let image = convert::fit(image, Some(SAMPLE_DIMENSION)).into_rgba8();
+  let labs: Vec<Option<Lab>> = image
+    .pixels()
+    .map(|p| (p[3] >= ALPHA_THRESHOLD).then(|| Lab::from_rgb([p[0], p[1], p[2]])))
+    .collect();
+
+  let mask = match options.ignore_background.unwrap_or(true) {
+    true => background_mask(&image, &labs),
+    false => None,
+  };
+  let count = |mask: Option<&[bool]>| {
+    // 相同的颜色合并成一个带权重的点，聚类快得多。
+    let mut points: HashMap<[u8; 3], (Lab, f32)> = HashMap::new();
+    for (i, (p, lab)) in image.pixels().zip(&labs).enumerate() {
+      if let Some(lab) = lab
+        && !mask.is_some_and(|mask| mask[i])
+      {
+        points.entry([p[0], p[1], p[2]]).or_insert((*lab, 0.0)).1 += 1.0;
+      }
+    }
+    let mut points: Vec<_> = points.into_iter().collect();
+    points.sort_unstable_by_key(|(rgb, _)| *rgb);
+    points
+      .into_iter()
+      .map(|(_, point)| point)
+      .collect::<Vec<_>>()
+  };
+  let mut points = count(mask.as_deref());
+  if points.is_empty() {
+    // 整张图都是背景，那背景就是主体。
+    points = count(None);
+  }
+  Ok(kmeans(&points, k.clamp(1, MAX_K) as usize))
+}
+
+/// 从调色板里挑占比和彩度都高的颜色，透明像素和纯色背景不算在内。
+pub(crate) fn dominant(img: &[u8]) -> anyhow::Result<Option<Lab>> {
+  let clusters = palette(img, DOMINANT_K, &PaletteOptions::default())?;
+  // 灰色的彩度接近 0，加 1 让全灰的头像按占比挑。
+  let score = |cluster: &&Cluster| cluster.weight * (cluster.lab.chroma() + 1.0);
+  Ok(
+    clusters
+      .iter()
+      .max_by(|a, b| score(a).total_cmp(&score(b)))
+      .map(|cluster| cluster.lab),
+  )
+}
+
+/// 在 Lab 空间里用 k-means 提取最多 `k` 种主要颜色，按占比从大到小排列。
+///
+/// 透明的像素不参与统计。
+#[napi]
+pub fn calc_palette(
+  img: &[u8],
+  k: u32,
+  options: Option<PaletteOptions>,
+) -> anyhow::Result<Vec<PaletteColor>> {
+  let clusters = palette(img, k, &options.unwrap_or_default())?;
+  Ok(clusters.iter().map(Into::into).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{ImageFormat, Rgba};
+
+  use super::*;
+
+  fn png(image: &RgbaImage) -> Vec<u8> {
+    convert::encode(&image.clone().into(), ImageFormat::Png, None).unwrap()
+  }
+
+  #[test]
+  fn kmeans_finds_weighted_centers() {
+    let red = Lab::from_rgb([255, 0, 0]);
+    let blue = Lab::from_rgb([0, 0, 255]);
+    let darker = Lab {
+      l: red.l - 2.0,
+      ..red
+    };
+    let points = [(red, 3.0), (darker, 1.0), (blue, 4.0), (blue, 4.0)];
+    let clusters = kmeans(&points, 2);
+    assert_eq!(clusters.len(), 2);
+    // 按权重从大到小，权重加起来是 1。
+    assert!((clusters[0].weight - 2.0 / 3.0).abs() < 1e-6);
+    assert!((clusters[1].weight - 1.0 / 3.0).abs() < 1e-6);
+    assert!(clusters[0].lab.distance_squared(blue) < 1e-6);
+    assert!((clusters[1].lab.l - (red.l - 0.5)).abs() < 1e-4);
+  }
+
+  #[test]
+  fn kmeans_never_returns_empty_clusters() {
+    let gray = Lab::from_rgb([128, 128, 128]);
+    let clusters = kmeans(&[(gray, 1.0), (gray, 2.0)], 5);
+    assert_eq!(clusters.len(), 1);
+    assert_eq!(clusters[0].weight, 1.0);
+    assert!(kmeans(&[], 3).is_empty());
+  }
+
+  #[test]
+  fn ignores_solid_background() {
+    let mut image = RgbaImage::from_pixel(32, 32, Rgba([255, 255, 255, 255]));
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+      if (8..24).contains(&x) && (8..24).contains(&y) {
+        *pixel = Rgba([200, 30, 30, 255]);
+      }
+    }
+    let image = png(&image);
+    let clusters = palette(&image, 4, &PaletteOptions::default()).unwrap();
+    assert_eq!(clusters.len(), 1);
+    assert_eq!(clusters[0].lab.to_rgb(), [200, 30, 30]);
+
+    let options = PaletteOptions {
+      ignore_background: Some(false),
+    };
+    assert_eq!(palette(&image, 4, &options).unwrap().len(), 2);
+    // 整张图都是背景时不去掉。
+    let white = png(&RgbaImage::from_pixel(8, 8, Rgba([255, 255, 255, 255])));
+    assert_eq!(
+      palette(&white, 4, &PaletteOptions::default())
+        .unwrap()
+        .len(),
+      1
+    );
+  }
+}
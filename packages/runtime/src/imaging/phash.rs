@@ -0,0 +1,254 @@
+use std::{
+  collections::VecDeque,
+  f32::consts::PI,
+  sync::{LazyLock, Mutex},
+};
+
+use image::{DynamicImage, GrayImage, RgbaImage, imageops::FilterType};
+use napi::{bindgen_prelude::Buffer, tokio};
+use napi_derive::napi;
+
+use super::animation;
+use crate::qqbot::{
+  error::{BridgeError, Coded},
+  outbox::now_ms,
+};
+
+const DEFAULT_CAPACITY: u32 = 4096;
+
+/// 最多 64 位的哈希（pHash 63 位），写成 16 位十六进制，JS 的数字装不下。
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct PerceptualHash {
+  /// 相邻像素的明暗关系，对缩放和重新压缩不敏感。
+  pub dhash: String,
+  /// 低频 DCT 系数和中位数的关系，对调色、加水印更稳健。
+  pub phash: String,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct PerceptualMatch {
+  pub key: String,
+  /// 不同的位数，0 表示哈希完全相同。
+  pub distance: u32,
+}
+
+/// 透明的地方按白色算，再转成灰度。
+fn grayscale(image: RgbaImage, width: u32, height: u32) -> GrayImage {
+  let mut image = image;
+  for p in image.pixels_mut() {
+    let alpha = p[3] as u32;
+    for c in &mut p.0[..3] {
+      *c = ((*c as u32 * alpha + 255 * (255 - alpha)) / 255) as u8;
+    }
+    p[3] = 255;
+  }
+  DynamicImage::ImageRgba8(image)
+    .resize_exact(width, height, FilterType::Triangle)
+    .into_luma8()
+}
+
+fn dhash(image: RgbaImage) -> u64 {
+  let gray = grayscale(image, 9, 8);
+  let mut hash = 0;
+  for y in 0..8 {
+    for x in 0..8 {
+      hash = hash << 1 | (gray.get_pixel(x, y)[0] < gray.get_pixel(x + 1, y)[0]) as u64;
+    }
+  }
+  hash
+}
+
+/// 32×32 灰度图的 DCT，只算左上角 8×8 的低频部分。
+fn phash(image: RgbaImage) -> u64 {
+  let gray = grayscale(image, 32, 32);
+  static COS: LazyLock<[[f32; 32]; 8]> = LazyLock::new(|| {
+    std::array::from_fn(|u| {
+      std::array::from_fn(|x| ((2 * x + 1) as f32 * u as f32 * PI / 64.0).cos())
+    })
+  });
+  let mut coefficients = [0f32; 64];
+  for v in 0..8 {
+    for u in 0..8 {
+      let mut sum = 0.0;
+      for (y, row) in gray.rows().enumerate() {
+        let cos_y = COS[v][y];
+        for (x, p) in row.enumerate() {
+          sum += p[0] as f32 * COS[u][x] * cos_y;
+        }
+      }
+      coefficients[v * 8 + u] = sum;
+    }
+  }
+  // 第一个是直流分量，只反映整体亮度，比其他系数大得多，不参与中位数也不占位，哈希只有 63 位。
+  let ac = &coefficients[1..];
+  let mut sorted = ac.to_vec();
+  sorted.sort_by(f32::total_cmp);
+  let median = sorted[sorted.len() / 2];
+  ac.iter()
+    .fold(0, |hash, &x| hash << 1 | (x > median) as u64)
+}
+
+fn parse(hash: &str) -> anyhow::Result<u64> {
+  u64::from_str_radix(hash, 16)
+    .map_err(|_| BridgeError::InvalidArgument(format!("not a perceptual hash: {hash:?}")).into())
+}
+
+/// 计算图片的 dHash 和 pHash，动图只看第一帧。
+#[napi(ts_return_type = "Promise<PerceptualHash>")]
+pub async fn perceptual_hash(buffer: Buffer) -> Coded<PerceptualHash> {
+  Coded::wrap(async move {
+    let data = buffer.to_vec();
+    tokio::task::spawn_blocking(move || {
+      let (frame, _) = animation::first_frame(&data)?;
+      Ok(PerceptualHash {
+        dhash: format!("{:016x}", dhash(frame.clone())),
+        phash: format!("{:016x}", phash(frame)),
+      })
+    })
+    .await?
+  })
+  .await
+}
+
+struct Entry {
+  hash: u64,
+  key: String,
+  at: i64,
+}
+
+/// 按汉明距离查找相似图片的索引，只保留最近的 `capacity` 条，超出后丢掉最早的。
+///
+/// 用来让看起来一样的表情包复用已有的上传，或者发现短时间内反复刷同一张图。
+#[napi]
+pub struct PerceptualIndex {
+  capacity: usize,
+  entries: Mutex<VecDeque<Entry>>,
+}
+
+#[napi]
+impl PerceptualIndex {
+  #[napi(constructor)]
+  pub fn new(capacity: Option<u32>) -> Self {
+    Self {
+      capacity: capacity.unwrap_or(DEFAULT_CAPACITY).max(1) as usize,
+      entries: Default::default(),
+    }
+  }
+
+  /// 记下一张图。`key` 可以重复，每次出现都算一条，`count_recent` 靠这个统计刷图。
+  #[napi]
+  pub fn insert(&self, hash: String, key: String) -> anyhow::Result<()> {
+    let hash = parse(&hash)?;
+    let mut entries = self.entries.lock().unwrap();
+    if entries.len() >= self.capacity {
+      entries.pop_front();
+    }
+    entries.push_back(Entry {
+      hash,
+      key,
+      at: now_ms(),
+    });
+    Ok(())
+  }
+
+  /// 距离不超过 `max_distance` 的最相似的一条，距离相同时取最近插入的。
+  #[napi]
+  pub fn nearest(
+    &self,
+    hash: String,
+    max_distance: u32,
+  ) -> anyhow::Result<Option<PerceptualMatch>> {
+    let hash = parse(&hash)?;
+    let entries = self.entries.lock().unwrap();
+    Ok(
+      entries
+        .iter()
+        .rev()
+        .map(|x| (x, (x.hash ^ hash).count_ones()))
+        .filter(|&(_, distance)| distance <= max_distance)
+        .min_by_key(|&(_, distance)| distance)
+        .map(|(x, distance)| PerceptualMatch {
+          key: x.key.clone(),
+          distance,
+        }),
+    )
+  }
+
+  /// 最近 `window_ms` 毫秒内插入过的相似图片的条数。
+  #[napi]
+  pub fn count_recent(
+    &self,
+    hash: String,
+    max_distance: u32,
+    window_ms: u32,
+  ) -> anyhow::Result<u32> {
+    let hash = parse(&hash)?;
+    let since = now_ms() - window_ms as i64;
+    let entries = self.entries.lock().unwrap();
+    Ok(
+      entries
+        .iter()
+        .filter(|x| x.at >= since && (x.hash ^ hash).count_ones() <= max_distance)
+        .count() as u32,
+    )
+  }
+
+  #[napi(getter)]
+  pub fn size(&self) -> u32 {
+    self.entries.lock().unwrap().len() as u32
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use image::Rgba;
+
+  use super::*;
+
+  /// 8 像素一格、深浅不一的色块，整体加上 `offset`。
+  fn blocks(offset: u8) -> RgbaImage {
+    RgbaImage::from_fn(64, 64, |x, y| {
+      let v = ((x / 8 * 37 + y / 8 * 91) % 101) as u8 + offset;
+      Rgba([v, v, v, 255])
+    })
+  }
+
+  #[test]
+  fn phash_ignores_the_dc_coefficient() {
+    let hash = phash(blocks(0));
+    // 直流分量总是最大的，算进去的话最高位永远是 1。
+    assert_eq!(hash >> 63, 0);
+    // 整体调亮只改变直流分量。
+    assert_eq!(hash, phash(blocks(60)));
+  }
+
+  #[test]
+  fn finds_nearest_by_hamming_distance() {
+    let index = PerceptualIndex::new(Some(2));
+    index.insert("00000000000000ff".into(), "a".into()).unwrap();
+    index.insert("000000000000000f".into(), "b".into()).unwrap();
+    let nearest = index
+      .nearest("000000000000001f".into(), 2)
+      .unwrap()
+      .unwrap();
+    assert_eq!((nearest.key.as_str(), nearest.distance), ("b", 1));
+    assert!(
+      index
+        .nearest("ffffffffffffffff".into(), 8)
+        .unwrap()
+        .is_none()
+    );
+    // 超出容量丢掉最早的。
+    index.insert("000000000000001f".into(), "c".into()).unwrap();
+    assert_eq!(index.size(), 2);
+    assert_eq!(
+      index
+        .count_recent("00000000000000ff".into(), 0, 60_000)
+        .unwrap(),
+      0
+    );
+    assert!(index.insert("not hex".into(), "d".into()).is_err());
+  }
+}
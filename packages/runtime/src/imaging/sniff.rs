@@ -0,0 +1,108 @@
+use std::{io::Cursor, path::Path};
+
+use image::ImageReader;
+
+/// 认不出来时用的类型。
+pub const UNKNOWN_MIME: &str = "application/octet-stream";
+
+/// 判断类型需要的文件头长度。
+pub const SNIFF_LEN: usize = 16;
+
+/// 按文件头判断媒体的真实类型，不信任扩展名和 `Content-Type`。
+pub fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+  let starts = |magic: &[u8]| data.starts_with(magic);
+  let at = |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+  Some(if starts(b"\x89PNG\r\n\x1a\n") {
+    "image/png"
+  } else if starts(b"\xff\xd8\xff") {
+    "image/jpeg"
+  } else if starts(b"GIF87a") || starts(b"GIF89a") {
+    "image/gif"
+  } else if starts(b"RIFF") && at(8, b"WEBP") {
+    "image/webp"
+  } else if starts(b"BM") {
+    "image/bmp"
+  } else if starts(b"II*\0") || starts(b"MM\0*") {
+    "image/tiff"
+  } else if starts(b"\0\0\x01\0") {
+    "image/x-icon"
+  } else if at(4, b"ftyp") {
+    match data.get(8..12)? {
+      b"avif" | b"avis" => "image/avif",
+      b"heic" | b"heix" | b"mif1" | b"msf1" => "image/heic",
+      _ => "video/mp4",
+    }
+  } else if starts(b"#!SILK") || starts(b"\x02#!SILK") {
+    // QQ 语音。
+    "audio/silk"
+  } else {
+    return None;
+  })
+}
+
+/// 只解析文件头拿到图片尺寸，不是图片或者格式不支持时返回 `None`。
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+  ImageReader::new(Cursor::new(data))
+    .with_guessed_format()
+    .ok()?
+    .into_dimensions()
+    .ok()
+}
+
+pub fn file_dimensions(path: &Path) -> Option<(u32, u32)> {
+  ImageReader::open(path)
+    .ok()?
+    .with_guessed_format()
+    .ok()?
+    .into_dimensions()
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{ImageFormat, Rgba, RgbaImage};
+
+  use super::*;
+
+  fn encode(format: ImageFormat) -> Vec<u8> {
+    let mut buffer = Cursor::new(vec![]);
+    RgbaImage::from_pixel(3, 2, Rgba([1, 2, 3, 255]))
+      .write_to(&mut buffer, format)
+      .unwrap();
+    buffer.into_inner()
+  }
+
+  #[test]
+  fn sniffs_encoded_images() {
+    assert_eq!(sniff_mime(&encode(ImageFormat::Png)), Some("image/png"));
+    assert_eq!(sniff_mime(&encode(ImageFormat::Gif)), Some("image/gif"));
+    assert_eq!(sniff_mime(&encode(ImageFormat::WebP)), Some("image/webp"));
+    assert_eq!(sniff_mime(&encode(ImageFormat::Bmp)), Some("image/bmp"));
+    assert_eq!(dimensions(&encode(ImageFormat::Png)), Some((3, 2)));
+  }
+
+  #[test]
+  fn sniffs_magic_numbers() {
+    assert_eq!(
+      sniff_mime(b"\xff\xd8\xff\xe0\0\x10JFIF"),
+      Some("image/jpeg")
+    );
+    assert_eq!(sniff_mime(b"MM\0*\0\0\0\x08"), Some("image/tiff"));
+    assert_eq!(sniff_mime(b"\0\0\x01\0\x01\0"), Some("image/x-icon"));
+    assert_eq!(sniff_mime(b"\0\0\0\x1cftypavif"), Some("image/avif"));
+    assert_eq!(sniff_mime(b"\0\0\0\x18ftypheic"), Some("image/heic"));
+    assert_eq!(sniff_mime(b"\0\0\0\x18ftypisom"), Some("video/mp4"));
+    assert_eq!(sniff_mime(b"\x02#!SILK_V3"), Some("audio/silk"));
+  }
+
+  #[test]
+  fn rejects_truncated_or_unknown_headers() {
+    // `RIFF` 之后不是 `WEBP`，比如 WAV。
+    assert_eq!(sniff_mime(b"RIFF\0\0\0\0WAVEfmt "), None);
+    // `ftyp` 后面的品牌被截断了。
+    assert_eq!(sniff_mime(b"\0\0\0\x18ftyp"), None);
+    assert_eq!(sniff_mime(b"<svg"), None);
+    assert_eq!(sniff_mime(b""), None);
+    assert_eq!(dimensions(b"not an image"), None);
+  }
+}
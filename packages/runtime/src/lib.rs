@@ -1,9 +1,10 @@
 #![deny(clippy::all)]
 
+pub mod imaging;
 pub mod qqbot;
 
+use imaging::palette;
 use napi_derive::napi;
-use std::{collections::HashMap, io::Cursor};
 use time::macros::format_description;
 use tracing_subscriber::{
   EnvFilter, Layer, fmt::time::UtcTime, layer::SubscriberExt, util::SubscriberInitExt,
@@ -14,8 +15,6 @@ pub fn plus_100(input: u32) -> u32 {
   input + 100
 }
 
-use image::ImageReader;
-
 static INITIALIZE_ONCE: std::sync::Once = std::sync::Once::new();
 #[napi]
 pub fn initialize() -> bool {
@@ -37,20 +36,12 @@ pub fn initialize() -> bool {
   initialized
 }
 
+/// 头像的主色，各分量量化到 0-15。
+///
+/// 从 `calc_palette` 的结果里挑占比和彩度都高的颜色，透明像素和纯色背景不算在内。
 #[napi]
 pub fn calc_dominant_color(img: &[u8]) -> anyhow::Result<Vec<u8>> {
-  let data = ImageReader::new(Cursor::new(img))
-    .with_guessed_format()?
-    .decode()?;
-
-  let mut histogram = HashMap::new();
-  for (_, _, p) in data.into_rgb8().enumerate_pixels() {
-    let color16 = p.0.map(|x| ((x as u32 * 15) / 255) as u8);
-    *histogram.entry(color16).or_insert(0u32) += 1u32;
-  }
-
-  let mut histogram: Vec<_> = histogram.into_iter().collect();
-  histogram
-    .sort_unstable_by_key(|([r, g, b], count)| (r.max(g).max(b) - r.min(g).min(b)) as u32 * count);
-  Ok(histogram.last().copied().unwrap_or_default().0.into())
+  let dominant =
+    palette::dominant(img)?.map(|lab| lab.to_rgb().map(|x| ((x as u32 * 15) / 255) as u8));
+  Ok(dominant.unwrap_or_default().into())
 }
@@ -0,0 +1,507 @@
+use std::{
+  collections::VecDeque,
+  path::PathBuf,
+  sync::{
+    Arc, Mutex as StdMutex,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+  },
+  time::Duration,
+};
+
+use anyhow::{Context, bail};
+use async_broadcast::{InactiveReceiver, Receiver, RecvError, Sender, TrySendError};
+use napi::tokio::{
+  self,
+  sync::{Mutex, Notify},
+};
+use napi_derive::napi;
+use rusqlite::{Connection, OptionalExtension, params};
+use tracing::warn;
+
+use crate::qqbot::event::{Event, SequencedEvent};
+
+/// 新订阅从哪里开始收事件。都不填时只收订阅之后的事件；`since_seq` 优先于 `last`。
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+  /// 先补发最近的 N 条。
+  pub last: Option<u32>,
+  /// 补发序号大于它的所有事件。早于回放缓冲区的部分已经丢失，不会报错。
+  pub since_seq: Option<i64>,
+}
+
+/// 总线写满时的处理方式。
+#[napi(string_enum)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EventOverflow {
+  /// 等最慢的订阅读走一条再发，OneBot 事件的读取也会跟着停下。
+  #[default]
+  Block,
+  /// 丢掉最旧的事件，落后的订阅会先收到一条 `Lagged`。
+  DropOldest,
+  /// 写满后先存到磁盘，有空位时再按顺序放回总线。
+  SpillToDisk,
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBusConfig {
+  pub capacity: usize,
+  pub replay: usize,
+  pub overflow: EventOverflow,
+  /// `SpillToDisk` 用的 SQLite 文件，为 `None` 时放在内存里。
+  pub spill_path: Option<PathBuf>,
+}
+
+impl Default for EventBusConfig {
+  fn default() -> Self {
+    Self {
+      capacity: 1024,
+      replay: 256,
+      overflow: EventOverflow::default(),
+      spill_path: None,
+    }
+  }
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct EventBusMetrics {
+  /// 总线里还没被所有订阅读走的事件数。
+  pub depth: u32,
+  pub capacity: u32,
+  /// 溢出到磁盘、还没放回总线的事件数。
+  pub spilled: u32,
+  /// `DropOldest` 模式下因溢出丢掉的事件总数。
+  pub dropped: i64,
+  pub published: i64,
+  pub subscribers: u32,
+}
+
+/// 事件总线。每个事件发出时分配一个递增的序号，最近的若干条留在回放缓冲区里，
+/// 这样晚注册的订阅也能拿到 `Connected` 之类的早期事件。
+pub struct EventBus {
+  config: EventBusConfig,
+  tx: Sender<SequencedEvent>,
+  // 保持通道开着，没有订阅者时也能发。
+  _keepalive: InactiveReceiver<SequencedEvent>,
+  // 保证序号和广播顺序一致。
+  publish: Mutex<()>,
+  replay: StdMutex<Replay>,
+  spill: Option<Arc<Spill>>,
+  dropped: AtomicU64,
+}
+
+struct Replay {
+  next_seq: u64,
+  capacity: usize,
+  buffer: VecDeque<SequencedEvent>,
+}
+
+impl EventBus {
+  pub fn new(config: EventBusConfig) -> anyhow::Result<Self> {
+    let (mut tx, rx) = async_broadcast::broadcast(config.capacity.max(1));
+    tx.set_await_active(false);
+    tx.set_overflow(config.overflow == EventOverflow::DropOldest);
+    let spill = match config.overflow {
+      EventOverflow::SpillToDisk => Some(Arc::new(Spill::open(config.spill_path.as_ref())?)),
+      _ => None,
+    };
+    Ok(Self {
+      tx,
+      _keepalive: rx.deactivate(),
+      publish: Mutex::new(()),
+      replay: StdMutex::new(Replay {
+        next_seq: 1,
+        capacity: config.replay,
+        buffer: VecDeque::new(),
+      }),
+      spill,
+      dropped: AtomicU64::new(0),
+      config,
+    })
+  }
+
+  fn sequence(&self, event: Event) -> SequencedEvent {
+    let mut replay = self.replay.lock().unwrap();
+    let event = SequencedEvent {
+      seq: replay.next_seq,
+      event,
+    };
+    replay.next_seq += 1;
+    if replay.capacity > 0 {
+      if replay.buffer.len() >= replay.capacity {
+        replay.buffer.pop_front();
+      }
+      replay.buffer.push_back(event.clone());
+    }
+    event
+  }
+
+  /// 发出一个事件。除了 `Block` 模式外不会等订阅者；没有订阅者时事件只留在回放缓冲区里。
+  pub async fn publish(&self, event: Event) -> anyhow::Result<()> {
+    let _guard = self.publish.lock().await;
+    let event = self.sequence(event);
+    let sent = match (&self.spill, self.config.overflow) {
+      (_, EventOverflow::Block) => self.tx.broadcast_direct(event).await.map(|_| ()).is_ok(),
+      (Some(spill), EventOverflow::SpillToDisk) => {
+        // 磁盘上还有积压时，新事件也要排在后面，否则顺序会乱。
+        if spill.pending() == 0 {
+          match self.tx.try_broadcast(event) {
+            Ok(_) => true,
+            Err(TrySendError::Full(event)) => {
+              spill.push(&event)?;
+              self.ensure_draining(spill);
+              true
+            }
+            Err(_) => false,
+          }
+        } else {
+          spill.push(&event)?;
+          true
+        }
+      }
+      _ => match self.tx.try_broadcast(event) {
+        Ok(Some(_)) => {
+          self.dropped.fetch_add(1, Ordering::Relaxed);
+          true
+        }
+        Ok(None) => true,
+        Err(_) => false,
+      },
+    };
+    if !sent && self.tx.is_closed() {
+      bail!("event bus closed");
+    }
+    Ok(())
+  }
+
+  fn ensure_draining(&self, spill: &Arc<Spill>) {
+    if !spill.draining.swap(true, Ordering::SeqCst) {
+      tokio::spawn(drain_spill(spill.clone(), self.tx.clone()));
+    }
+    spill.notify.notify_one();
+  }
+
+  pub fn subscribe(&self, options: &ReplayOptions) -> Subscriber {
+    let replay = self.replay.lock().unwrap();
+    let rx = self.tx.new_receiver();
+    let backlog: VecDeque<_> = match (options.since_seq, options.last) {
+      (Some(since), _) => replay
+        .buffer
+        .iter()
+        .filter(|x| x.seq as i64 > since)
+        .cloned()
+        .collect(),
+      (None, Some(last)) => {
+        let skip = replay.buffer.len().saturating_sub(last as usize);
+        replay.buffer.iter().skip(skip).cloned().collect()
+      }
+      (None, None) => VecDeque::new(),
+    };
+    Subscriber {
+      backlog,
+      // 已经分配了序号、还在广播途中的事件也会出现在接收端，按序号去重。
+      last_seq: replay.next_seq - 1,
+      rx,
+    }
+  }
+
+  pub fn metrics(&self) -> EventBusMetrics {
+    EventBusMetrics {
+      depth: self.tx.len() as u32,
+      capacity: self.tx.capacity() as u32,
+      spilled: self.spill.as_ref().map(|x| x.pending()).unwrap_or_default() as u32,
+      dropped: self.dropped.load(Ordering::Relaxed) as i64,
+      published: (self.replay.lock().unwrap().next_seq - 1) as i64,
+      subscribers: self.tx.receiver_count() as u32,
+    }
+  }
+}
+
+impl Drop for EventBus {
+  fn drop(&mut self) {
+    // 溢出任务也持有发送端，这里显式关闭，让所有订阅结束。
+    self.tx.close();
+  }
+}
+
+/// 溢出到磁盘的事件，按序号排队。只在本次运行内有效，打开时清空。
+struct Spill {
+  conn: StdMutex<Connection>,
+  pending: AtomicU64,
+  draining: AtomicBool,
+  notify: Notify,
+}
+
+impl Spill {
+  fn open(path: Option<&PathBuf>) -> anyhow::Result<Self> {
+    let conn = match path {
+      Some(path) => Connection::open(path)
+        .with_context(|| format!("Failed to open event spill file {}", path.display()))?,
+      None => Connection::open_in_memory()?,
+    };
+    conn.execute_batch(
+      "PRAGMA journal_mode = WAL;
+      CREATE TABLE IF NOT EXISTS spill (
+        seq INTEGER PRIMARY KEY,
+        event TEXT NOT NULL
+      );
+      DELETE FROM spill;",
+    )?;
+    Ok(Self {
+      conn: StdMutex::new(conn),
+      pending: AtomicU64::new(0),
+      draining: AtomicBool::new(false),
+      notify: Notify::new(),
+    })
+  }
+
+  fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+    self.conn.lock().expect("event spill poisoned")
+  }
+
+  fn pending(&self) -> u64 {
+    self.pending.load(Ordering::SeqCst)
+  }
+
+  fn push(&self, event: &SequencedEvent) -> anyhow::Result<()> {
+    self.conn().execute(
+      "INSERT INTO spill (seq, event) VALUES (?1, ?2)",
+      params![event.seq as i64, serde_json::to_string(event)?],
+    )?;
+    self.pending.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+  }
+
+  fn head(&self) -> anyhow::Result<Option<SequencedEvent>> {
+    let event: Option<String> = self
+      .conn()
+      .query_row("SELECT event FROM spill ORDER BY seq LIMIT 1", [], |row| {
+        row.get(0)
+      })
+      .optional()?;
+    Ok(match event {
+      Some(event) => Some(serde_json::from_str(&event)?),
+      None => None,
+    })
+  }
+
+  fn remove(&self, seq: u64) -> anyhow::Result<()> {
+    self
+      .conn()
+      .execute("DELETE FROM spill WHERE seq = ?1", params![seq as i64])?;
+    self.pending.fetch_sub(1, Ordering::SeqCst);
+    Ok(())
+  }
+}
+
+async fn drain_spill(spill: Arc<Spill>, tx: Sender<SequencedEvent>) {
+  loop {
+    match spill.head() {
+      Ok(Some(event)) => {
+        let seq = event.seq;
+        if tx.broadcast_direct(event).await.is_err() && tx.is_closed() {
+          return;
+        }
+        if let Err(err) = spill.remove(seq) {
+          warn!("Failed to remove spilled event {seq}: {err:#}");
+          tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+      }
+      Ok(None) => spill.notify.notified().await,
+      Err(err) => {
+        warn!("Failed to read spilled events: {err:#}");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+      }
+    }
+  }
+}
+
+pub struct Subscriber {
+  backlog: VecDeque<SequencedEvent>,
+  last_seq: u64,
+  rx: Receiver<SequencedEvent>,
+}
+
+impl Subscriber {
+  /// 总线关闭后返回 `None`。落后太多时先返回一条 `Lagged`。
+  pub async fn recv(&mut self) -> Option<SequencedEvent> {
+    if let Some(event) = self.backlog.pop_front() {
+      return Some(event);
+    }
+    loop {
+      match self.rx.recv().await {
+        Ok(event) if event.seq > self.last_seq => {
+          self.last_seq = event.seq;
+          return Some(event);
+        }
+        Ok(_) => {}
+        Err(RecvError::Overflowed(dropped)) => {
+          return Some(SequencedEvent {
+            seq: 0,
+            event: Event::Lagged {
+              dropped: dropped.min(u32::MAX as u64) as u32,
+            },
+          });
+        }
+        Err(RecvError::Closed) => return None,
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn event(n: u64) -> Event {
+    Event::Connected {
+      name: String::new(),
+      qq: n.to_string(),
+    }
+  }
+
+  fn bus(capacity: usize, replay: usize, overflow: EventOverflow) -> EventBus {
+    EventBus::new(EventBusConfig {
+      capacity,
+      replay,
+      overflow,
+      spill_path: None,
+    })
+    .unwrap()
+  }
+
+  async fn publish(bus: &EventBus, range: std::ops::RangeInclusive<u64>) {
+    for n in range {
+      bus.publish(event(n)).await.unwrap();
+    }
+  }
+
+  /// 收 `n` 条事件的序号，等太久说明卡住了。
+  async fn seqs(rx: &mut Subscriber, n: usize) -> Vec<u64> {
+    let mut seqs = vec![];
+    for _ in 0..n {
+      let event = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("event bus stalled")
+        .expect("event bus closed");
+      seqs.push(event.seq);
+    }
+    seqs
+  }
+
+  fn replay_since(since_seq: i64) -> ReplayOptions {
+    ReplayOptions {
+      since_seq: Some(since_seq),
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn replays_from_the_start() {
+    let bus = bus(16, 16, EventOverflow::Block);
+    publish(&bus, 1..=3).await;
+    let mut rx = bus.subscribe(&replay_since(0));
+    publish(&bus, 4..=4).await;
+    assert_eq!(seqs(&mut rx, 4).await, [1, 2, 3, 4]);
+  }
+
+  #[tokio::test]
+  async fn replays_from_the_middle() {
+    let bus = bus(16, 16, EventOverflow::Block);
+    publish(&bus, 1..=5).await;
+    let mut rx = bus.subscribe(&replay_since(3));
+    publish(&bus, 6..=6).await;
+    assert_eq!(seqs(&mut rx, 3).await, [4, 5, 6]);
+
+    let mut rx = bus.subscribe(&ReplayOptions {
+      last: Some(2),
+      ..Default::default()
+    });
+    publish(&bus, 7..=7).await;
+    assert_eq!(seqs(&mut rx, 3).await, [5, 6, 7]);
+  }
+
+  #[tokio::test]
+  async fn replays_what_is_left_when_since_seq_was_evicted() {
+    let bus = bus(16, 2, EventOverflow::Block);
+    publish(&bus, 1..=5).await;
+    // 1 到 3 已经被挤出回放缓冲区，只补发还在的部分。
+    let mut rx = bus.subscribe(&replay_since(1));
+    publish(&bus, 6..=6).await;
+    assert_eq!(seqs(&mut rx, 3).await, [4, 5, 6]);
+  }
+
+  #[tokio::test]
+  async fn does_not_repeat_replayed_events() {
+    let bus = bus(1, 16, EventOverflow::SpillToDisk);
+    let mut slow = bus.subscribe(&ReplayOptions::default());
+    // 2 和 3 溢出到了磁盘，已经分配序号、进了回放缓冲区，但还没广播。
+    publish(&bus, 1..=3).await;
+    let mut rx = bus.subscribe(&replay_since(0));
+    publish(&bus, 4..=4).await;
+    let slow = tokio::spawn(async move { seqs(&mut slow, 4).await });
+    // 2 和 3 之后还会从接收端到达，按序号去重。
+    assert_eq!(seqs(&mut rx, 4).await, [1, 2, 3, 4]);
+    assert_eq!(slow.await.unwrap(), [1, 2, 3, 4]);
+  }
+
+  #[tokio::test]
+  async fn block_waits_for_the_slowest_subscriber() {
+    let bus = Arc::new(bus(1, 16, EventOverflow::Block));
+    let mut rx = bus.subscribe(&ReplayOptions::default());
+    publish(&bus, 1..=1).await;
+    let blocked = tokio::spawn({
+      let bus = bus.clone();
+      async move { publish(&bus, 2..=2).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!blocked.is_finished());
+    assert_eq!(seqs(&mut rx, 1).await, [1]);
+    blocked.await.unwrap();
+    assert_eq!(seqs(&mut rx, 1).await, [2]);
+  }
+
+  #[tokio::test]
+  async fn drop_oldest_reports_lagged() {
+    let bus = bus(2, 16, EventOverflow::DropOldest);
+    let mut rx = bus.subscribe(&ReplayOptions::default());
+    publish(&bus, 1..=5).await;
+    let metrics = bus.metrics();
+    assert_eq!(metrics.dropped, 3);
+    assert_eq!(metrics.published, 5);
+    assert_eq!(metrics.depth, 2);
+    assert_eq!(metrics.capacity, 2);
+    assert_eq!(metrics.subscribers, 1);
+
+    let lagged = rx.recv().await.unwrap();
+    assert_eq!(lagged.seq, 0);
+    assert!(matches!(lagged.event, Event::Lagged { dropped: 3 }));
+    assert_eq!(seqs(&mut rx, 2).await, [4, 5]);
+  }
+
+  #[tokio::test]
+  async fn spills_to_disk_and_drains_in_order() {
+    let bus = bus(2, 16, EventOverflow::SpillToDisk);
+    let mut rx = bus.subscribe(&ReplayOptions::default());
+    publish(&bus, 1..=5).await;
+    let metrics = bus.metrics();
+    assert_eq!(metrics.depth, 2);
+    assert_eq!(metrics.spilled, 3);
+    assert_eq!(metrics.published, 5);
+    assert_eq!(metrics.dropped, 0);
+
+    assert_eq!(seqs(&mut rx, 3).await, [1, 2, 3]);
+    // 磁盘上还有积压时，新事件排在积压之后。
+    publish(&bus, 6..=6).await;
+    assert_eq!(seqs(&mut rx, 3).await, [4, 5, 6]);
+    // 放回总线之后才从磁盘上删掉，稍等一下。
+    tokio::time::timeout(Duration::from_secs(5), async {
+      while bus.metrics().spilled > 0 {
+        tokio::task::yield_now().await;
+      }
+    })
+    .await
+    .expect("spilled events were not removed");
+  }
+}
@@ -3,8 +3,9 @@ use napi::{
   bindgen_prelude::{Buffer, FromNapiValue, ToNapiValue},
   sys,
 };
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ByteBuffer(pub Vec<u8>);
 
 use std::fmt::Debug;
@@ -1,10 +1,11 @@
-use std::sync::Arc;
+use std::{future::Future, sync::Arc, time::Duration};
 
-use anyhow::bail;
-use onebot_v11::{
-  api::{payload::ApiPayload, resp::ApiResp},
-  connect::ws::WsConnect,
-};
+use napi::tokio;
+use onebot_v11::api::{payload::ApiPayload, resp::ApiResp};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::qqbot::{connection::Connection, error::BridgeError};
 
 pub trait ClientRaw {
   fn call_api_raw(
@@ -13,23 +14,123 @@ pub trait ClientRaw {
   ) -> impl std::future::Future<Output = anyhow::Result<ApiResp>>;
 }
 
-impl ClientRaw for WsConnect {
+impl ClientRaw for Connection {
   async fn call_api_raw(self: Arc<Self>, api_data: ApiPayload) -> anyhow::Result<ApiResp> {
     self.call_api(api_data).await
   }
 }
 
-pub struct ClientProxy<C>(pub Arc<C>);
+/// 检查 `retcode`，失败时保留 retcode 和 wording。
+///
+/// 各家实现的 `ApiResp` 附加字段并不统一（`wording` / `message` 只有部分实现会给），
+/// 所以这里按 JSON 读取而不是依赖具体的结构体字段。
+fn check_api_resp(action: &'static str, resp: &ApiResp) -> Result<(), BridgeError> {
+  let raw = serde_json::to_value(resp).map_err(|e| BridgeError::Internal(e.into()))?;
+  let retcode = raw
+    .get("retcode")
+    .and_then(|x| x.as_i64())
+    .unwrap_or_default();
+  let status = raw
+    .get("status")
+    .and_then(|x| x.as_str())
+    .unwrap_or("ok")
+    .to_owned();
+  // retcode 1 表示已提交异步处理，也算成功。
+  if (retcode == 0 || retcode == 1) && status != "failed" {
+    return Ok(());
+  }
+  let wording = ["wording", "message", "msg"]
+    .into_iter()
+    .find_map(|key| raw.get(key).and_then(|x| x.as_str()))
+    .filter(|x| !x.is_empty())
+    .map(ToOwned::to_owned);
+  Err(BridgeError::Api {
+    action,
+    retcode,
+    status,
+    wording,
+  })
+}
+
+/// 各类 action 的默认超时。发消息要上传图片，通常比查询慢得多。
+#[derive(Debug, Clone, Copy)]
+pub struct ApiTimeouts {
+  pub send: Duration,
+  pub query: Duration,
+}
+
+impl Default for ApiTimeouts {
+  fn default() -> Self {
+    Self {
+      send: Duration::from_secs(60),
+      query: Duration::from_secs(15),
+    }
+  }
+}
+
+impl ApiTimeouts {
+  fn for_action(&self, action: &str) -> Duration {
+    const SEND_PREFIXES: [&str; 6] = ["send_", "forward_", "delete_", "del_", "set_", "mark_"];
+    if SEND_PREFIXES
+      .iter()
+      .any(|prefix| action.starts_with(prefix))
+    {
+      self.send
+    } else {
+      self.query
+    }
+  }
+}
+
+/// 单次调用的超时和取消设置。
+#[derive(Debug, Clone, Default)]
+pub struct CallPolicy {
+  pub timeouts: ApiTimeouts,
+  /// 覆盖按 action 类别选出的默认超时。
+  pub timeout: Option<Duration>,
+  pub cancel: Option<CancellationToken>,
+}
+
+impl CallPolicy {
+  pub async fn run<T>(
+    &self,
+    action: &'static str,
+    fut: impl Future<Output = anyhow::Result<T>>,
+  ) -> anyhow::Result<T> {
+    let after = self
+      .timeout
+      .unwrap_or_else(|| self.timeouts.for_action(action));
+    let cancelled = async {
+      match &self.cancel {
+        Some(token) => token.cancelled().await,
+        None => std::future::pending().await,
+      }
+    };
+    tokio::select! {
+      biased;
+      _ = cancelled => Err(BridgeError::Cancelled { action }.into()),
+      result = tokio::time::timeout(after, fut) => match result {
+        Ok(result) => result,
+        Err(_) => Err(BridgeError::Timeout { action, after }.into()),
+      },
+    }
+  }
+}
+
+pub struct ClientProxy<C>(pub Arc<C>, pub CallPolicy);
 
 impl<C: ClientRaw> ClientProxy<C> {
   pub fn new(client: Arc<C>) -> Self {
-    Self(client)
+    Self(client, CallPolicy::default())
+  }
+  pub fn with_policy(self, policy: CallPolicy) -> Self {
+    Self(self.0, policy)
   }
 }
 
 impl<C> Clone for ClientProxy<C> {
   fn clone(&self) -> Self {
-    Self(self.0.clone())
+    Self(self.0.clone(), self.1.clone())
   }
 }
 
@@ -49,17 +150,43 @@ macro_rules! impl_api {
     ) -> anyhow::Result<$resp_type> {
       use onebot_v11::api::payload::ApiPayload::$api_name as Ctor;
       use onebot_v11::api::resp::ApiRespData::$resp_variant as Dtor;
+      const ACTION: &str = stringify!($fn_name);
 
-      let client = self.0;
+      let Self(client, policy) = self;
       // 调用底层方法，并传入构造好的 Payload
-      let resp = client.call_api_raw(Ctor(arg)).await?;
+      let call = async {
+        client
+          .call_api_raw(Ctor(arg))
+          .await
+          .map_err(|source| match source.downcast::<BridgeError>() {
+            // 请求还没发出去，调用方可以放心重发。
+            Ok(BridgeError::NotConnected) => BridgeError::NotConnected,
+            Ok(err) => BridgeError::Transport {
+              action: ACTION,
+              source: err.into(),
+            },
+            Err(source) => BridgeError::Transport {
+              action: ACTION,
+              source,
+            },
+          })
+          .map_err(anyhow::Error::from)
+      };
+      let resp = policy.run(ACTION, call).await?;
+      check_api_resp(ACTION, &resp)?;
 
       // 使用 let-else 优雅地解构响应，如果类型不匹配则返回错误
       let Dtor(resp) = resp.data else {
-        bail!(
-          "unexpected response type! expected {}, got {:?}",
-          stringify!(onebot_v11::api::resp::ApiRespData::$resp_variant),
-          resp.data
+        return Err(
+          BridgeError::UnexpectedResponse {
+            action: ACTION,
+            data: format!(
+              "expected {}, got {:?}",
+              stringify!(onebot_v11::api::resp::ApiRespData::$resp_variant),
+              resp.data
+            ),
+          }
+          .into(),
         );
       };
 
@@ -0,0 +1,139 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  hash::Hash,
+  sync::Arc,
+};
+
+use napi::tokio::sync::broadcast;
+use napi_derive::napi;
+use onebot_v11::api::{payload::ApiPayload, resp::ApiResp};
+use serde_json::{Value, json};
+
+use super::{
+  mirai::MiraiWs,
+  satori::SatoriWs,
+  ws::{ConnectOptions, OneBotWs},
+};
+
+#[napi(string_enum)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+  /// OneBot v11 正向 WebSocket。
+  #[default]
+  OneBot,
+  /// mirai-api-http 2.x 的 WebSocket adapter。`accessToken` 填 verifyKey，还要填 `qq`。
+  Mirai,
+  /// Satori 协议，地址填服务根路径（如 `ws://127.0.0.1:5140/satori`），`accessToken` 填 token，
+  /// `qq` 选择用哪个登录，不填用第一个。
+  Satori,
+}
+
+/// 到 QQ 的连接。不管底下是哪种协议，对上层都表现成 OneBot。
+pub enum Connection {
+  OneBot(OneBotWs),
+  Mirai(MiraiWs),
+  Satori(SatoriWs),
+}
+
+impl Connection {
+  pub async fn connect(
+    transport: Transport,
+    options: ConnectOptions<'_>,
+  ) -> anyhow::Result<Arc<Self>> {
+    let connection = match transport {
+      Transport::OneBot => Self::OneBot(OneBotWs::connect(options).await?),
+      Transport::Mirai => Self::Mirai(MiraiWs::connect(options).await?),
+      Transport::Satori => Self::Satori(SatoriWs::connect(options).await?),
+    };
+    Ok(Arc::new(connection))
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<onebot_v11::Event> {
+    match self {
+      Self::OneBot(ws) => ws.subscribe(),
+      Self::Mirai(ws) => ws.subscribe(),
+      Self::Satori(ws) => ws.subscribe(),
+    }
+  }
+
+  pub async fn call_api(&self, payload: ApiPayload) -> anyhow::Result<ApiResp> {
+    match self {
+      Self::OneBot(ws) => ws.call_api(payload).await,
+      Self::Mirai(ws) => ws.call_api(payload).await,
+      Self::Satori(ws) => ws.call_api(payload).await,
+    }
+  }
+}
+
+/// 对端拒绝了请求（mirai 的 `code`、Satori 的 HTTP 状态码），转成 OneBot 的失败响应。
+#[derive(Debug)]
+pub(crate) struct Rejected {
+  pub code: i64,
+  pub msg: String,
+}
+
+impl std::fmt::Display for Rejected {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "rejected with {}: {}", self.code, self.msg)
+  }
+}
+
+impl std::error::Error for Rejected {}
+
+pub(crate) fn unsupported(action: &str) -> anyhow::Error {
+  Rejected {
+    code: 1404,
+    msg: format!("{action} is not supported by this transport"),
+  }
+  .into()
+}
+
+/// 把翻译后的结果包装成 OneBot 响应。`Rejected` 变成 `status: failed`，其余错误照常返回。
+pub(crate) fn respond(result: anyhow::Result<Value>) -> anyhow::Result<ApiResp> {
+  let resp = match result {
+    Ok(data) => json!({ "status": "ok", "retcode": 0, "data": data }),
+    Err(err) => match err.downcast::<Rejected>() {
+      Ok(rejected) => json!({
+        "status": "failed",
+        "retcode": rejected.code,
+        "data": null,
+        "message": rejected.msg,
+      }),
+      Err(err) => return Err(err),
+    },
+  };
+  Ok(serde_json::from_value(resp)?)
+}
+
+/// 记住最近多少条消息 id、图片地址之类的映射。
+const RECENT_CAPACITY: usize = 4096;
+
+/// 只保留最近若干条的映射。
+pub(crate) struct Recent<K, V> {
+  map: HashMap<K, V>,
+  order: VecDeque<K>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Recent<K, V> {
+  pub fn new() -> Self {
+    Self {
+      map: HashMap::new(),
+      order: VecDeque::new(),
+    }
+  }
+
+  pub fn insert(&mut self, key: K, value: V) {
+    if self.map.insert(key.clone(), value).is_none() {
+      self.order.push_back(key);
+      if self.order.len() > RECENT_CAPACITY
+        && let Some(oldest) = self.order.pop_front()
+      {
+        self.map.remove(&oldest);
+      }
+    }
+  }
+
+  pub fn get(&self, key: &K) -> Option<V> {
+    self.map.get(key).cloned()
+  }
+}
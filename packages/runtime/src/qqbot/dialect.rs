@@ -0,0 +1,141 @@
+use napi_derive::napi;
+use reqwest::Url;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use super::client_proxy::{ClientProxy, ClientRaw};
+
+/// 连上的 OneBot 实现，按 `get_version_info` 的 `app_name` 识别。
+#[napi(string_enum)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Implementation {
+  NapCat,
+  LLOneBot,
+  Lagrange,
+  GoCqhttp,
+  /// 经 mirai-api-http 转换。
+  Mirai,
+  /// 经 Satori 转换。
+  Satori,
+  /// 没认出来，或者不支持 `get_version_info`，按 NapCat 的行为处理。
+  #[default]
+  Unknown,
+}
+
+impl Implementation {
+  fn from_app_name(app_name: &str) -> Self {
+    let name = app_name.to_ascii_lowercase();
+    let matches = |keys: &[&str]| keys.iter().any(|key| name.contains(key));
+    if matches(&["napcat"]) {
+      Self::NapCat
+    } else if matches(&["llonebot", "llbot"]) {
+      Self::LLOneBot
+    } else if matches(&["lagrange"]) {
+      Self::Lagrange
+    } else if matches(&["go-cqhttp", "gocqhttp"]) {
+      Self::GoCqhttp
+    } else if matches(&["mirai"]) {
+      Self::Mirai
+    } else if matches(&["satori"]) {
+      Self::Satori
+    } else {
+      Self::Unknown
+    }
+  }
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct DialectInfo {
+  pub implementation: Implementation,
+  pub app_name: String,
+  pub app_version: String,
+  pub protocol_version: String,
+}
+
+/// `get_image` 返回后去哪里下载图片。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageSource {
+  /// `file` 是实现所在机器上的路径，拼到 `downloadImage.baseurl` 上，由旁边的 HTTP 服务提供。
+  BaseUrl,
+  /// 直接用返回的 `url`，没有时退回 `BaseUrl`。
+  Url,
+}
+
+/// 各家实现的差异。转换消息、下载图片时查这里，而不是假定对面是 NapCat。
+#[derive(Debug, Clone)]
+pub struct Dialect {
+  pub info: DialectInfo,
+  pub image_source: ImageSource,
+  /// 合并转发消息段是否直接带着内容，不带的要再调 `get_forward_msg`。
+  pub inline_forward: bool,
+}
+
+impl Default for Dialect {
+  fn default() -> Self {
+    Self::new(DialectInfo::default())
+  }
+}
+
+impl Dialect {
+  pub fn new(info: DialectInfo) -> Self {
+    use Implementation::*;
+    let image_source = match info.implementation {
+      NapCat | Unknown => ImageSource::BaseUrl,
+      LLOneBot | Lagrange | GoCqhttp | Mirai | Satori => ImageSource::Url,
+    };
+    let inline_forward = !matches!(info.implementation, Lagrange | GoCqhttp);
+    Self {
+      info,
+      image_source,
+      inline_forward,
+    }
+  }
+
+  /// 调 `get_version_info` 识别实现。失败时不影响连接，按 `Unknown` 处理。
+  pub async fn detect<C: ClientRaw>(client: ClientProxy<C>) -> Self {
+    let resp = match client
+      .get_version_info(onebot_v11::api::payload::GetVersionInfo {})
+      .await
+    {
+      Ok(resp) => resp,
+      Err(err) => {
+        warn!("Failed to get version info, assuming NapCat behaviour: {err:#}");
+        return Self::default();
+      }
+    };
+    // 各家在这里附加的字段不同，按 JSON 读取。
+    let resp = serde_json::to_value(&resp).unwrap_or_default();
+    let field = |key: &str| match &resp[key] {
+      Value::String(x) => x.clone(),
+      Value::Null => String::new(),
+      other => other.to_string(),
+    };
+    let app_name = field("app_name");
+    let info = DialectInfo {
+      implementation: Implementation::from_app_name(&app_name),
+      app_version: field("app_version"),
+      protocol_version: field("protocol_version"),
+      app_name,
+    };
+    info!(
+      "OneBot implementation: {:?} {} ({})",
+      info.implementation, info.app_version, info.app_name
+    );
+    Self::new(info)
+  }
+
+  /// 根据 `get_image` 的响应算出下载地址。
+  pub fn image_url(&self, baseurl: &Url, image: &Value) -> anyhow::Result<Url> {
+    let url = image["url"]
+      .as_str()
+      .filter(|x| x.starts_with("http://") || x.starts_with("https://"));
+    if self.image_source == ImageSource::Url
+      && let Some(url) = url
+    {
+      return Ok(Url::parse(url)?);
+    }
+    let file = image["file"].as_str().unwrap_or_default();
+    Ok(baseurl.join(file)?)
+  }
+}
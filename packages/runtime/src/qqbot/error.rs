@@ -0,0 +1,252 @@
+use std::{ffi::CStr, fmt::Display, future::Future, time::Duration};
+
+use napi::{
+  bindgen_prelude::{FromNapiValue, ToNapiValue, Unknown},
+  check_status, sys,
+};
+
+/// 桥接层的错误。`code()` 是给 JS 侧判断用的稳定字符串，不随错误信息的措辞变化。
+#[derive(Debug)]
+pub enum BridgeError {
+  /// OneBot 连接还没建立，或者已经断开。
+  NotConnected,
+  /// JS 传进来的参数不合法，比如 QQ 号不是数字。
+  InvalidArgument(String),
+  /// OneBot 实现返回了非零 retcode。
+  Api {
+    action: &'static str,
+    retcode: i64,
+    status: String,
+    wording: Option<String>,
+  },
+  /// 连接池里没有哪个账号在这个群里。
+  NotMember {
+    group_id: i64,
+  },
+  /// 返回的数据和请求的 action 对不上。
+  UnexpectedResponse {
+    action: &'static str,
+    data: String,
+  },
+  /// 超过了调用的截止时间还没有收到回应。
+  Timeout {
+    action: &'static str,
+    after: Duration,
+  },
+  /// JS 侧通过 `AbortSignal` 取消了调用。
+  Cancelled {
+    action: &'static str,
+  },
+  /// 下载的媒体超过了大小上限。
+  TooLarge {
+    limit: u64,
+  },
+  /// WebSocket 读写失败。
+  Transport {
+    action: &'static str,
+    source: anyhow::Error,
+  },
+  Internal(anyhow::Error),
+}
+
+impl BridgeError {
+  pub fn code(&self) -> &'static str {
+    match self {
+      BridgeError::NotConnected => "NOT_CONNECTED",
+      BridgeError::InvalidArgument(_) => "INVALID_ARGUMENT",
+      BridgeError::NotMember { .. } => "NOT_MEMBER",
+      BridgeError::Api {
+        retcode, wording, ..
+      } => api_error_code(*retcode, wording.as_deref().unwrap_or_default()),
+      BridgeError::UnexpectedResponse { .. } => "UNEXPECTED_RESPONSE",
+      BridgeError::Timeout { .. } => "TIMEOUT",
+      BridgeError::Cancelled { .. } => "CANCELLED",
+      BridgeError::TooLarge { .. } => "TOO_LARGE",
+      BridgeError::Transport { .. } => "TRANSPORT",
+      BridgeError::Internal(_) => "INTERNAL",
+    }
+  }
+
+  pub fn action(&self) -> Option<&'static str> {
+    match self {
+      BridgeError::Api { action, .. }
+      | BridgeError::UnexpectedResponse { action, .. }
+      | BridgeError::Timeout { action, .. }
+      | BridgeError::Cancelled { action }
+      | BridgeError::Transport { action, .. } => Some(action),
+      _ => None,
+    }
+  }
+
+  /// 换个时间重发是否可能成功。参数错误、消息过长这类错误重试也没用；说不清原因的 `API_FAILED`
+  /// 也不重试，消息可能已经发出去了。
+  pub fn is_retryable(&self) -> bool {
+    matches!(
+      self.code(),
+      "NOT_CONNECTED" | "TRANSPORT" | "TIMEOUT" | "API_TIMEOUT"
+    )
+  }
+
+  unsafe fn to_js_error(&self, env: sys::napi_env) -> napi::Result<sys::napi_value> {
+    unsafe {
+      let code = String::to_napi_value(env, self.code().to_owned())?;
+      let message = String::to_napi_value(env, self.to_string())?;
+      let mut error = std::ptr::null_mut();
+      check_status!(
+        sys::napi_create_error(env, code, message, &mut error),
+        "Failed to create error object"
+      )?;
+      let set = |name: &CStr, value: sys::napi_value| {
+        check_status!(
+          sys::napi_set_named_property(env, error, name.as_ptr(), value),
+          "Failed to set error property"
+        )
+      };
+      if let Some(action) = self.action() {
+        set(c"action", String::to_napi_value(env, action.to_owned())?)?;
+      }
+      if let BridgeError::Api {
+        retcode, wording, ..
+      } = self
+      {
+        set(c"retcode", i64::to_napi_value(env, *retcode)?)?;
+        if let Some(wording) = wording {
+          set(c"wording", String::to_napi_value(env, wording.clone())?)?;
+        }
+      }
+      Ok(error)
+    }
+  }
+}
+
+/// 先看 retcode：mirai-api-http 的 `code` 和 Satori 的 HTTP 状态码会原样放进 retcode（见
+/// `connection::Rejected`）。OneBot 实现大多只给 100、1400 这类笼统的 retcode，这时再看 wording。
+fn api_error_code(retcode: i64, wording: &str) -> &'static str {
+  match retcode {
+    // mirai-api-http：Bot 被禁言、消息过长。
+    20 => return "BOT_MUTED",
+    30 => return "MESSAGE_TOO_LONG",
+    // Satori：408 Request Timeout、504 Gateway Timeout。
+    408 | 504 => return "API_TIMEOUT",
+    _ => {}
+  }
+  let wording = wording.to_lowercase();
+  if wording.contains("禁言") || wording.contains("muted") {
+    "BOT_MUTED"
+  } else if wording.contains("过长") || wording.contains("too long") {
+    "MESSAGE_TOO_LONG"
+  } else if wording.contains("超时") || wording.contains("timeout") {
+    // 和本地的 `TIMEOUT` 区分开：这是对端报告的超时，请求可能已经生效。
+    "API_TIMEOUT"
+  } else {
+    match retcode {
+      1404 => "ACTION_NOT_FOUND",
+      100 | 1400 => "BAD_REQUEST",
+      _ => "API_FAILED",
+    }
+  }
+}
+
+impl Display for BridgeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      BridgeError::NotConnected => write!(f, "Client not initialized"),
+      BridgeError::InvalidArgument(reason) => write!(f, "invalid argument: {reason}"),
+      BridgeError::Api {
+        action,
+        retcode,
+        status,
+        wording,
+      } => {
+        write!(f, "{action} failed: status={status}, retcode={retcode}")?;
+        if let Some(wording) = wording {
+          write!(f, ", {wording}")?;
+        }
+        Ok(())
+      }
+      BridgeError::NotMember { group_id } => {
+        write!(f, "no account is a member of group {group_id}")
+      }
+      BridgeError::UnexpectedResponse { action, data } => {
+        write!(f, "unexpected response type for {action}: {data}")
+      }
+      BridgeError::Timeout { action, after } => {
+        write!(f, "{action} timed out after {}ms", after.as_millis())
+      }
+      BridgeError::Cancelled { action } => write!(f, "{action} was cancelled"),
+      BridgeError::TooLarge { limit } => write!(f, "media is larger than {limit} bytes"),
+      BridgeError::Transport { action, source } => write!(f, "{action} failed: {source:#}"),
+      BridgeError::Internal(err) => write!(f, "{err:#}"),
+    }
+  }
+}
+
+impl std::error::Error for BridgeError {}
+
+impl From<anyhow::Error> for BridgeError {
+  fn from(err: anyhow::Error) -> Self {
+    match err.downcast::<BridgeError>() {
+      Ok(err) => err,
+      Err(err) => BridgeError::Internal(err),
+    }
+  }
+}
+
+/// napi 异步方法的返回值。
+///
+/// napi 自带的错误转换只能带上固定的 `Status`，所以这里把错误留到 JS 线程上再构造成带 `code`
+/// 属性的 `Error` 对象，然后 reject 出去。
+pub struct Coded<T>(pub Result<T, BridgeError>);
+
+impl<T> Coded<T> {
+  pub async fn wrap(fut: impl Future<Output = anyhow::Result<T>>) -> Self {
+    Coded(fut.await.map_err(BridgeError::from))
+  }
+}
+
+impl<T: ToNapiValue> ToNapiValue for Coded<T> {
+  unsafe fn to_napi_value(env: sys::napi_env, val: Self) -> napi::Result<sys::napi_value> {
+    match val.0 {
+      Ok(val) => unsafe { T::to_napi_value(env, val) },
+      Err(err) => unsafe {
+        let error = err.to_js_error(env)?;
+        Err(napi::Error::from(Unknown::from_napi_value(env, error)?))
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn api(retcode: i64, wording: &str) -> BridgeError {
+    BridgeError::Api {
+      action: "send_group_msg",
+      retcode,
+      status: "failed".to_owned(),
+      wording: Some(wording.to_owned()),
+    }
+  }
+
+  #[test]
+  fn maps_retcodes_before_wording() {
+    assert_eq!(api(20, "").code(), "BOT_MUTED");
+    assert_eq!(api(30, "").code(), "MESSAGE_TOO_LONG");
+    assert_eq!(api(504, "bad gateway").code(), "API_TIMEOUT");
+    // retcode 说不清时才看 wording。
+    assert_eq!(api(100, "账号被禁言").code(), "BOT_MUTED");
+    assert_eq!(api(1200, "消息过长").code(), "MESSAGE_TOO_LONG");
+    assert_eq!(api(100, "").code(), "BAD_REQUEST");
+    assert_eq!(api(1404, "").code(), "ACTION_NOT_FOUND");
+    assert_eq!(api(200, "unknown").code(), "API_FAILED");
+  }
+
+  #[test]
+  fn retries_only_transient_errors() {
+    assert!(BridgeError::NotConnected.is_retryable());
+    assert!(api(504, "").is_retryable());
+    assert!(!api(200, "unknown").is_retryable());
+    assert!(!api(20, "").is_retryable());
+  }
+}
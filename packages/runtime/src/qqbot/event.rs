@@ -1,9 +1,11 @@
+use napi::{bindgen_prelude::ToNapiValue, check_status, sys};
 use napi_derive::napi;
+use serde::{Deserialize, Serialize};
 
 use crate::qqbot::export::{GroupMemberInfo, Mockv2MessageChain};
 
 #[napi]
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Event {
   Connected {
     name: String,
@@ -20,5 +22,186 @@ pub enum Event {
     self_id: String,
     message_id: String,
   },
-  Closed,
+  /// 有人入群，来自 OneBot 的 `group_increase`。
+  GroupMemberJoined {
+    self_id: String,
+    group_id: String,
+    user_id: String,
+  },
+  /// 有人退群或被移出，来自 `group_decrease`。
+  GroupMemberLeft {
+    self_id: String,
+    group_id: String,
+    user_id: String,
+  },
+  /// 群名片变化，来自 `group_card`。
+  GroupMemberUpdated {
+    self_id: String,
+    group_id: String,
+    user_id: String,
+    card: Option<String>,
+  },
+  OutboundDelivered {
+    local_id: String,
+    message_id: String,
+  },
+  OutboundFailed {
+    local_id: String,
+    /// 与 `BridgeError` 的 `code` 一致，过期时为 `EXPIRED`。
+    code: String,
+    error: String,
+  },
+  /// QQ 账号上线，来自 lifecycle 的 `enable`/`connect`。
+  BotOnline {
+    self_id: String,
+  },
+  /// QQ 账号掉线。WebSocket 可能还连着，但消息收发都会失败。
+  BotOffline {
+    self_id: String,
+    reason: String,
+  },
+  /// 订阅读得太慢，总线溢出丢掉了 `dropped` 条事件。只发给落后的那个订阅，不占序号。
+  ///
+  /// 读 OneBot 连接跟不上、连接那边丢了事件时也会发一条，这时发给所有订阅，占序号。
+  Lagged {
+    dropped: u32,
+  },
+  /// 每次运行结束（`terminate` 或断线）时发出，之后可以再次 `start`。
+  Closed {
+    /// 由 JS 调用 `terminate` 引起；断线、自动重连时为 `false`。
+    terminated: bool,
+  },
+}
+
+impl Event {
+  /// 与 JS 侧 `type` 字段一致。
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Event::Connected { .. } => "Connected",
+      Event::GroupMessage { .. } => "GroupMessage",
+      Event::GroupMessageDeleted { .. } => "GroupMessageDeleted",
+      Event::GroupMemberJoined { .. } => "GroupMemberJoined",
+      Event::GroupMemberLeft { .. } => "GroupMemberLeft",
+      Event::GroupMemberUpdated { .. } => "GroupMemberUpdated",
+      Event::OutboundDelivered { .. } => "OutboundDelivered",
+      Event::OutboundFailed { .. } => "OutboundFailed",
+      Event::BotOnline { .. } => "BotOnline",
+      Event::BotOffline { .. } => "BotOffline",
+      Event::Lagged { .. } => "Lagged",
+      Event::Closed { .. } => "Closed",
+    }
+  }
+
+  pub fn group_id(&self) -> Option<&str> {
+    match self {
+      Event::GroupMessage { group_id, .. }
+      | Event::GroupMessageDeleted { group_id, .. }
+      | Event::GroupMemberJoined { group_id, .. }
+      | Event::GroupMemberLeft { group_id, .. }
+      | Event::GroupMemberUpdated { group_id, .. } => Some(group_id),
+      _ => None,
+    }
+  }
+
+  pub fn self_id(&self) -> Option<&str> {
+    match self {
+      Event::Connected { qq, .. } => Some(qq),
+      Event::BotOnline { self_id } | Event::BotOffline { self_id, .. } => Some(self_id),
+      Event::GroupMessage { self_id, .. }
+      | Event::GroupMessageDeleted { self_id, .. }
+      | Event::GroupMemberJoined { self_id, .. }
+      | Event::GroupMemberLeft { self_id, .. }
+      | Event::GroupMemberUpdated { self_id, .. } => Some(self_id),
+      _ => None,
+    }
+  }
+}
+
+fn json_id(value: &serde_json::Value) -> String {
+  match value {
+    serde_json::Value::String(id) => id.clone(),
+    value => value.to_string(),
+  }
+}
+
+impl Event {
+  /// OneBot 的 lifecycle 元事件：`enable`/`connect` 视为上线，`disable` 视为下线。
+  pub fn from_lifecycle(lifecycle: &serde_json::Value) -> Option<Event> {
+    let self_id = json_id(&lifecycle["self_id"]);
+    match lifecycle["sub_type"].as_str()? {
+      "enable" | "connect" => Some(Event::BotOnline { self_id }),
+      "disable" => Some(Event::BotOffline {
+        self_id,
+        reason: "disable".to_owned(),
+      }),
+      _ => None,
+    }
+  }
+
+  /// NapCat 扩展的 `bot_offline` 通知，`message` 里是掉线原因。
+  pub fn from_bot_offline_notice(notice: &serde_json::Value) -> Option<Event> {
+    if notice["notice_type"].as_str()? != "bot_offline" {
+      return None;
+    }
+    let reason = notice["message"]
+      .as_str()
+      .or(notice["tag"].as_str())
+      .unwrap_or("bot_offline");
+    Some(Event::BotOffline {
+      self_id: json_id(&notice["self_id"]),
+      reason: reason.to_owned(),
+    })
+  }
+
+  /// 群成员变动的通知：`group_increase`、`group_decrease`、`group_card`。
+  pub fn from_member_notice(notice: &serde_json::Value) -> Option<Event> {
+    let self_id = json_id(&notice["self_id"]);
+    let group_id = json_id(&notice["group_id"]);
+    let user_id = json_id(&notice["user_id"]);
+    match notice["notice_type"].as_str()? {
+      "group_increase" => Some(Event::GroupMemberJoined {
+        self_id,
+        group_id,
+        user_id,
+      }),
+      "group_decrease" => Some(Event::GroupMemberLeft {
+        self_id,
+        group_id,
+        user_id,
+      }),
+      "group_card" => Some(Event::GroupMemberUpdated {
+        self_id,
+        group_id,
+        user_id,
+        card: notice["card_new"]
+          .as_str()
+          .filter(|x| !x.is_empty())
+          .map(ToOwned::to_owned),
+      }),
+      _ => None,
+    }
+  }
+}
+
+/// 带序号的事件，交给 JS 时序号作为 `seq` 属性挂在事件对象上。
+///
+/// 序号从 1 开始；订阅落后时收到的 `Lagged` 不占序号，`seq` 为 0。
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SequencedEvent {
+  pub seq: u64,
+  pub event: Event,
+}
+
+impl ToNapiValue for SequencedEvent {
+  unsafe fn to_napi_value(env: sys::napi_env, val: Self) -> napi::Result<sys::napi_value> {
+    unsafe {
+      let object = Event::to_napi_value(env, val.event)?;
+      let seq = i64::to_napi_value(env, val.seq as i64)?;
+      check_status!(
+        sys::napi_set_named_property(env, object, c"seq".as_ptr(), seq),
+        "Failed to set event sequence"
+      )?;
+      Ok(object)
+    }
+  }
 }
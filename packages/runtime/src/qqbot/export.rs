@@ -1,27 +1,36 @@
 use std::path::Path;
-use std::{any, fmt::format, sync::Arc};
+use std::{collections::HashMap, sync::Arc};
 
-use crate::qqbot::{bytes::ByteBuffer, client_proxy::ClientProxy, event};
+use crate::qqbot::bus::{self, EventBusMetrics, EventOverflow, ReplayOptions};
+use crate::qqbot::client_proxy::{ApiTimeouts, CallPolicy};
+use crate::qqbot::connection::{Connection, Transport};
+use crate::qqbot::dialect::{Dialect, DialectInfo};
+use crate::qqbot::error::{BridgeError, Coded};
+use crate::qqbot::event::SequencedEvent;
+use crate::qqbot::health::{self, Health};
+use crate::qqbot::lifecycle::EndpointState;
+use crate::qqbot::media::{self, Fetched, Media, MediaConfig, MediaStrategy};
+use crate::qqbot::media_cache;
+use crate::qqbot::outbox;
+use crate::qqbot::scheduler::{RateLimit, SchedulerConfig, SendPriority};
+use crate::qqbot::signal::CancelSignal;
+use crate::qqbot::subscription::{EventFilter, EventStream, SubscribeOptions, Subscription};
+use crate::qqbot::ws;
+use crate::qqbot::{bytes::ByteBuffer, client_proxy::ClientProxy};
 use anyhow::{Context, bail};
-use futures_util::TryStreamExt;
-use futures_util::future::{join_all, try_join_all};
+use futures_util::future::try_join_all;
 use itertools::Itertools;
-use napi::{bindgen_prelude::FromNapiValue, threadsafe_function::ThreadsafeFunction};
+use napi::threadsafe_function::ThreadsafeFunction;
 use napi::{bindgen_prelude::*, tokio};
 use napi_derive::napi;
-use onebot_v11::api::payload::GetImage;
 use onebot_v11::message::segment::{CustomNodeData, NodeData};
 use onebot_v11::{
   MessageSegment,
   api::payload::{DeleteMsg, GetForwardMsg, GetFriendList, GetGroupMemberInfo, SendMsg},
-  connect::ws::WsConnect,
   event::message::Message,
-  message::segment::ReplyData,
 };
-use reqwest::Url;
-use secrecy::ExposeSecret;
-use tokio_util::io::StreamReader;
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
 use super::QQBotEndpoint as Inner;
 
@@ -30,13 +39,224 @@ use super::QQBotEndpoint as Inner;
 pub struct DownloadImageEndpoint {
   pub baseurl: String,
   pub authorization_header: String,
+  /// 取图片的途径和顺序，默认 `["Sidecar", "SegmentUrl"]`。
+  pub strategies: Option<Vec<MediaStrategy>>,
+  /// `LocalPath` 允许读取的目录。
+  pub local_root: Option<String>,
+  /// 单个文件的大小上限，默认 20 MiB。
+  pub max_size_mb: Option<u32>,
 }
+/// 令牌桶：每 `interval_ms` 毫秒补充一条，最多连发 `burst` 条。
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+  pub burst: u32,
+  pub interval_ms: u32,
+}
+
+impl From<RateLimitConfig> for RateLimit {
+  fn from(value: RateLimitConfig) -> Self {
+    RateLimit {
+      burst: value.burst,
+      interval: std::time::Duration::from_millis(value.interval_ms as u64),
+    }
+  }
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct SendRateLimitConfig {
+  pub per_group: Option<RateLimitConfig>,
+  pub global: Option<RateLimitConfig>,
+  pub max_in_flight: Option<u32>,
+}
+
+impl From<SendRateLimitConfig> for SchedulerConfig {
+  fn from(value: SendRateLimitConfig) -> Self {
+    let default = SchedulerConfig::default();
+    SchedulerConfig {
+      per_group: value.per_group.map_or(default.per_group, Into::into),
+      global: value.global.map_or(default.global, Into::into),
+      max_in_flight: value
+        .max_in_flight
+        .map_or(default.max_in_flight, |x| x.max(1) as usize),
+    }
+  }
+}
+
+/// 出站消息日志。
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+  /// SQLite 文件，默认是当前目录下的 `outbox.sqlite3`；`:memory:` 表示只保存在内存里。
+  pub path: Option<String>,
+  pub max_attempts: Option<u32>,
+  pub ttl_ms: Option<u32>,
+  pub retry_base_ms: Option<u32>,
+  pub retry_max_ms: Option<u32>,
+}
+
+impl From<OutboxConfig> for outbox::OutboxConfig {
+  fn from(value: OutboxConfig) -> Self {
+    let default = outbox::OutboxConfig::default();
+    let millis = |x: u32| std::time::Duration::from_millis(x as u64);
+    outbox::OutboxConfig {
+      path: value.path.map_or(default.path, Into::into),
+      max_attempts: value.max_attempts.unwrap_or(default.max_attempts).max(1),
+      ttl: value.ttl_ms.map_or(default.ttl, millis),
+      retry_base: value.retry_base_ms.map_or(default.retry_base, millis),
+      retry_max: value.retry_max_ms.map_or(default.retry_max, millis),
+    }
+  }
+}
+
+/// 按 SHA-256 存放下载和发出的媒体，重复的表情包不用再下载、上传。
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct MediaCacheConfig {
+  pub dir: String,
+  /// 缓存文件总大小上限，默认 512 MiB。
+  pub max_size_mb: Option<u32>,
+}
+
+impl From<MediaCacheConfig> for media_cache::MediaCacheConfig {
+  fn from(value: MediaCacheConfig) -> Self {
+    media_cache::MediaCacheConfig {
+      dir: value.dir.into(),
+      max_bytes: value.max_size_mb.unwrap_or(512) as u64 * 1024 * 1024,
+    }
+  }
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct EventBusConfig {
+  /// 总线容量，默认 1024。
+  pub capacity: Option<u32>,
+  pub overflow: Option<EventOverflow>,
+  /// `SpillToDisk` 用的 SQLite 文件，为空时放在内存里。
+  pub spill_path: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+  /// 连续这么多个心跳周期没收到心跳就认为连接已经失效，默认 3。
+  pub missed_heartbeats: Option<u32>,
+  /// `get_status` 探测的间隔，默认 30 秒。
+  pub probe_interval_ms: Option<u32>,
+  /// 实现没上报心跳间隔、或者不发心跳时按这个间隔判断，默认 30 秒。
+  pub heartbeat_interval_ms: Option<u32>,
+  /// 连接失效后是否自动重连，默认开启。
+  pub auto_reconnect: Option<bool>,
+}
+
+impl From<WatchdogConfig> for health::WatchdogConfig {
+  fn from(value: WatchdogConfig) -> Self {
+    let default = health::WatchdogConfig::default();
+    health::WatchdogConfig {
+      missed_heartbeats: value
+        .missed_heartbeats
+        .unwrap_or(default.missed_heartbeats)
+        .max(1),
+      probe_interval: value.probe_interval_ms.map_or(default.probe_interval, |x| {
+        std::time::Duration::from_millis(x.max(1) as u64)
+      }),
+      heartbeat_interval: value
+        .heartbeat_interval_ms
+        .map_or(default.heartbeat_interval, |x| {
+          std::time::Duration::from_millis(x.max(1) as u64)
+        }),
+      auto_reconnect: value.auto_reconnect.unwrap_or(default.auto_reconnect),
+    }
+  }
+}
+
+/// 默认调用超时。发消息类（send_*、delete_msg、set_* 等）用 `send_ms`，其余用 `query_ms`。
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct ApiTimeoutConfig {
+  pub send_ms: Option<u32>,
+  pub query_ms: Option<u32>,
+}
+
+impl From<ApiTimeoutConfig> for ApiTimeouts {
+  fn from(value: ApiTimeoutConfig) -> Self {
+    let default = ApiTimeouts::default();
+    let millis = |x: u32| std::time::Duration::from_millis(x as u64);
+    ApiTimeouts {
+      send: value.send_ms.map_or(default.send, millis),
+      query: value.query_ms.map_or(default.query, millis),
+    }
+  }
+}
+
+/// 单次调用的选项，用法和 `fetch` 的 `signal` 类似。
+#[napi(object, object_to_js = false)]
+#[derive(Debug, Clone, Default)]
+pub struct CallOptions {
+  /// 覆盖默认超时。
+  pub timeout_ms: Option<u32>,
+  #[napi(ts_type = "AbortSignal")]
+  pub signal: Option<CancelSignal>,
+}
+
+impl CallOptions {
+  fn policy(options: Option<CallOptions>, timeouts: ApiTimeouts) -> CallPolicy {
+    let options = options.unwrap_or_default();
+    CallPolicy {
+      timeouts,
+      timeout: options
+        .timeout_ms
+        .map(|x| std::time::Duration::from_millis(x as u64)),
+      cancel: options.signal.map(|signal| signal.0),
+    }
+  }
+}
+
+/// `wss://` 连接用的证书，都是 PEM 文件路径。
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+  /// 额外信任的 CA，和内置的根证书一起使用。
+  pub ca_file: Option<String>,
+  /// 客户端证书，要和 `client_key` 一起设置。
+  pub client_cert: Option<String>,
+  pub client_key: Option<String>,
+}
+
+impl From<TlsConfig> for ws::TlsConfig {
+  fn from(value: TlsConfig) -> Self {
+    ws::TlsConfig {
+      ca_file: value.ca_file.map(Into::into),
+      client_cert: value.client_cert.map(Into::into),
+      client_key: value.client_key.map(Into::into),
+    }
+  }
+}
+
 #[napi(object)]
 #[derive(Debug, Clone)]
 pub struct QQBotConfig {
+  /// `ws://`/`wss://` 开头的完整 URL，可以带路径和 query；也兼容旧的 `host:port`。
   pub addr: String,
   pub access_token: String,
+  /// 握手时附加的请求头。
+  pub headers: Option<HashMap<String, String>>,
+  pub tls: Option<TlsConfig>,
+  /// 默认 `OneBot`。
+  pub transport: Option<Transport>,
+  /// mirai-api-http 绑定的 QQ 号。
+  pub qq: Option<String>,
   pub download_image: DownloadImageEndpoint,
+  pub send_rate_limit: Option<SendRateLimitConfig>,
+  pub outbox: Option<OutboxConfig>,
+  pub api_timeout: Option<ApiTimeoutConfig>,
+  /// 回放缓冲区保留的事件数，默认 256。
+  pub event_replay: Option<u32>,
+  pub event_bus: Option<EventBusConfig>,
+  pub watchdog: Option<WatchdogConfig>,
+  pub media_cache: Option<MediaCacheConfig>,
 }
 
 impl From<QQBotConfig> for super::QQBotConfig {
@@ -44,12 +264,58 @@ impl From<QQBotConfig> for super::QQBotConfig {
     super::QQBotConfig {
       addr: value.addr,
       access_token: value.access_token.into(),
-      download_image_baseurl: value.download_image.baseurl,
-      download_image_authorization_header: value.download_image.authorization_header.into(),
+      headers: value
+        .headers
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| (name, value.into()))
+        .collect(),
+      tls: value.tls.map(Into::into).unwrap_or_default(),
+      transport: value.transport.unwrap_or_default(),
+      qq: value.qq,
+      media: MediaConfig {
+        baseurl: value.download_image.baseurl,
+        authorization_header: value.download_image.authorization_header.into(),
+        strategies: value
+          .download_image
+          .strategies
+          .unwrap_or_else(media::default_strategies),
+        local_root: value.download_image.local_root.map(Into::into),
+        max_bytes: value
+          .download_image
+          .max_size_mb
+          .map_or(media::DEFAULT_MAX_BYTES, |x| x as u64 * 1024 * 1024),
+      },
+      send_scheduler: value.send_rate_limit.map(Into::into).unwrap_or_default(),
+      outbox: value.outbox.map(Into::into).unwrap_or_default(),
+      api_timeouts: value.api_timeout.map(Into::into).unwrap_or_default(),
+      watchdog: value.watchdog.map(Into::into).unwrap_or_default(),
+      media_cache: value.media_cache.map(Into::into),
+      event_bus: bus_config(value.event_bus, value.event_replay),
     }
   }
 }
 
+/// 连接和连接池共用。
+pub(crate) fn bus_config(
+  event_bus: Option<EventBusConfig>,
+  event_replay: Option<u32>,
+) -> bus::EventBusConfig {
+  let default = bus::EventBusConfig::default();
+  bus::EventBusConfig {
+    capacity: event_bus
+      .as_ref()
+      .and_then(|x| x.capacity)
+      .map_or(default.capacity, |x| x.max(1) as usize),
+    replay: event_replay.map_or(default.replay, |x| x as usize),
+    overflow: event_bus
+      .as_ref()
+      .and_then(|x| x.overflow)
+      .unwrap_or_default(),
+    spill_path: event_bus.and_then(|x| x.spill_path).map(Into::into),
+  }
+}
+
 #[napi]
 pub struct QQBotEndpoint {
   inner: Arc<Inner>,
@@ -69,132 +335,275 @@ impl QQBotEndpoint {
       inner: Inner::new(config.into())?,
     })
   }
-  pub fn client(&self) -> anyhow::Result<ClientProxy<WsConnect>> {
-    self.inner.get_client()
+  pub(crate) fn from_inner(inner: Arc<Inner>) -> Self {
+    Self { inner }
+  }
+  pub(crate) fn inner(&self) -> &Arc<Inner> {
+    &self.inner
+  }
+  pub fn client(&self, options: Option<CallOptions>) -> anyhow::Result<ClientProxy<Connection>> {
+    Ok(self.inner.get_client()?.with_policy(self.policy(options)))
+  }
+  pub(crate) fn policy(&self, options: Option<CallOptions>) -> CallPolicy {
+    CallOptions::policy(options, self.inner.config.api_timeouts)
+  }
+  #[napi(ts_return_type = "Promise<void>")]
+  pub async fn start(&self, options: Option<CallOptions>) -> Coded<()> {
+    Coded::wrap(self.inner.clone().start(self.policy(options))).await
   }
   #[napi]
-  pub async fn start(&self) -> anyhow::Result<()> {
-    self.inner.clone().start().await
+  pub fn state(&self) -> EndpointState {
+    self.inner.state()
   }
   #[napi]
-  pub async fn terminate(&self) -> anyhow::Result<()> {
-    self.inner.terminate().await
+  pub fn health(&self) -> Health {
+    self.inner.health()
   }
+  /// 当前连接的 OneBot 实现和版本，连上之前是 `Unknown`。
   #[napi]
-  pub async fn register_callback(
-    &self,
-    callback: ThreadsafeFunction<event::Event>,
-  ) -> anyhow::Result<()> {
-    self.inner.register_callback(callback)
+  pub fn dialect(&self) -> DialectInfo {
+    self.inner.dialect().info.clone()
+  }
+  /// 下载过的 QQ 图片的 SHA-256，没有启用缓存或没下载过时为空。
+  #[napi]
+  pub fn cached_image_hash(&self, image_id: String) -> anyhow::Result<Option<String>> {
+    match &self.inner.media_cache {
+      Some(cache) => cache.image_hash(&image_id),
+      None => Ok(None),
+    }
   }
+  /// 这份媒体之前上传到 Matrix 得到的 `mxc://` URI。
   #[napi]
-  pub async fn get_friend_list(&self) -> anyhow::Result<Vec<(String, String)>> {
-    info!("get friend list");
-    let client = self.client()?;
-    let resp = client.get_friend_list(GetFriendList {}).await?;
+  pub fn lookup_mxc(&self, hash: String) -> anyhow::Result<Option<String>> {
+    match &self.inner.media_cache {
+      Some(cache) => cache.mxc(&hash),
+      None => Ok(None),
+    }
+  }
+  #[napi]
+  pub fn remember_mxc(&self, hash: String, mxc: String) -> anyhow::Result<()> {
+    match &self.inner.media_cache {
+      Some(cache) => cache.set_mxc(&hash, &mxc),
+      None => Ok(()),
+    }
+  }
+  /// 停止当前运行，等主循环退出、`Closed` 发出后才返回。之后可以再次 `start`。
+  #[napi(ts_return_type = "Promise<void>")]
+  pub async fn terminate(&self) -> Coded<()> {
+    Coded::wrap(self.inner.terminate()).await
+  }
+  #[napi(
+    ts_args_type = "callback: ((err: Error | null, arg: SequencedEvent) => any), options?: SubscribeOptions | undefined | null"
+  )]
+  pub async fn register_callback(
+    &self,
+    callback: ThreadsafeFunction<SequencedEvent>,
+    options: Option<SubscribeOptions>,
+  ) -> anyhow::Result<Subscription> {
     Ok(
-      resp
-        .iter()
-        .map(|x| (x.user_id.to_string(), x.nickname.clone()))
-        .collect_vec(),
+      self
+        .inner
+        .register_callback(callback, options.unwrap_or_default()),
     )
   }
+  /// 事件总线的队列深度等指标，用来观察 JS 侧是否处理不过来。
+  #[napi]
+  pub fn event_bus_metrics(&self) -> EventBusMetrics {
+    self.inner.event_bus_metrics()
+  }
+  /// 以 `AsyncIterator` 的形式读取事件，`terminate` 之后产出 `Closed` 然后结束迭代。
   #[napi]
+  pub fn events(&self, filter: Option<EventFilter>, replay: Option<ReplayOptions>) -> EventStream {
+    self
+      .inner
+      .events(filter.unwrap_or_default(), replay.unwrap_or_default())
+  }
+  /// 连接成功并拿到登录信息后 resolve；还没连上就被终止时以 `NOT_CONNECTED` reject。
+  /// 默认一直等下去，不套用查询类的超时。
+  #[napi(ts_return_type = "Promise<void>")]
+  pub async fn wait_connected(&self, options: Option<CallOptions>) -> Coded<()> {
+    let mut policy = self.policy(options);
+    policy.timeout.get_or_insert(std::time::Duration::MAX);
+    Coded::wrap(policy.run("wait_connected", self.inner.wait_connected())).await
+  }
+  #[napi(ts_return_type = "Promise<Array<[string, string]>>")]
+  pub async fn get_friend_list(
+    &self,
+    options: Option<CallOptions>,
+  ) -> Coded<Vec<(String, String)>> {
+    Coded::wrap(async {
+      info!("get friend list");
+      let client = self.client(options)?;
+      let resp = client.get_friend_list(GetFriendList {}).await?;
+      Ok(
+        resp
+          .iter()
+          .map(|x| (x.user_id.to_string(), x.nickname.clone()))
+          .collect_vec(),
+      )
+    })
+    .await
+  }
+  #[napi(ts_return_type = "Promise<GroupMemberInfo>")]
   pub async fn get_group_member(
     &self,
     group_id: String,
     user_id: String,
-  ) -> anyhow::Result<GroupMemberInfo> {
-    let group_id = parse_qq_id(&group_id)?;
-    let user_id = parse_qq_id(&user_id)?;
-    let resp = self
-      .client()?
-      .get_group_member_info(GetGroupMemberInfo {
-        group_id,
-        user_id,
-        no_cache: false,
-      })
-      .await?;
+    options: Option<CallOptions>,
+  ) -> Coded<GroupMemberInfo> {
+    Coded::wrap(async {
+      let group_id = parse_qq_id(&group_id)?;
+      let user_id = parse_qq_id(&user_id)?;
+      let resp = self
+        .client(options)?
+        .get_group_member_info(GetGroupMemberInfo {
+          group_id,
+          user_id,
+          no_cache: false,
+        })
+        .await?;
 
-    Ok(GroupMemberInfo {
-      user_id: user_id.to_string(),
-      nick: Some(resp.card),
-      name: Some(resp.nickname),
+      Ok(GroupMemberInfo {
+        user_id: user_id.to_string(),
+        nick: Some(resp.card),
+        name: Some(resp.nickname),
+      })
     })
+    .await
   }
-  #[napi]
-  pub async fn delete_message(&self, message_id: String) -> anyhow::Result<()> {
-    self
-      .client()?
-      .delete_msg(DeleteMsg {
-        message_id: parse_qq_id(&message_id)?,
-      })
-      .await?;
-    Ok(())
+  #[napi(ts_return_type = "Promise<void>")]
+  pub async fn delete_message(
+    &self,
+    message_id: String,
+    options: Option<CallOptions>,
+  ) -> Coded<()> {
+    Coded::wrap(async {
+      self
+        .client(options)?
+        .delete_msg(DeleteMsg {
+          message_id: parse_qq_id(&message_id)?,
+        })
+        .await?;
+      Ok(())
+    })
+    .await
   }
 
-  #[napi]
+  #[napi(ts_return_type = "Promise<DownloadedMedia>")]
   pub async fn download_image(
     &self,
-    image_id: String, /*, target_path: String*/
-  ) -> anyhow::Result<Buffer> {
-    let file = self
-      .client()?
-      .get_image(GetImage { file: image_id })
-      .await?;
-    // download.
-    //let mut dest = tokio::fs::File::create(Path::new(&target_path)).await?;
-    let mut dest = vec![];
-    let baseurl = Url::parse(&format!("{}/", &self.inner.config.download_image_baseurl))?;
-    let file_url = baseurl.join(&file.file)?;
-    let authorization = self
-      .inner
-      .config
-      .download_image_authorization_header
-      .expose_secret();
-    let client = reqwest::Client::new();
-    let resp = client
-      .get(file_url)
-      .header(reqwest::header::AUTHORIZATION, authorization)
-      .send()
-      .await?
-      .error_for_status()?;
-    let mut stream = StreamReader::new(
-      resp
-        .bytes_stream()
-        .map_err(|e| futures_util::io::Error::new(futures_util::io::ErrorKind::Other, e)),
-    );
-    tokio::io::copy(&mut stream, &mut dest).await?;
-    Ok(dest.into())
+    image_id: String,
+    options: Option<CallOptions>,
+  ) -> Coded<DownloadedMedia> {
+    Coded::wrap(async {
+      let media = self
+        .inner
+        .fetch_image(&image_id, self.policy(options), None)
+        .await?;
+      Ok(DownloadedMedia::new(media, None))
+    })
+    .await
   }
-  #[napi]
+  /// 边下载边写进 `path`，返回的 `buffer` 为空。
+  #[napi(ts_return_type = "Promise<DownloadedMedia>")]
+  pub async fn download_image_to_file(
+    &self,
+    image_id: String,
+    path: String,
+    options: Option<CallOptions>,
+  ) -> Coded<DownloadedMedia> {
+    Coded::wrap(async {
+      let media = self
+        .inner
+        .fetch_image(&image_id, self.policy(options), Some(Path::new(&path)))
+        .await?;
+      Ok(DownloadedMedia::new(media, Some(path)))
+    })
+    .await
+  }
+  #[napi(ts_return_type = "Promise<SendGroupMsgResp>")]
   pub async fn send_group_message(
     &self,
     group_id: String,
     message: Vec<Mockv2MessageChain>,
-  ) -> anyhow::Result<SendGroupMsgResp> {
-    let segments = msgchain_to_segments(&message)?;
-    // 检查segments要么全node，要么全不是node
-    let all_nodes = segments
-      .iter()
-      .map(|x| matches!(x, MessageSegment::CustomNode { .. }))
-      .all_equal();
-    if !all_nodes {
-      bail!("转发节点有一半是Node，另一半不是！");
-    }
-    let resp = self
-      .client()?
-      .send_msg(SendMsg {
-        message_type: onebot_v11::api::payload::MessageType::Group,
-        group_id: Some(parse_qq_id(&group_id)?),
-        auto_escape: false,
-        user_id: None,
-        message: segments,
+    priority: Option<SendPriority>,
+    options: Option<CallOptions>,
+  ) -> Coded<SendGroupMsgResp> {
+    Coded::wrap(async {
+      let segments = group_message_segments(&message)?;
+      self.inner.cache_outbound(&message);
+      let group_id = parse_qq_id(&group_id)?;
+      let priority = priority.unwrap_or_default();
+      let outbox = &self.inner.outbox;
+      // 出站日志里还有这个群没送达的消息时，新消息排在它们后面，保证同群按顺序送达。
+      if !outbox.has_pending(group_id).await? {
+        let send = async {
+          let client = self.client(options)?;
+          let policy = client.1.clone();
+          // 排队等待令牌，同群消息按提交顺序发出。超时从入队时算起，排队的时间也算在内。
+          policy
+            .run(
+              "send_msg",
+              self.inner.scheduler.submit(
+                group_id,
+                priority,
+                client.send_msg(SendMsg {
+                  message_type: onebot_v11::api::payload::MessageType::Group,
+                  group_id: Some(group_id),
+                  auto_escape: false,
+                  user_id: None,
+                  message: segments.clone(),
+                }),
+              ),
+            )
+            .await
+        };
+        match send.await.map_err(BridgeError::from) {
+          Ok(resp) => {
+            return Ok(SendGroupMsgResp {
+              message_id: Some(resp.message_id.to_string()),
+              local_id: None,
+            });
+          }
+          // 请求还没发出去就断线了，写进出站日志，重连后重放。其他错误时消息可能已经送达，
+          // 重放会发重，直接报错。
+          Err(BridgeError::NotConnected) => warn!(group_id, "QQ is disconnected"),
+          Err(err) => return Err(err.into()),
+        }
+      }
+      let local_id = outbox.push(group_id, priority, &segments).await?;
+      info!(group_id, local_id, "queued in outbox");
+      Ok(SendGroupMsgResp {
+        message_id: None,
+        local_id: Some(local_id.to_string()),
       })
-      .await?;
-    Ok(SendGroupMsgResp {
-      message_id: resp.message_id.to_string(),
     })
+    .await
+  }
+  /// 写入出站日志后立即返回本地 id，投递结果通过 `OutboundDelivered` / `OutboundFailed` 事件通知。
+  /// 断线期间的消息会在重连后按顺序重放。
+  #[napi(ts_return_type = "Promise<string>")]
+  pub async fn enqueue_group_message(
+    &self,
+    group_id: String,
+    message: Vec<Mockv2MessageChain>,
+    priority: Option<SendPriority>,
+  ) -> Coded<String> {
+    Coded::wrap(async {
+      let segments = group_message_segments(&message)?;
+      self.inner.cache_outbound(&message);
+      let local_id = self
+        .inner
+        .outbox
+        .push(
+          parse_qq_id(&group_id)?,
+          priority.unwrap_or_default(),
+          &segments,
+        )
+        .await?;
+      Ok(local_id.to_string())
+    })
+    .await
   }
   /*
   #[napi]
@@ -209,14 +618,63 @@ impl QQBotEndpoint {
   */
 }
 
+/// `mime` 按文件头判断，认不出时为 `application/octet-stream`。
+#[napi(object)]
+pub struct DownloadedMedia {
+  pub buffer: Buffer,
+  /// 写进文件时为文件路径。
+  pub path: Option<String>,
+  pub mime: String,
+  pub width: Option<u32>,
+  pub height: Option<u32>,
+  pub size: i64,
+}
+
+impl DownloadedMedia {
+  fn new(media: Media, path: Option<String>) -> Self {
+    let size = media.size() as i64;
+    let (width, height) = media.dimensions.unzip();
+    let buffer = match media.fetched {
+      Fetched::Memory(buffer) => buffer,
+      Fetched::File { .. } => vec![],
+    };
+    Self {
+      buffer: buffer.into(),
+      path,
+      mime: media.mime.to_owned(),
+      width,
+      height,
+      size,
+    }
+  }
+}
+
 #[napi(object)]
 #[derive(Clone)]
 pub struct SendGroupMsgResp {
-  pub message_id: String,
+  /// 断线、或者这个群还有没送达的消息时，新消息进了出站日志，这里为空。
+  pub message_id: Option<String>,
+  /// 进了出站日志时的本地 id，之后的投递结果通过 `OutboundDelivered` / `OutboundFailed` 事件通知。
+  pub local_id: Option<String>,
+}
+pub(crate) fn parse_qq_id(s: &str) -> anyhow::Result<i64> {
+  let id = s
+    .parse::<i64>()
+    .map_err(|_| BridgeError::InvalidArgument(format!("not a QQ id: {s:?}")))?;
+  Ok(id)
 }
-fn parse_qq_id(s: &str) -> anyhow::Result<i64> {
-  let s = s.parse::<i64>()?;
-  Ok(s)
+
+fn group_message_segments(message: &[Mockv2MessageChain]) -> anyhow::Result<Vec<MessageSegment>> {
+  let segments = msgchain_to_segments(message)?;
+  // 检查segments要么全node，要么全不是node
+  let all_nodes = segments
+    .iter()
+    .map(|x| matches!(x, MessageSegment::CustomNode { .. }))
+    .all_equal();
+  if !all_nodes {
+    bail!("转发节点有一半是Node，另一半不是！");
+  }
+  Ok(segments)
 }
 
 pub fn msgchain_to_segments(
@@ -267,7 +725,8 @@ pub fn msgchain_to_segments(
 }
 
 pub async fn message_to_msgchain(
-  client: ClientProxy<WsConnect>,
+  client: ClientProxy<Connection>,
+  dialect: Arc<Dialect>,
   message: &Message,
 ) -> anyhow::Result<(String, Vec<Mockv2MessageChain>)> {
   let unnamed = "未知用户".to_owned();
@@ -293,7 +752,7 @@ pub async fn message_to_msgchain(
   let client = client.clone();
   let body = body
     .iter()
-    .map(|y| message_segment_to_msgchain(client.clone(), y))
+    .map(|y| message_segment_to_msgchain(client.clone(), dialect.clone(), y))
     .collect_vec();
   let mut body = try_join_all(body).await?;
   body.insert(0, Mockv2MessageChain::Source { id: id.to_string() });
@@ -301,7 +760,8 @@ pub async fn message_to_msgchain(
 }
 
 pub async fn message_segment_to_msgchain(
-  client: ClientProxy<WsConnect>,
+  client: ClientProxy<Connection>,
+  dialect: Arc<Dialect>,
   segment: &MessageSegment,
 ) -> anyhow::Result<Mockv2MessageChain> {
   Ok(match segment {
@@ -326,6 +786,12 @@ pub async fn message_segment_to_msgchain(
     MessageSegment::Forward { data } => Mockv2MessageChain::Forward {
       node_list: {
         let Some(messages) = data.content.as_ref() else {
+          if !dialect.inline_forward {
+            match Box::pin(fetch_forward(client.clone(), dialect.clone(), &data.id)).await {
+              Ok(node_list) => return Ok(Mockv2MessageChain::Forward { node_list }),
+              Err(err) => warn!("Failed to fetch forward {}: {err:#}", data.id),
+            }
+          }
           // soft error
           return Ok(Mockv2MessageChain::Error {
             message: format!("获取Forward信息失败: {}", data.id),
@@ -334,7 +800,8 @@ pub async fn message_segment_to_msgchain(
         let messages = messages
           .into_iter()
           .map(async |x| {
-            let (sender, message_chain) = message_to_msgchain(client.clone(), x).await?;
+            let (sender, message_chain) =
+              message_to_msgchain(client.clone(), dialect.clone(), x).await?;
             Ok::<_, anyhow::Error>(ForwardItem {
               sender_name: sender,
               message_chain,
@@ -361,8 +828,54 @@ pub async fn message_segment_to_msgchain(
   })
 }
 
+/// go-cqhttp 和 Lagrange 的合并转发消息段只有 id，内容要另外取。
+async fn fetch_forward(
+  client: ClientProxy<Connection>,
+  dialect: Arc<Dialect>,
+  id: &str,
+) -> anyhow::Result<Vec<ForwardItem>> {
+  let resp = client
+    .clone()
+    .get_forward_msg(GetForwardMsg { id: id.to_owned() })
+    .await?;
+  let resp = serde_json::to_value(&resp)?;
+  let nodes = resp["messages"]
+    .as_array()
+    .or(resp["message"].as_array())
+    .context("No messages in forward")?;
+  let nodes = nodes
+    .iter()
+    .map(async |node| {
+      let sender = &node["sender"];
+      let sender_name = [&sender["card"], &sender["nickname"]]
+        .into_iter()
+        .find_map(|x| x.as_str().filter(|s| !s.is_empty()))
+        .unwrap_or("未知用户")
+        .to_owned();
+      // go-cqhttp 用 `content`，Lagrange 用 `message`。
+      let content = if node["content"].is_array() {
+        &node["content"]
+      } else {
+        &node["message"]
+      };
+      let segments: Vec<MessageSegment> = serde_json::from_value(content.clone())?;
+      let message_chain = try_join_all(
+        segments
+          .iter()
+          .map(|x| message_segment_to_msgchain(client.clone(), dialect.clone(), x)),
+      )
+      .await?;
+      Ok::<_, anyhow::Error>(ForwardItem {
+        sender_name,
+        message_chain,
+      })
+    })
+    .collect_vec();
+  try_join_all(nodes).await
+}
+
 #[napi(object)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GroupMemberInfo {
   pub user_id: String,
   pub nick: Option<String>,
@@ -370,14 +883,14 @@ pub struct GroupMemberInfo {
 }
 
 #[napi(object)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ForwardItem {
   pub sender_name: String,
   pub message_chain: Vec<Mockv2MessageChain>,
 }
 
 #[napi]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Mockv2MessageChain {
   Forward {
     node_list: Vec<ForwardItem>,
@@ -412,6 +925,12 @@ pub enum Mockv2MessageChain {
   },
 }
 
+/// 媒体缓存使用的 SHA-256，十六进制小写。
+#[napi]
+pub fn media_hash(buffer: &[u8]) -> String {
+  media_cache::media_hash(buffer)
+}
+
 #[napi]
 fn test_uint8array(elem: Mockv2MessageChain) {
   println!("{:?}", elem);
@@ -0,0 +1,296 @@
+use std::{
+  sync::{Arc, Weak, atomic::Ordering},
+  time::{Duration, Instant},
+};
+
+use futures_util::{FutureExt, future::BoxFuture};
+use napi::tokio;
+use napi_derive::napi;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use super::{
+  QQBotEndpoint, client_proxy::CallPolicy, event::Event, lifecycle::EndpointState, outbox::now_ms,
+};
+
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+  /// 连续这么多个心跳周期没收到心跳就认为连接已经失效。
+  pub missed_heartbeats: u32,
+  /// `get_status` 探测的间隔。
+  pub probe_interval: Duration,
+  /// 实现没上报心跳间隔、或者根本不发心跳时，按这个间隔判断是否失效。
+  pub heartbeat_interval: Duration,
+  /// 连接失效后是否自动重连。
+  pub auto_reconnect: bool,
+}
+
+impl Default for WatchdogConfig {
+  fn default() -> Self {
+    Self {
+      missed_heartbeats: 3,
+      probe_interval: Duration::from_secs(30),
+      heartbeat_interval: Duration::from_secs(30),
+      auto_reconnect: true,
+    }
+  }
+}
+
+/// 连接健康状况。时间都是毫秒时间戳，还没有数据时为空。
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+  pub state: EndpointState,
+  pub last_heartbeat_at: Option<i64>,
+  /// OneBot 实现上报的心跳间隔。
+  pub heartbeat_interval_ms: Option<i64>,
+  pub last_event_at: Option<i64>,
+  /// 最近一次 `get_status` 探测的往返时间，探测失败时为空。
+  pub rtt_ms: Option<u32>,
+  /// 心跳里的 `status.online`。
+  pub online: Option<bool>,
+  /// 心跳里的 `status.good`。
+  pub good: Option<bool>,
+  /// 心跳已经超时。
+  pub stale: bool,
+}
+
+/// 每次运行重新计数。
+#[derive(Debug, Default)]
+pub struct HealthState {
+  started_at: i64,
+  last_heartbeat_at: Option<i64>,
+  heartbeat_interval: Option<i64>,
+  last_event_at: Option<i64>,
+  /// 最近一次 `get_status` 探测成功的时间。
+  last_probe_at: Option<i64>,
+  rtt: Option<Duration>,
+  online: Option<bool>,
+  good: Option<bool>,
+}
+
+impl HealthState {
+  pub fn new() -> Self {
+    Self {
+      started_at: now_ms(),
+      ..Default::default()
+    }
+  }
+
+  pub fn record_event(&mut self) {
+    self.last_event_at = Some(now_ms());
+  }
+
+  /// 心跳事件的格式各家实现略有不同，这里只取 `interval` 和 `status.online/good`。
+  pub fn record_heartbeat(&mut self, heartbeat: &serde_json::Value) {
+    self.last_heartbeat_at = Some(now_ms());
+    if let Some(interval) = heartbeat["interval"].as_i64().filter(|x| *x > 0) {
+      self.heartbeat_interval = Some(interval);
+    }
+    let status = &heartbeat["status"];
+    self.online = status["online"].as_bool().or(self.online);
+    self.good = status["good"].as_bool().or(self.good);
+  }
+
+  fn is_stale(&self, config: &WatchdogConfig) -> bool {
+    let missed = config.missed_heartbeats.max(1) as i64;
+    if let Some(interval) = self.heartbeat_interval {
+      let last = self.last_heartbeat_at.unwrap_or(self.started_at);
+      return now_ms() - last > interval.saturating_mul(missed);
+    }
+    // 没见过心跳间隔时按配置的间隔算。不发心跳的实现（比如 mirai、Satori）收到事件、探测成功也算还活着，
+    // 所以至少要等过一次探测。
+    let interval = config
+      .heartbeat_interval
+      .max(config.probe_interval)
+      .as_millis() as i64;
+    let last = [
+      Some(self.started_at),
+      self.last_heartbeat_at,
+      self.last_event_at,
+      self.last_probe_at,
+    ]
+    .into_iter()
+    .flatten()
+    .max()
+    .unwrap_or_default();
+    now_ms() - last > interval.saturating_mul(missed)
+  }
+}
+
+impl QQBotEndpoint {
+  pub fn health(&self) -> Health {
+    let health = self.health.lock().unwrap();
+    Health {
+      state: self.state(),
+      last_heartbeat_at: health.last_heartbeat_at,
+      heartbeat_interval_ms: health.heartbeat_interval,
+      last_event_at: health.last_event_at,
+      rtt_ms: health.rtt.map(|x| x.as_millis() as u32),
+      online: health.online,
+      good: health.good,
+      stale: self.state() == EndpointState::Running && health.is_stale(&self.config.watchdog),
+    }
+  }
+
+  /// 上下线事件也会更新 `online`，不用等下一次心跳。
+  pub(crate) fn record_bot_status(&self, event: &Event) {
+    let online = match event {
+      Event::BotOnline { .. } => true,
+      Event::BotOffline { .. } => false,
+      _ => return,
+    };
+    self.health.lock().unwrap().online = Some(online);
+  }
+
+  /// 每次运行一个，随运行的取消令牌结束。
+  pub(crate) async fn watchdog(this: Weak<Self>, cancel: CancellationToken) {
+    let Some(config) = this.upgrade().map(|x| x.config.watchdog.clone()) else {
+      return;
+    };
+    let mut check = tokio::time::interval(Duration::from_secs(1));
+    let mut probe = tokio::time::interval(config.probe_interval);
+    loop {
+      tokio::select! {
+        _ = cancel.cancelled() => return,
+        _ = check.tick() => {
+          let Some(this) = this.upgrade() else { return };
+          let stale = this.health.lock().unwrap().is_stale(&config);
+          if stale {
+            warn!("No heartbeat for {} intervals, connection is stale", config.missed_heartbeats);
+            if config.auto_reconnect {
+              tokio::spawn(this.reconnect());
+              return;
+            }
+          }
+        }
+        _ = probe.tick() => {
+          let Some(this) = this.upgrade() else { return };
+          this.probe().await;
+        }
+      }
+    }
+  }
+
+  async fn probe(&self) {
+    let Ok(client) = self.get_client() else {
+      return;
+    };
+    let started = Instant::now();
+    let result = client
+      .get_status(onebot_v11::api::payload::GetStatus {})
+      .await;
+    let rtt = started.elapsed();
+    let mut health = self.health.lock().unwrap();
+    match result {
+      Ok(_) => {
+        debug!(?rtt, "get_status probe");
+        health.rtt = Some(rtt);
+        health.last_probe_at = Some(now_ms());
+      }
+      Err(err) => {
+        warn!("get_status probe failed: {err:#}");
+        health.rtt = None;
+      }
+    }
+  }
+
+  /// 断开当前连接后按退避重连，直到连上或者 JS 侧调用了 `start`/`terminate`。
+  ///
+  /// `start_run` 里会再次用到它，返回装箱的 future 以打断类型上的循环。
+  pub(crate) fn reconnect(self: Arc<Self>) -> BoxFuture<'static, ()> {
+    async move {
+      let generation = self.generation.load(Ordering::SeqCst);
+      if let Err(err) = self.stop_run(false).await {
+        warn!("Failed to stop stale connection: {err:#}");
+      }
+      let mut delay = Duration::from_secs(1);
+      loop {
+        if self.generation.load(Ordering::SeqCst) != generation {
+          return;
+        }
+        info!("QQBot reconnecting...");
+        let policy = CallPolicy {
+          timeouts: self.config.api_timeouts,
+          ..Default::default()
+        };
+        match self.clone().start_run(policy).await {
+          Ok(()) => return,
+          Err(err) => warn!("Reconnect failed: {err:#}"),
+        }
+        if self.state() != EndpointState::Stopped {
+          return;
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(Duration::from_secs(60));
+      }
+    }
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// `ago` 毫秒之前开始运行。
+  fn started(ago: i64) -> HealthState {
+    HealthState {
+      started_at: now_ms() - ago,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn uses_the_reported_heartbeat_interval() {
+    let config = WatchdogConfig::default();
+    let mut health = started(60_000);
+    health.record_heartbeat(&serde_json::json!({ "interval": 5000 }));
+    assert!(!health.is_stale(&config));
+    health.last_heartbeat_at = Some(now_ms() - 14_000);
+    assert!(!health.is_stale(&config));
+    health.last_heartbeat_at = Some(now_ms() - 16_000);
+    assert!(health.is_stale(&config));
+  }
+
+  #[test]
+  fn falls_back_to_the_configured_interval() {
+    let config = WatchdogConfig {
+      heartbeat_interval: Duration::from_secs(10),
+      probe_interval: Duration::from_secs(5),
+      ..Default::default()
+    };
+    assert!(!started(29_000).is_stale(&config));
+    assert!(started(31_000).is_stale(&config));
+
+    // 心跳里没带 `interval` 也算。
+    let mut health = started(60_000);
+    health.record_heartbeat(&serde_json::json!({ "status": { "online": true } }));
+    assert!(!health.is_stale(&config));
+  }
+
+  #[test]
+  fn events_and_probes_keep_a_silent_connection_alive() {
+    let config = WatchdogConfig::default();
+    let mut health = started(300_000);
+    assert!(health.is_stale(&config));
+    health.record_event();
+    assert!(!health.is_stale(&config));
+
+    let mut health = started(300_000);
+    health.last_probe_at = Some(now_ms() - 60_000);
+    assert!(!health.is_stale(&config));
+  }
+
+  #[test]
+  fn waits_for_at_least_one_probe() {
+    // 探测间隔比心跳间隔长时，按探测间隔算，免得探测还没来得及成功就判定失效。
+    let config = WatchdogConfig {
+      heartbeat_interval: Duration::from_secs(1),
+      probe_interval: Duration::from_secs(60),
+      ..Default::default()
+    };
+    assert!(!started(120_000).is_stale(&config));
+    assert!(started(181_000).is_stale(&config));
+  }
+}
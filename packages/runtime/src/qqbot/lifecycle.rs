@@ -0,0 +1,369 @@
+use std::sync::{Arc, atomic::Ordering};
+
+use anyhow::{Context, bail};
+use napi::tokio;
+use napi_derive::napi;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+use super::{
+  QQBotEndpoint,
+  client_proxy::{CallPolicy, ClientProxy},
+  connection::Connection,
+  dialect::Dialect,
+  error::BridgeError,
+  event::Event,
+  health::HealthState,
+  ws::{self, ConnectOptions},
+};
+
+/// 连接的生命周期：Idle → Connecting → Running → Stopping → Stopped。`Stopped` 之后可以再次 `start`。
+#[napi(string_enum)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointState {
+  #[default]
+  Idle,
+  Connecting,
+  Running,
+  Stopping,
+  Stopped,
+}
+
+impl QQBotEndpoint {
+  pub fn state(&self) -> EndpointState {
+    *self.state.borrow()
+  }
+
+  pub async fn start(self: Arc<Self>, policy: CallPolicy) -> anyhow::Result<()> {
+    // 打断正在进行的自动重连。
+    self.generation.fetch_add(1, Ordering::SeqCst);
+    self.start_run(policy).await
+  }
+
+  /// 停止当前运行，等主循环退出、`Closed` 发出后才返回。没有在运行时什么也不做。
+  pub async fn terminate(&self) -> anyhow::Result<()> {
+    self.generation.fetch_add(1, Ordering::SeqCst);
+    self.stop_run(true).await
+  }
+
+  #[instrument]
+  pub(crate) async fn start_run(self: Arc<Self>, policy: CallPolicy) -> anyhow::Result<()> {
+    let cancel = {
+      let mut run = self.run.lock().await;
+      let state = self.state();
+      if !matches!(state, EndpointState::Idle | EndpointState::Stopped) {
+        bail!("Cannot start while {state:?}");
+      }
+      let cancel = CancellationToken::new();
+      *run = Some(cancel.clone());
+      *self.health.lock().unwrap() = HealthState::new();
+      self.terminated.store(false, Ordering::SeqCst);
+      self.state.send_replace(EndpointState::Connecting);
+      cancel
+    };
+
+    info!(
+      "QQBot starting, connecting to {}",
+      ws::redacted_addr(&self.config.addr)
+    );
+
+    let connected = tokio::select! {
+      _ = cancel.cancelled() => Err(BridgeError::Cancelled { action: "connect" }.into()),
+      result = self.connect(policy) => result,
+    };
+    let (name, qq) = {
+      let mut run = self.run.lock().await;
+      match connected {
+        // `terminate` 可能恰好在连上的同时取消。
+        Ok((client, dialect, name, qq)) if !cancel.is_cancelled() => {
+          *self.client.write().unwrap() = Some(client);
+          *self.dialect.write().unwrap() = dialect;
+          *self.self_id.write().unwrap() = Some(qq.clone());
+          self.state.send_replace(EndpointState::Running);
+          (name, qq)
+        }
+        result => {
+          run.take();
+          self.state.send_replace(EndpointState::Stopped);
+          return match result {
+            Ok(_) => Err(BridgeError::Cancelled { action: "connect" }.into()),
+            Err(err) => Err(err),
+          };
+        }
+      }
+    };
+    if let Err(err) = self.outbox.claim(&qq).await {
+      warn!("Failed to claim outbox entries for {qq}: {err:#}");
+    }
+    self.events.publish(Event::Connected { name, qq }).await?;
+
+    if !self.outbox_running.swap(true, Ordering::SeqCst) {
+      tokio::spawn(Self::outbox_loop(
+        Arc::downgrade(&self),
+        self.outbox.clone(),
+      ));
+    }
+    // 连上之后重放断线期间积压的出站消息。
+    self.outbox.wake();
+    tokio::spawn(Self::watchdog(Arc::downgrade(&self), cancel.clone()));
+    tokio::spawn(async move {
+      let result = self.main_loop(cancel).await;
+      self.finish_run().await;
+      if let Err(err) = result {
+        warn!("Main loop failed: {err:#}");
+        if self.config.watchdog.auto_reconnect {
+          self.reconnect().await;
+        }
+      }
+    });
+
+    info!("QQBot started!");
+
+    Ok(())
+  }
+
+  async fn connect(
+    &self,
+    policy: CallPolicy,
+  ) -> anyhow::Result<(Arc<Connection>, Arc<Dialect>, String, String)> {
+    let options = ConnectOptions {
+      addr: &self.config.addr,
+      access_token: &self.config.access_token,
+      headers: &self.config.headers,
+      tls: &self.config.tls,
+      qq: self.config.qq.as_deref(),
+    };
+    let client = policy
+      .run(
+        "connect",
+        Connection::connect(self.config.transport, options),
+      )
+      .await?;
+
+    // do a whoami.
+    let proxy = ClientProxy::new(client.clone()).with_policy(CallPolicy {
+      timeouts: self.config.api_timeouts,
+      ..policy
+    });
+    let login_info = proxy
+      .clone()
+      .get_login_info(onebot_v11::api::payload::GetLoginInfo {})
+      .await
+      .context("Failed to get login info")?;
+    let dialect = Arc::new(Dialect::detect(proxy).await);
+    Ok((
+      client,
+      dialect,
+      login_info.nickname,
+      login_info.user_id.to_string(),
+    ))
+  }
+
+  /// 主循环退出后（`terminate` 或者断线）清理本次运行，并通知订阅者 `Closed`。
+  async fn finish_run(&self) {
+    self.state.send_replace(EndpointState::Stopping);
+    self.client.write().unwrap().take();
+    let terminated = self.terminated.swap(false, Ordering::SeqCst);
+    if let Err(err) = self.events.publish(Event::Closed { terminated }).await {
+      warn!("Failed to publish Closed: {err:#}");
+    }
+    if let Some(cancel) = self.run.lock().await.take() {
+      // 让看门狗也退出。
+      cancel.cancel();
+    }
+    self.state.send_replace(EndpointState::Stopped);
+    info!("QQBot stopped");
+  }
+
+  /// `terminated` 为 `true` 表示 JS 调用了 `terminate`，自动重连时为 `false`。
+  pub(crate) async fn stop_run(&self, terminated: bool) -> anyhow::Result<()> {
+    {
+      let run = self.run.lock().await;
+      let Some(cancel) = run.as_ref() else {
+        return Ok(());
+      };
+      info!("QQBot terminating...");
+      self.terminated.store(terminated, Ordering::SeqCst);
+      self.state.send_replace(EndpointState::Stopping);
+      cancel.cancel();
+    }
+    self
+      .state
+      .subscribe()
+      .wait_for(|state| *state == EndpointState::Stopped)
+      .await?;
+    Ok(())
+  }
+
+  /// 等到进入 `Running`。还没连上就停下来时返回 `NotConnected`。
+  pub async fn wait_connected(&self) -> anyhow::Result<()> {
+    let mut rx = self.state.subscribe();
+    if *rx.borrow_and_update() == EndpointState::Running {
+      return Ok(());
+    }
+    loop {
+      rx.changed().await?;
+      match *rx.borrow_and_update() {
+        EndpointState::Running => return Ok(()),
+        EndpointState::Stopped => return Err(BridgeError::NotConnected.into()),
+        _ => {}
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use futures_util::{SinkExt, StreamExt};
+  use secrecy::SecretString;
+  use serde_json::{Value, json};
+  use tokio::net::TcpListener;
+  use tokio_tungstenite::tungstenite::Message;
+
+  use super::*;
+  use crate::qqbot::{
+    QQBotConfig,
+    bus::{EventBusConfig, ReplayOptions},
+    client_proxy::ApiTimeouts,
+    connection::Transport,
+    health::WatchdogConfig,
+    media::MediaConfig,
+    outbox::OutboxConfig,
+    scheduler::SchedulerConfig,
+    ws::TlsConfig,
+  };
+
+  /// 只会回答 `get_login_info` 的 OneBot 服务端，其他 action 一律报错。
+  async fn fake_onebot() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(async move {
+          let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+            return;
+          };
+          while let Some(Ok(Message::Text(text))) = ws.next().await {
+            let request: Value = serde_json::from_str(&text).unwrap();
+            let mut resp = match request["action"].as_str() {
+              Some("get_login_info") => json!({
+                "status": "ok",
+                "retcode": 0,
+                "data": { "user_id": 42, "nickname": "bot" },
+              }),
+              _ => json!({ "status": "failed", "retcode": 1404, "data": null }),
+            };
+            resp["echo"] = request["echo"].clone();
+            if ws.send(Message::Text(resp.to_string())).await.is_err() {
+              return;
+            }
+          }
+        });
+      }
+    });
+    format!("ws://{addr}")
+  }
+
+  fn endpoint(addr: String) -> Arc<QQBotEndpoint> {
+    QQBotEndpoint::new(QQBotConfig {
+      addr,
+      access_token: SecretString::from(""),
+      headers: HashMap::new(),
+      tls: TlsConfig::default(),
+      transport: Transport::OneBot,
+      qq: None,
+      media: MediaConfig {
+        baseurl: String::new(),
+        authorization_header: SecretString::from(""),
+        strategies: vec![],
+        local_root: None,
+        max_bytes: 0,
+      },
+      media_cache: None,
+      send_scheduler: SchedulerConfig::default(),
+      outbox: OutboxConfig {
+        path: ":memory:".into(),
+        ..Default::default()
+      },
+      api_timeouts: ApiTimeouts::default(),
+      event_bus: EventBusConfig::default(),
+      watchdog: WatchdogConfig {
+        auto_reconnect: false,
+        ..Default::default()
+      },
+    })
+    .unwrap()
+  }
+
+  #[tokio::test]
+  async fn starts_terminates_and_restarts() {
+    let endpoint = endpoint(fake_onebot().await);
+    assert_eq!(endpoint.state(), EndpointState::Idle);
+    let mut events = endpoint.events.subscribe(&ReplayOptions::default());
+
+    endpoint.clone().start(CallPolicy::default()).await.unwrap();
+    assert_eq!(endpoint.state(), EndpointState::Running);
+    assert_eq!(endpoint.self_id().as_deref(), Some("42"));
+    let connected = events.recv().await.unwrap();
+    assert!(matches!(connected.event, Event::Connected { ref qq, .. } if qq == "42"));
+
+    endpoint.terminate().await.unwrap();
+    assert_eq!(endpoint.state(), EndpointState::Stopped);
+    assert!(endpoint.get_client().is_err());
+    let closed = events.recv().await.unwrap();
+    assert!(matches!(closed.event, Event::Closed { terminated: true }));
+
+    // `Stopped` 之后可以再次启动。
+    endpoint.clone().start(CallPolicy::default()).await.unwrap();
+    assert_eq!(endpoint.state(), EndpointState::Running);
+    let connected = events.recv().await.unwrap();
+    assert!(matches!(connected.event, Event::Connected { .. }));
+    endpoint.terminate().await.unwrap();
+    assert_eq!(endpoint.state(), EndpointState::Stopped);
+  }
+
+  #[tokio::test]
+  async fn rejects_start_while_running() {
+    let endpoint = endpoint(fake_onebot().await);
+    // 没在运行时 `terminate` 什么也不做。
+    endpoint.terminate().await.unwrap();
+    assert_eq!(endpoint.state(), EndpointState::Idle);
+
+    endpoint.clone().start(CallPolicy::default()).await.unwrap();
+    let err = endpoint
+      .clone()
+      .start(CallPolicy::default())
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("Running"), "{err}");
+    assert_eq!(endpoint.state(), EndpointState::Running);
+    endpoint.terminate().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn terminate_cancels_connecting() {
+    // 只接 TCP、不做 WebSocket 握手的服务端，`start` 会一直停在 `Connecting`。
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = endpoint(format!("ws://{}", listener.local_addr().unwrap()));
+    let starting = tokio::spawn(endpoint.clone().start(CallPolicy::default()));
+    endpoint
+      .state
+      .subscribe()
+      .wait_for(|state| *state == EndpointState::Connecting)
+      .await
+      .unwrap();
+    let err = endpoint
+      .clone()
+      .start(CallPolicy::default())
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("Connecting"), "{err}");
+
+    endpoint.terminate().await.unwrap();
+    assert_eq!(endpoint.state(), EndpointState::Stopped);
+    let err = BridgeError::from(starting.await.unwrap().unwrap_err());
+    assert_eq!(err.code(), "CANCELLED");
+  }
+}
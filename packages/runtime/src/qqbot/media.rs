@@ -0,0 +1,430 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, anyhow, ensure};
+use base64::Engine;
+use futures_util::{Stream, TryStreamExt};
+use napi::tokio::{
+  self,
+  io::{AsyncWrite, AsyncWriteExt},
+};
+use napi_derive::napi;
+use onebot_v11::api::payload::{GetFile, GetImage};
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use super::{
+  QQBotEndpoint, client_proxy::CallPolicy, connection::Recent, error::BridgeError,
+  export::Mockv2MessageChain,
+};
+use crate::imaging::sniff::{self, SNIFF_LEN, UNKNOWN_MIME};
+
+/// 取图片的一种途径。`downloadImage.strategies` 按顺序尝试，前面的失败了再试后面的。
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaStrategy {
+  /// 直接下载收到消息时图片段里的 `url`。
+  SegmentUrl,
+  /// 读 `get_image` 返回的本地路径，实现和桥接共用一个卷时使用，需要设置 `localRoot`。
+  LocalPath,
+  /// 用 `get_file` 要 base64。
+  GetFile,
+  /// `get_image` 之后从 `baseurl` 上的 HTTP 服务下载。
+  Sidecar,
+}
+
+/// 不配置时先走原来的 HTTP 服务，不行再用图片段里的地址。
+pub fn default_strategies() -> Vec<MediaStrategy> {
+  vec![MediaStrategy::Sidecar, MediaStrategy::SegmentUrl]
+}
+
+#[derive(Debug, Clone)]
+pub struct MediaConfig {
+  pub baseurl: String,
+  pub authorization_header: secrecy::SecretString,
+  pub strategies: Vec<MediaStrategy>,
+  /// `LocalPath` 只读这个目录下的文件，相对路径也相对它解析。
+  pub local_root: Option<PathBuf>,
+  /// 单个文件的大小上限。
+  pub max_bytes: u64,
+}
+
+pub const DEFAULT_MAX_BYTES: u64 = 20 * 1024 * 1024;
+
+/// 取到的内容：在内存里，或者已经写进了调用方给的文件。
+pub(crate) enum Fetched {
+  Memory(Vec<u8>),
+  File { size: u64 },
+}
+
+/// 下载结果，类型按文件头判断。
+pub(crate) struct Media {
+  pub fetched: Fetched,
+  pub mime: &'static str,
+  pub dimensions: Option<(u32, u32)>,
+}
+
+impl Media {
+  pub fn size(&self) -> u64 {
+    match &self.fetched {
+      Fetched::Memory(buffer) => buffer.len() as u64,
+      Fetched::File { size } => *size,
+    }
+  }
+}
+
+/// 收到的图片 id → 图片段里的 `url`。
+pub(crate) type ImageUrls = std::sync::Mutex<Recent<String, String>>;
+
+/// 消息里要发出的图片，包括合并转发里的。
+fn outbound_images(chain: &[Mockv2MessageChain], buffers: &mut Vec<Vec<u8>>) {
+  for element in chain {
+    match element {
+      Mockv2MessageChain::ImageOutbound { buffer, .. } => buffers.push(buffer.0.clone()),
+      Mockv2MessageChain::Forward { node_list } => {
+        for node in node_list {
+          outbound_images(&node.message_chain, buffers);
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+/// 记下消息（包括合并转发里）图片段的地址，供 `SegmentUrl` 使用。
+pub(crate) fn remember_images(urls: &ImageUrls, chain: &[Mockv2MessageChain]) {
+  for element in chain {
+    match element {
+      Mockv2MessageChain::ImageInbound { url, image_id } => {
+        urls.lock().unwrap().insert(image_id.clone(), url.clone());
+      }
+      Mockv2MessageChain::Forward { node_list } => {
+        for node in node_list {
+          remember_images(urls, &node.message_chain);
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+impl QQBotEndpoint {
+  /// 把要发出的图片也放进缓存，之后从 QQ 收到同样的图时可以直接复用。
+  ///
+  /// 哈希、写文件和 SQLite 都是阻塞的，放到后台线程里做，不等它完成。
+  pub(crate) fn cache_outbound(&self, chain: &[Mockv2MessageChain]) {
+    let Some(cache) = self.media_cache.clone() else {
+      return;
+    };
+    let mut buffers = vec![];
+    outbound_images(chain, &mut buffers);
+    if buffers.is_empty() {
+      return;
+    }
+    tokio::task::spawn_blocking(move || {
+      for buffer in buffers {
+        if let Err(err) = cache.put(&buffer) {
+          warn!("Failed to write media cache: {err:#}");
+        }
+      }
+    });
+  }
+
+  /// 按配置的顺序尝试各个途径，每个失败的都记一条日志，全部失败时返回最后一个错误。
+  ///
+  /// 给了 `dest` 时写进这个文件，HTTP 下载边收边写，不占内存。
+  pub(crate) async fn fetch_image(
+    &self,
+    image_id: &str,
+    policy: CallPolicy,
+    dest: Option<&Path>,
+  ) -> anyhow::Result<Media> {
+    if let Some(cache) = self.media_cache.clone() {
+      let cached = {
+        let image_id = image_id.to_owned();
+        tokio::task::spawn_blocking(move || cache.get_image(&image_id)).await?
+      };
+      match cached {
+        Ok(Some(buffer)) => return deliver(Fetched::Memory(buffer), dest).await,
+        Ok(None) => {}
+        Err(err) => warn!(image_id, "Failed to read media cache: {err:#}"),
+      }
+    }
+    let mut image = None;
+    let mut last_err = None;
+    for &strategy in &self.config.media.strategies {
+      let result = match strategy {
+        MediaStrategy::SegmentUrl => self.fetch_segment_url(image_id, &policy, dest).await,
+        MediaStrategy::LocalPath => self.fetch_local_path(image_id, &policy, &mut image).await,
+        MediaStrategy::GetFile => self.fetch_get_file(image_id, &policy).await,
+        MediaStrategy::Sidecar => {
+          self
+            .fetch_sidecar(image_id, &policy, &mut image, dest)
+            .await
+        }
+      };
+      let fetched = match result {
+        Ok(fetched) => fetched,
+        Err(err) => {
+          warn!(image_id, ?strategy, "Failed to fetch image: {err:#}");
+          last_err = Some(err);
+          continue;
+        }
+      };
+      debug!(image_id, ?strategy, "Fetched image");
+      let media = deliver(fetched, dest).await?;
+      if let Some(cache) = self.media_cache.clone() {
+        let id = image_id.to_owned();
+        let cached = match (&media.fetched, dest) {
+          (Fetched::Memory(buffer), _) => {
+            let buffer = buffer.clone();
+            tokio::task::spawn_blocking(move || cache.put_image(&id, &buffer))
+          }
+          (Fetched::File { .. }, Some(path)) => {
+            let path = path.to_owned();
+            tokio::task::spawn_blocking(move || cache.put_image_file(&id, &path))
+          }
+          (Fetched::File { .. }, None) => unreachable!("fetched into a file without a path"),
+        };
+        if let Err(err) = cached.await? {
+          warn!(image_id, "Failed to write media cache: {err:#}");
+        }
+      }
+      return Ok(media);
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("No media strategy configured")))
+  }
+
+  /// `get_image` 的响应，几个途径共用，只请求一次。
+  async fn get_image(
+    &self,
+    image_id: &str,
+    policy: &CallPolicy,
+    image: &mut Option<Value>,
+  ) -> anyhow::Result<Value> {
+    if let Some(image) = image {
+      return Ok(image.clone());
+    }
+    let resp = self
+      .get_client()?
+      .with_policy(policy.clone())
+      .get_image(GetImage {
+        file: image_id.to_owned(),
+      })
+      .await?;
+    Ok(image.insert(serde_json::to_value(&resp)?).clone())
+  }
+
+  async fn fetch_segment_url(
+    &self,
+    image_id: &str,
+    policy: &CallPolicy,
+    dest: Option<&Path>,
+  ) -> anyhow::Result<Fetched> {
+    let url = self
+      .image_urls
+      .lock()
+      .unwrap()
+      .get(&image_id.to_owned())
+      .context("No segment url seen for this image")?;
+    self.download(Url::parse(&url)?, None, policy, dest).await
+  }
+
+  async fn fetch_local_path(
+    &self,
+    image_id: &str,
+    policy: &CallPolicy,
+    image: &mut Option<Value>,
+  ) -> anyhow::Result<Fetched> {
+    let root = self
+      .config
+      .media
+      .local_root
+      .as_deref()
+      .context("downloadImage.localRoot is not set")?;
+    let image = self.get_image(image_id, policy, image).await?;
+    let file = image["file"]
+      .as_str()
+      .context("get_image returned no file")?;
+    let buffer = read_under(root, Path::new(file), self.config.media.max_bytes).await?;
+    Ok(Fetched::Memory(buffer))
+  }
+
+  async fn fetch_get_file(&self, image_id: &str, policy: &CallPolicy) -> anyhow::Result<Fetched> {
+    let resp = self
+      .get_client()?
+      .with_policy(policy.clone())
+      .get_file(GetFile {
+        file_id: image_id.to_owned(),
+      })
+      .await?;
+    let resp = serde_json::to_value(&resp)?;
+    let data = resp["base64"]
+      .as_str()
+      .filter(|x| !x.is_empty())
+      .context("get_file returned no base64")?;
+    // 解码前按长度估算，避免先解出一大块内存。
+    let limit = self.config.media.max_bytes;
+    if data.len() as u64 / 4 * 3 > limit {
+      return Err(BridgeError::TooLarge { limit }.into());
+    }
+    let buffer = base64::engine::general_purpose::STANDARD.decode(data)?;
+    Ok(Fetched::Memory(buffer))
+  }
+
+  async fn fetch_sidecar(
+    &self,
+    image_id: &str,
+    policy: &CallPolicy,
+    image: &mut Option<Value>,
+    dest: Option<&Path>,
+  ) -> anyhow::Result<Fetched> {
+    let image = self.get_image(image_id, policy, image).await?;
+    let baseurl = Url::parse(&format!("{}/", &self.config.media.baseurl))?;
+    let file_url = self.dialect().image_url(&baseurl, &image)?;
+    // mirai 给的是图片的完整地址，凭据不能带到别的站点。
+    let authorization = (file_url.origin() == baseurl.origin())
+      .then(|| self.config.media.authorization_header.expose_secret());
+    self.download(file_url, authorization, policy, dest).await
+  }
+
+  /// 先看 `Content-Length`，再边收边数，超过上限就放弃。
+  async fn download(
+    &self,
+    url: Url,
+    authorization: Option<&str>,
+    policy: &CallPolicy,
+    dest: Option<&Path>,
+  ) -> anyhow::Result<Fetched> {
+    let limit = self.config.media.max_bytes;
+    let client = reqwest::Client::new();
+    policy
+      .run("download_image", async {
+        let mut request = client.get(url);
+        if let Some(authorization) = authorization {
+          request = request.header(reqwest::header::AUTHORIZATION, authorization);
+        }
+        let resp = request.send().await?.error_for_status()?;
+        if let Some(length) = resp.content_length()
+          && length > limit
+        {
+          return Err(BridgeError::TooLarge { limit }.into());
+        }
+        let mut stream = resp.bytes_stream();
+        match dest {
+          None => {
+            let mut buffer = vec![];
+            copy_limited(&mut stream, &mut buffer, limit).await?;
+            Ok(Fetched::Memory(buffer))
+          }
+          Some(path) => {
+            let size = write_file(path, async |file| {
+              copy_limited(&mut stream, file, limit).await
+            })
+            .await?;
+            Ok(Fetched::File { size })
+          }
+        }
+      })
+      .await
+  }
+}
+
+async fn copy_limited<S, B, W>(stream: &mut S, writer: &mut W, limit: u64) -> anyhow::Result<u64>
+where
+  S: Stream<Item = reqwest::Result<B>> + Unpin,
+  B: AsRef<[u8]>,
+  W: AsyncWrite + Unpin,
+{
+  let mut size = 0u64;
+  while let Some(chunk) = stream.try_next().await? {
+    let chunk = chunk.as_ref();
+    size += chunk.len() as u64;
+    if size > limit {
+      return Err(BridgeError::TooLarge { limit }.into());
+    }
+    writer.write_all(chunk).await?;
+  }
+  writer.flush().await?;
+  Ok(size)
+}
+
+/// 写到 `path` 旁边的临时文件，成功后再改名，失败时删掉。
+async fn write_file(
+  path: &Path,
+  write: impl AsyncFnOnce(&mut tokio::fs::File) -> anyhow::Result<u64>,
+) -> anyhow::Result<u64> {
+  let mut tmp = path.as_os_str().to_owned();
+  tmp.push(".part");
+  let tmp = PathBuf::from(tmp);
+  let result = async {
+    let mut file = tokio::fs::File::create(&tmp)
+      .await
+      .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    let size = write(&mut file).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(size)
+  }
+  .await;
+  if result.is_err() {
+    let _ = tokio::fs::remove_file(&tmp).await;
+  }
+  result
+}
+
+/// 按需写进文件，并按文件头判断类型和尺寸。
+async fn deliver(fetched: Fetched, dest: Option<&Path>) -> anyhow::Result<Media> {
+  let fetched = match (fetched, dest) {
+    (Fetched::Memory(buffer), Some(path)) => {
+      let size = write_file(path, async |file| {
+        file.write_all(&buffer).await?;
+        Ok(buffer.len() as u64)
+      })
+      .await?;
+      Fetched::File { size }
+    }
+    (fetched, _) => fetched,
+  };
+  let (mime, dimensions) = match (&fetched, dest) {
+    (Fetched::Memory(buffer), _) => (sniff::sniff_mime(buffer), sniff::dimensions(buffer)),
+    (Fetched::File { .. }, Some(path)) => {
+      let path = path.to_owned();
+      tokio::task::spawn_blocking(move || {
+        let mut header = [0u8; SNIFF_LEN];
+        let len = std::io::Read::read(&mut std::fs::File::open(&path)?, &mut header)?;
+        Ok::<_, std::io::Error>((
+          sniff::sniff_mime(&header[..len]),
+          sniff::file_dimensions(&path),
+        ))
+      })
+      .await??
+    }
+    (Fetched::File { .. }, None) => (None, None),
+  };
+  Ok(Media {
+    fetched,
+    mime: mime.unwrap_or(UNKNOWN_MIME),
+    dimensions,
+  })
+}
+
+/// 只读 `root` 下的文件，防止对面给出任意路径。
+async fn read_under(root: &Path, file: &Path, limit: u64) -> anyhow::Result<Vec<u8>> {
+  let root = tokio::fs::canonicalize(root)
+    .await
+    .with_context(|| format!("Invalid localRoot {}", root.display()))?;
+  let path = tokio::fs::canonicalize(root.join(file))
+    .await
+    .with_context(|| format!("Cannot resolve {}", file.display()))?;
+  ensure!(
+    path.starts_with(&root),
+    "{} is outside localRoot",
+    path.display()
+  );
+  if tokio::fs::metadata(&path).await?.len() > limit {
+    return Err(BridgeError::TooLarge { limit }.into());
+  }
+  Ok(tokio::fs::read(&path).await?)
+}
@@ -0,0 +1,309 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+
+use anyhow::Context;
+use rusqlite::{Connection, OptionalExtension, params};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use super::outbox::now_ms;
+
+#[derive(Debug, Clone)]
+pub struct MediaCacheConfig {
+  pub dir: PathBuf,
+  /// 缓存文件的总大小上限，超过后按最近使用时间淘汰。
+  pub max_bytes: u64,
+}
+
+pub fn media_hash(data: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(data))
+}
+
+/// 按 SHA-256 存放媒体文件的缓存目录，索引放在同目录的 SQLite 里。
+///
+/// 文件被淘汰后，QQ 图片 id 和 mxc URI 的对应关系仍然保留，上传过的媒体依然可以跳过重传。
+pub struct MediaCache {
+  config: MediaCacheConfig,
+  conn: Mutex<Connection>,
+}
+
+impl std::fmt::Debug for MediaCache {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("MediaCache")
+      .field("config", &self.config)
+      .finish_non_exhaustive()
+  }
+}
+
+impl MediaCache {
+  pub fn open(config: MediaCacheConfig) -> anyhow::Result<Self> {
+    std::fs::create_dir_all(&config.dir)
+      .with_context(|| format!("Failed to create media cache {}", config.dir.display()))?;
+    let conn = Connection::open(config.dir.join("index.sqlite"))?;
+    conn.execute_batch(
+      "PRAGMA journal_mode = WAL;
+      CREATE TABLE IF NOT EXISTS blobs (
+        hash TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        last_used INTEGER NOT NULL
+      );
+      CREATE INDEX IF NOT EXISTS blobs_last_used ON blobs (last_used);
+      CREATE TABLE IF NOT EXISTS image_ids (
+        image_id TEXT PRIMARY KEY,
+        hash TEXT NOT NULL
+      );
+      CREATE TABLE IF NOT EXISTS uploads (
+        hash TEXT PRIMARY KEY,
+        mxc TEXT NOT NULL
+      );",
+    )?;
+    Ok(Self {
+      config,
+      conn: Mutex::new(conn),
+    })
+  }
+
+  fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+    self.conn.lock().expect("media cache index poisoned")
+  }
+
+  fn path(&self, hash: &str) -> PathBuf {
+    self.config.dir.join(&hash[..2]).join(hash)
+  }
+
+  /// 比整个缓存还大的文件存进去也会马上被淘汰，还会挤掉其他文件，不如不存。
+  fn too_large(&self, hash: &str, size: u64) -> bool {
+    let too_large = size > self.config.max_bytes;
+    if too_large {
+      debug!("Not caching {hash}: {size} bytes is over the cache limit");
+    }
+    too_large
+  }
+
+  /// 存入一份媒体，返回它的哈希。阻塞，在异步代码里要放到 `spawn_blocking` 里调用。
+  pub fn put(&self, data: &[u8]) -> anyhow::Result<String> {
+    let hash = media_hash(data);
+    if self.too_large(&hash, data.len() as u64) {
+      return Ok(hash);
+    }
+    let path = self.path(&hash);
+    if !path.exists() {
+      write_atomic(&path, data)?;
+    }
+    let conn = self.conn();
+    conn.execute(
+      "INSERT INTO blobs (hash, size, last_used) VALUES (?1, ?2, ?3)
+      ON CONFLICT (hash) DO UPDATE SET last_used = excluded.last_used",
+      params![hash, data.len() as i64, now_ms()],
+    )?;
+    self.evict(&conn)?;
+    Ok(hash)
+  }
+
+  pub fn get(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    let conn = self.conn();
+    let touched = conn.execute(
+      "UPDATE blobs SET last_used = ?2 WHERE hash = ?1",
+      params![hash, now_ms()],
+    )?;
+    if touched == 0 {
+      return Ok(None);
+    }
+    match std::fs::read(self.path(hash)) {
+      Ok(data) => Ok(Some(data)),
+      // 文件被外部删掉了，索引跟着清理。
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        conn.execute("DELETE FROM blobs WHERE hash = ?1", params![hash])?;
+        Ok(None)
+      }
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  /// 存入从 QQ 下载的图片，同时记下图片 id。太大的不存文件，只记 id 对应的哈希。
+  pub fn put_image(&self, image_id: &str, data: &[u8]) -> anyhow::Result<String> {
+    let hash = self.put(data)?;
+    self.conn().execute(
+      "INSERT OR REPLACE INTO image_ids (image_id, hash) VALUES (?1, ?2)",
+      params![image_id, hash],
+    )?;
+    Ok(hash)
+  }
+
+  /// 和 `put_image` 相同，内容从文件里读，不整个载入内存。
+  pub fn put_image_file(&self, image_id: &str, file: &Path) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut std::fs::File::open(file)?, &mut hasher)?;
+    let hash = format!("{:x}", hasher.finalize());
+    if self.too_large(&hash, size) {
+      self.conn().execute(
+        "INSERT OR REPLACE INTO image_ids (image_id, hash) VALUES (?1, ?2)",
+        params![image_id, hash],
+      )?;
+      return Ok(hash);
+    }
+    let path = self.path(&hash);
+    if !path.exists() {
+      let dir = path.parent().context("Cache path has no parent")?;
+      std::fs::create_dir_all(dir)?;
+      let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+      std::fs::copy(file, &tmp)?;
+      std::fs::rename(&tmp, &path)?;
+    }
+    let conn = self.conn();
+    conn.execute(
+      "INSERT INTO blobs (hash, size, last_used) VALUES (?1, ?2, ?3)
+      ON CONFLICT (hash) DO UPDATE SET last_used = excluded.last_used",
+      params![hash, size as i64, now_ms()],
+    )?;
+    conn.execute(
+      "INSERT OR REPLACE INTO image_ids (image_id, hash) VALUES (?1, ?2)",
+      params![image_id, hash],
+    )?;
+    self.evict(&conn)?;
+    Ok(hash)
+  }
+
+  pub fn image_hash(&self, image_id: &str) -> anyhow::Result<Option<String>> {
+    Ok(
+      self
+        .conn()
+        .query_row(
+          "SELECT hash FROM image_ids WHERE image_id = ?1",
+          params![image_id],
+          |row| row.get(0),
+        )
+        .optional()?,
+    )
+  }
+
+  pub fn get_image(&self, image_id: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    match self.image_hash(image_id)? {
+      Some(hash) => self.get(&hash),
+      None => Ok(None),
+    }
+  }
+
+  pub fn mxc(&self, hash: &str) -> anyhow::Result<Option<String>> {
+    Ok(
+      self
+        .conn()
+        .query_row(
+          "SELECT mxc FROM uploads WHERE hash = ?1",
+          params![hash],
+          |row| row.get(0),
+        )
+        .optional()?,
+    )
+  }
+
+  pub fn set_mxc(&self, hash: &str, mxc: &str) -> anyhow::Result<()> {
+    self.conn().execute(
+      "INSERT OR REPLACE INTO uploads (hash, mxc) VALUES (?1, ?2)",
+      params![hash, mxc],
+    )?;
+    Ok(())
+  }
+
+  /// 总大小超过上限时，从最久没用的开始删。
+  fn evict(&self, conn: &Connection) -> anyhow::Result<()> {
+    let total: i64 = conn.query_row("SELECT COALESCE(SUM(size), 0) FROM blobs", [], |row| {
+      row.get(0)
+    })?;
+    let mut excess = total - self.config.max_bytes as i64;
+    if excess <= 0 {
+      return Ok(());
+    }
+    let oldest = {
+      let mut stmt = conn.prepare("SELECT hash, size FROM blobs ORDER BY last_used ASC")?;
+      let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+      })?;
+      let mut oldest = vec![];
+      for row in rows {
+        let (hash, size) = row?;
+        oldest.push(hash);
+        excess -= size;
+        if excess <= 0 {
+          break;
+        }
+      }
+      oldest
+    };
+    for hash in oldest {
+      if let Err(err) = std::fs::remove_file(self.path(&hash))
+        && err.kind() != std::io::ErrorKind::NotFound
+      {
+        warn!("Failed to evict {hash}: {err}");
+        continue;
+      }
+      conn.execute("DELETE FROM blobs WHERE hash = ?1", params![hash])?;
+      debug!("Evicted {hash} from media cache");
+    }
+    Ok(())
+  }
+}
+
+/// 先写临时文件再改名，避免读到写了一半的文件。
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+  let dir = path.parent().context("Cache path has no parent")?;
+  std::fs::create_dir_all(dir)?;
+  let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+  std::fs::write(&tmp, data)?;
+  std::fs::rename(&tmp, path)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+
+  fn cache(name: &str, max_bytes: u64) -> MediaCache {
+    let dir = std::env::temp_dir().join(format!("media-cache-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    MediaCache::open(MediaCacheConfig { dir, max_bytes }).unwrap()
+  }
+
+  /// `last_used` 是毫秒，隔开一点免得顺序打平。
+  fn tick() {
+    std::thread::sleep(Duration::from_millis(5));
+  }
+
+  #[test]
+  fn evicts_least_recently_used() {
+    let cache = cache("evict", 10);
+    let a = cache.put(b"aaaa").unwrap();
+    tick();
+    let b = cache.put_image("image-b", b"bbbb").unwrap();
+    tick();
+    assert_eq!(cache.get(&a).unwrap().as_deref(), Some(&b"aaaa"[..]));
+    tick();
+    let c = cache.put(b"cccc").unwrap();
+    assert!(cache.get(&b).unwrap().is_none());
+    assert!(cache.get(&a).unwrap().is_some());
+    assert!(cache.get(&c).unwrap().is_some());
+    // 文件没了，图片 id 和哈希的对应还在。
+    assert_eq!(cache.image_hash("image-b").unwrap(), Some(b));
+  }
+
+  #[test]
+  fn skips_blobs_larger_than_the_cache() {
+    let cache = cache("large", 4);
+    let small = cache.put(b"abc").unwrap();
+    let large = cache.put_image("image", b"0123456789").unwrap();
+    assert_eq!(large, media_hash(b"0123456789"));
+    assert!(cache.get(&large).unwrap().is_none());
+    assert_eq!(cache.image_hash("image").unwrap(), Some(large.clone()));
+
+    let file = cache.config.dir.join("large.bin");
+    std::fs::write(&file, b"0123456789").unwrap();
+    assert_eq!(cache.put_image_file("file", &file).unwrap(), large);
+    assert!(cache.get(&large).unwrap().is_none());
+    // 已有的文件没有被挤掉。
+    assert_eq!(cache.get(&small).unwrap().as_deref(), Some(&b"abc"[..]));
+  }
+}
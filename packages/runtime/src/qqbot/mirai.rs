@@ -0,0 +1,684 @@
+use std::sync::{
+  Arc, Mutex as StdMutex,
+  atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, bail};
+use napi::tokio::sync::{broadcast, oneshot};
+use onebot_v11::api::{payload::ApiPayload, resp::ApiResp};
+use secrecy::ExposeSecret;
+use serde_json::{Value, json};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tracing::debug;
+
+use super::{
+  connection::{Recent, Rejected, respond, unsupported},
+  outbox::now_ms,
+  ws::{ConnectOptions, Socket, extra_headers, parse_addr},
+};
+
+/// mirai-api-http 推送消息和事件时用的 syncId。
+const EVENT_SYNC_ID: &str = "-1";
+/// mirai 的撤回和取消息都要带上群号，图片也只能从收到的消息里拿到地址，这里记下最近见过的。
+struct Memory {
+  /// 消息 id → 群号。
+  groups: Recent<i64, i64>,
+  /// imageId → url。
+  images: Recent<String, String>,
+}
+
+impl Memory {
+  fn new() -> Self {
+    Self {
+      groups: Recent::new(),
+      images: Recent::new(),
+    }
+  }
+}
+
+/// mirai-api-http 2.x 的 WebSocket adapter。API 调用和事件都在这里翻译成 OneBot 的格式，
+/// 上层的消息链、事件转换照常工作。
+pub struct MiraiWs {
+  socket: Socket,
+  events: broadcast::Receiver<onebot_v11::Event>,
+  next_sync_id: AtomicU64,
+  qq: i64,
+  memory: Arc<StdMutex<Memory>>,
+}
+
+impl MiraiWs {
+  pub async fn connect(options: ConnectOptions<'_>) -> anyhow::Result<Self> {
+    let qq: i64 = options
+      .qq
+      .context("mirai-api-http needs the bot's qq")?
+      .parse()
+      .context("Invalid bot qq")?;
+    let mut url = parse_addr(options.addr)?;
+    // 只有 `/all` 同时推送消息和事件。
+    if url.path().is_empty() || url.path() == "/" {
+      url.set_path("/all");
+    }
+    let mut headers = extra_headers(options.headers)?;
+    headers.insert(
+      "verifyKey",
+      HeaderValue::from_str(options.access_token.expose_secret())?,
+    );
+    headers.insert("qq", HeaderValue::from(qq));
+
+    let memory = Arc::new(StdMutex::new(Memory::new()));
+    let (events_tx, events) = broadcast::channel(1024);
+    let (handshake_tx, handshake) = oneshot::channel();
+    let socket = Socket::open(&url, headers, options.tls, {
+      let memory = memory.clone();
+      let mut handshake_tx = Some(handshake_tx);
+      move |mut value| {
+        let frame = value.as_object_mut()?;
+        let sync_id = match frame.remove("syncId")? {
+          Value::String(x) => x,
+          other => other.to_string(),
+        };
+        let data = frame.remove("data").unwrap_or_default();
+        if sync_id.is_empty() {
+          // 连上后的第一帧，带着 session 或者认证失败的原因。
+          if let Some(tx) = handshake_tx.take() {
+            let _ = tx.send(data);
+          }
+          return None;
+        }
+        if sync_id != EVENT_SYNC_ID {
+          return Some((sync_id, data));
+        }
+        let Some(event) = event_to_onebot(qq, &data, &mut memory.lock().unwrap()) else {
+          debug!(kind = ?data["type"], "Ignored mirai event");
+          return None;
+        };
+        match serde_json::from_value(event) {
+          Ok(event) => {
+            let _ = events_tx.send(event);
+          }
+          Err(err) => debug!("Failed to translate mirai event: {err}"),
+        }
+        None
+      }
+    })
+    .await?;
+
+    let handshake = handshake
+      .await
+      .context("mirai-api-http closed the connection before the handshake")?;
+    let code = handshake["code"].as_i64().unwrap_or_default();
+    if code != 0 {
+      bail!(Rejected {
+        code,
+        msg: handshake["msg"].as_str().unwrap_or_default().to_owned(),
+      });
+    }
+    Ok(Self {
+      socket,
+      events,
+      next_sync_id: AtomicU64::new(1),
+      qq,
+      memory,
+    })
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<onebot_v11::Event> {
+    self.events.resubscribe()
+  }
+
+  pub async fn call_api(&self, payload: ApiPayload) -> anyhow::Result<ApiResp> {
+    let request = serde_json::to_value(&payload)?;
+    let action = request["action"].as_str().unwrap_or_default();
+    respond(self.translate(action, &request["params"]).await)
+  }
+
+  async fn command(
+    &self,
+    command: &str,
+    sub_command: Option<&str>,
+    content: Value,
+  ) -> anyhow::Result<Value> {
+    let sync_id = self
+      .next_sync_id
+      .fetch_add(1, Ordering::Relaxed)
+      .to_string();
+    let frame = json!({
+      "syncId": sync_id,
+      "command": command,
+      "subCommand": sub_command,
+      "content": content,
+    });
+    let data = self.socket.request(sync_id, frame).await?;
+    // 资料类的命令直接返回对象，不带 `code`。
+    let code = data["code"].as_i64().unwrap_or_default();
+    if code != 0 {
+      bail!(Rejected {
+        code,
+        msg: data["msg"].as_str().unwrap_or_default().to_owned(),
+      });
+    }
+    Ok(data)
+  }
+
+  /// 把一个 OneBot action 翻译成 mirai 命令，返回 OneBot 响应里的 `data`。
+  async fn translate(&self, action: &str, params: &Value) -> anyhow::Result<Value> {
+    Ok(match action {
+      "get_login_info" => {
+        let profile = self.command("botProfile", None, json!({})).await?;
+        json!({ "user_id": self.qq, "nickname": profile["nickname"] })
+      }
+      "get_status" => {
+        self.command("about", None, json!({})).await?;
+        json!({ "online": true, "good": true })
+      }
+      "get_version_info" => {
+        let about = self.command("about", None, json!({})).await?;
+        json!({
+          "app_name": "mirai-api-http",
+          "app_version": about["data"]["version"].as_str().unwrap_or_default(),
+          "protocol_version": "v11",
+        })
+      }
+      "get_friend_list" => {
+        let friends = self.command("friendList", None, json!({})).await?;
+        list(&friends["data"])
+          .map(|friend| {
+            json!({
+              "user_id": friend["id"],
+              "nickname": friend["nickname"],
+              "remark": friend["remark"],
+            })
+          })
+          .collect()
+      }
+      "get_group_list" => {
+        let groups = self.command("groupList", None, json!({})).await?;
+        list(&groups["data"])
+          .map(|group| {
+            json!({
+              "group_id": group["id"],
+              "group_name": group["name"],
+              "member_count": 0,
+              "max_member_count": 0,
+            })
+          })
+          .collect()
+      }
+      "get_group_member_info" => {
+        let member = self
+          .command(
+            "memberInfo",
+            Some("get"),
+            json!({ "target": params["group_id"], "memberId": params["user_id"] }),
+          )
+          .await?;
+        json!({
+          "group_id": params["group_id"],
+          "user_id": params["user_id"],
+          "nickname": member["memberName"],
+          "card": member["memberName"],
+          "sex": "unknown",
+          "age": 0,
+          "area": "",
+          "join_time": member["joinTimestamp"],
+          "last_sent_time": member["lastSpeakTimestamp"],
+          "level": "",
+          "role": role(&member["permission"]),
+          "unfriendly": false,
+          "title": member["specialTitle"],
+          "title_expire_time": 0,
+          "card_changeable": false,
+        })
+      }
+      "delete_msg" => {
+        let message_id = params["message_id"].as_i64().context("No message_id")?;
+        let group_id = self.group_of(message_id)?;
+        self
+          .command(
+            "recall",
+            None,
+            json!({ "target": group_id, "messageId": message_id }),
+          )
+          .await?;
+        Value::Null
+      }
+      "get_msg" => {
+        let message_id = params["message_id"].as_i64().context("No message_id")?;
+        let group_id = self.group_of(message_id)?;
+        let resp = self
+          .command(
+            "messageFromId",
+            None,
+            json!({ "target": group_id, "messageId": message_id }),
+          )
+          .await?;
+        let data = &resp["data"];
+        let mut memory = self.memory.lock().unwrap();
+        let (_, time, message) = chain_to_segments(self.qq, &data["messageChain"], &mut memory);
+        json!({
+          "time": time,
+          "message_type": "group",
+          "message_id": message_id,
+          "real_id": message_id,
+          "sender": sender(&data["sender"]),
+          "message": message,
+        })
+      }
+      "get_image" => {
+        let file = params["file"].as_str().context("No file")?;
+        let url = self.memory.lock().unwrap().images.get(&file.to_owned());
+        let url = url
+          .or_else(|| image_url(file))
+          .with_context(|| format!("Unknown image {file}"))?;
+        json!({ "file": url, "url": url })
+      }
+      "send_msg" | "send_group_msg" | "send_private_msg" => {
+        let private = action == "send_private_msg"
+          || (action == "send_msg" && params["message_type"] == "private");
+        let (command, target) = if private {
+          ("sendFriendMessage", &params["user_id"])
+        } else {
+          ("sendGroupMessage", &params["group_id"])
+        };
+        let (chain, quote) = segments_to_chain(&params["message"])?;
+        let mut content = json!({ "target": target, "messageChain": chain });
+        if let Some(quote) = quote {
+          content["quote"] = quote.into();
+        }
+        let resp = self.command(command, None, content).await?;
+        let message_id = resp["messageId"].as_i64().unwrap_or_default();
+        if !private && let Some(group_id) = target.as_i64() {
+          self
+            .memory
+            .lock()
+            .unwrap()
+            .groups
+            .insert(message_id, group_id);
+        }
+        json!({ "message_id": message_id })
+      }
+      // 合并转发在 mirai 里随消息一起下发，没有按 id 获取的接口。
+      other => return Err(unsupported(other)),
+    })
+  }
+
+  fn group_of(&self, message_id: i64) -> anyhow::Result<i64> {
+    self
+      .memory
+      .lock()
+      .unwrap()
+      .groups
+      .get(&message_id)
+      .with_context(|| format!("Message {message_id} is too old or not from a group"))
+  }
+}
+
+fn list(value: &Value) -> impl Iterator<Item = &Value> {
+  value.as_array().into_iter().flatten()
+}
+
+fn role(permission: &Value) -> &'static str {
+  match permission.as_str() {
+    Some("OWNER") => "owner",
+    Some("ADMINISTRATOR") => "admin",
+    _ => "member",
+  }
+}
+
+fn sender(sender: &Value) -> Value {
+  json!({
+    "user_id": sender["id"],
+    "nickname": sender["memberName"],
+    "card": sender["memberName"],
+    "role": role(&sender["permission"]),
+  })
+}
+
+/// 群图片的 imageId 形如 `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}.jpg`，可以直接拼出下载地址。
+fn image_url(image_id: &str) -> Option<String> {
+  let md5 = image_id
+    .strip_prefix('{')?
+    .split_once('}')?
+    .0
+    .replace('-', "");
+  (md5.len() == 32).then(|| format!("https://gchat.qpic.cn/gchatpic_new/0/0-0-{md5}/0?term=2"))
+}
+
+/// mirai 消息链转 OneBot 消息段，同时取出 `Source` 里的消息 id 和时间。
+fn chain_to_segments(self_id: i64, chain: &Value, memory: &mut Memory) -> (i64, i64, Vec<Value>) {
+  let mut message_id = 0;
+  let mut time = now_ms() / 1000;
+  let mut segments = vec![];
+  for element in list(chain) {
+    let segment = match element["type"].as_str().unwrap_or_default() {
+      "Source" => {
+        message_id = element["id"].as_i64().unwrap_or_default();
+        time = element["time"].as_i64().unwrap_or(time);
+        continue;
+      }
+      "Quote" => json!({ "type": "reply", "data": { "id": element["id"].to_string() } }),
+      "Plain" => json!({ "type": "text", "data": { "text": element["text"] } }),
+      "At" => json!({ "type": "at", "data": { "qq": element["target"].to_string() } }),
+      "AtAll" => json!({ "type": "at", "data": { "qq": "all" } }),
+      "Face" => json!({ "type": "face", "data": { "id": element["faceId"].to_string() } }),
+      "Image" | "FlashImage" => {
+        let image_id = element["imageId"].as_str().unwrap_or_default().to_owned();
+        if let Some(url) = element["url"].as_str() {
+          memory.images.insert(image_id.clone(), url.to_owned());
+        }
+        json!({ "type": "image", "data": { "file": image_id, "url": element["url"] } })
+      }
+      "Forward" => {
+        let content = list(&element["nodeList"])
+          .map(|node| {
+            let (_, _, message) = chain_to_segments(self_id, &node["messageChain"], memory);
+            json!({
+              "post_type": "message",
+              "message_type": "private",
+              "sub_type": "friend",
+              "time": node["time"],
+              "self_id": self_id,
+              "user_id": node["senderId"],
+              "message_id": 0,
+              "message": message,
+              "raw_message": "",
+              "font": 0,
+              "sender": { "user_id": node["senderId"], "nickname": node["senderName"] },
+            })
+          })
+          .collect::<Vec<_>>();
+        json!({ "type": "forward", "data": { "id": "", "content": content } })
+      }
+      other => json!({ "type": "text", "data": { "text": format!("[{other}]") } }),
+    };
+    segments.push(segment);
+  }
+  (message_id, time, segments)
+}
+
+/// OneBot 消息段转 mirai 消息链。`reply` 在 mirai 里是单独的 `quote` 参数，一并返回。
+fn segments_to_chain(segments: &Value) -> anyhow::Result<(Vec<Value>, Option<i64>)> {
+  let mut chain = vec![];
+  let mut quote = None;
+  let mut nodes = vec![];
+  for segment in list(segments) {
+    let data = &segment["data"];
+    match segment["type"].as_str().unwrap_or_default() {
+      "text" => chain.push(json!({ "type": "Plain", "text": data["text"] })),
+      "at" => match data["qq"].as_str() {
+        Some("all") => chain.push(json!({ "type": "AtAll" })),
+        Some(qq) => chain.push(json!({ "type": "At", "target": qq.parse::<i64>()? })),
+        None => chain.push(json!({ "type": "At", "target": data["qq"] })),
+      },
+      "face" => chain.push(json!({ "type": "Face", "faceId": as_i64(&data["id"])? })),
+      "reply" => quote = Some(as_i64(&data["id"])?),
+      "image" => {
+        let file = data["file"].as_str().unwrap_or_default();
+        let image = if let Some(base64) = file.strip_prefix("base64://") {
+          json!({ "type": "Image", "base64": base64 })
+        } else if file.starts_with("http://") || file.starts_with("https://") {
+          json!({ "type": "Image", "url": file })
+        } else {
+          json!({ "type": "Image", "imageId": file })
+        };
+        chain.push(image);
+      }
+      "node" => {
+        let (message_chain, _) = segments_to_chain(&data["content"])?;
+        nodes.push(json!({
+          "senderId": data["uin"],
+          "senderName": data["name"],
+          "time": now_ms() / 1000,
+          "messageChain": message_chain,
+        }));
+      }
+      other => bail!("Unsupported message segment {other} over mirai-api-http"),
+    }
+  }
+  if !nodes.is_empty() {
+    chain.push(json!({ "type": "Forward", "nodeList": nodes }));
+  }
+  Ok((chain, quote))
+}
+
+/// OneBot 的 id 有时是字符串，有时是数字。
+fn as_i64(value: &Value) -> anyhow::Result<i64> {
+  match value {
+    Value::String(x) => Ok(x.parse()?),
+    other => other
+      .as_i64()
+      .with_context(|| format!("Not an id: {other}")),
+  }
+}
+
+/// mirai 事件转 OneBot 事件，不关心的事件返回 `None`。
+fn event_to_onebot(self_id: i64, data: &Value, memory: &mut Memory) -> Option<Value> {
+  let lifecycle = |sub_type: &str| {
+    json!({
+      "post_type": "meta_event",
+      "meta_event_type": "lifecycle",
+      "sub_type": sub_type,
+      "time": now_ms() / 1000,
+      "self_id": self_id,
+    })
+  };
+  Some(match data["type"].as_str()? {
+    "GroupMessage" => {
+      let sender_info = &data["sender"];
+      let group_id = sender_info["group"]["id"].as_i64()?;
+      let (message_id, time, message) = chain_to_segments(self_id, &data["messageChain"], memory);
+      memory.groups.insert(message_id, group_id);
+      json!({
+        "post_type": "message",
+        "message_type": "group",
+        "sub_type": "normal",
+        "time": time,
+        "self_id": self_id,
+        "group_id": group_id,
+        "user_id": sender_info["id"],
+        "message_id": message_id,
+        "message": message,
+        "raw_message": "",
+        "font": 0,
+        "sender": sender(sender_info),
+      })
+    }
+    "GroupRecallEvent" => json!({
+      "post_type": "notice",
+      "notice_type": "group_recall",
+      "time": data["time"],
+      "self_id": self_id,
+      "group_id": data["group"]["id"],
+      "user_id": data["authorId"],
+      // 机器人自己撤回时 operator 为空。
+      "operator_id": data["operator"]["id"].as_i64().unwrap_or(self_id),
+      "message_id": data["messageId"],
+    }),
+    "BotOnlineEvent" | "BotReloginEvent" => lifecycle("enable"),
+    "BotOfflineEventActive" | "BotOfflineEventForce" | "BotOfflineEventDropped" => {
+      lifecycle("disable")
+    }
+    _ => return None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn translates_chain_to_segments() {
+    let mut memory = Memory::new();
+    let image_id = "{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.jpg";
+    let chain = json!([
+      { "type": "Source", "id": 42, "time": 1700000000 },
+      { "type": "Quote", "id": 41 },
+      { "type": "At", "target": 10001 },
+      { "type": "AtAll" },
+      { "type": "Plain", "text": "hi" },
+      { "type": "Face", "faceId": 14 },
+      { "type": "Image", "imageId": image_id, "url": "https://example.com/a.jpg" },
+      { "type": "Dice", "value": 6 },
+    ]);
+    let (message_id, time, segments) = chain_to_segments(1, &chain, &mut memory);
+    assert_eq!((message_id, time), (42, 1700000000));
+    assert_eq!(
+      Value::from(segments),
+      json!([
+        { "type": "reply", "data": { "id": "41" } },
+        { "type": "at", "data": { "qq": "10001" } },
+        { "type": "at", "data": { "qq": "all" } },
+        { "type": "text", "data": { "text": "hi" } },
+        { "type": "face", "data": { "id": "14" } },
+        { "type": "image", "data": { "file": image_id, "url": "https://example.com/a.jpg" } },
+        { "type": "text", "data": { "text": "[Dice]" } },
+      ])
+    );
+    // 收到过的图片记下地址。
+    assert_eq!(
+      memory.images.get(&image_id.to_owned()).as_deref(),
+      Some("https://example.com/a.jpg")
+    );
+  }
+
+  #[test]
+  fn translates_forward_nodes() {
+    let chain = json!([{
+      "type": "Forward",
+      "nodeList": [{
+        "senderId": 10002,
+        "senderName": "Alice",
+        "time": 1700000000,
+        "messageChain": [{ "type": "Plain", "text": "inner" }],
+      }],
+    }]);
+    let (message_id, _, segments) = chain_to_segments(1, &chain, &mut Memory::new());
+    assert_eq!(message_id, 0);
+    assert_eq!(segments[0]["type"], "forward");
+    let node = &segments[0]["data"]["content"][0];
+    assert_eq!(node["user_id"], 10002);
+    assert_eq!(node["sender"]["nickname"], "Alice");
+    assert_eq!(
+      node["message"],
+      json!([{ "type": "text", "data": { "text": "inner" } }])
+    );
+  }
+
+  #[test]
+  fn translates_segments_to_chain() {
+    let segments = json!([
+      { "type": "reply", "data": { "id": "41" } },
+      { "type": "text", "data": { "text": "hi" } },
+      { "type": "at", "data": { "qq": "10001" } },
+      { "type": "at", "data": { "qq": "all" } },
+      { "type": "face", "data": { "id": "14" } },
+      { "type": "image", "data": { "file": "base64://AAAA" } },
+      { "type": "image", "data": { "file": "https://example.com/a.jpg" } },
+      { "type": "image", "data": { "file": "{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.jpg" } },
+    ]);
+    let (chain, quote) = segments_to_chain(&segments).unwrap();
+    assert_eq!(quote, Some(41));
+    assert_eq!(
+      Value::from(chain),
+      json!([
+        { "type": "Plain", "text": "hi" },
+        { "type": "At", "target": 10001 },
+        { "type": "AtAll" },
+        { "type": "Face", "faceId": 14 },
+        { "type": "Image", "base64": "AAAA" },
+        { "type": "Image", "url": "https://example.com/a.jpg" },
+        { "type": "Image", "imageId": "{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.jpg" },
+      ])
+    );
+  }
+
+  #[test]
+  fn collects_nodes_into_one_forward() {
+    let node = |text: &str| {
+      json!({
+        "type": "node",
+        "data": { "uin": "10002", "name": "Alice", "content": [
+          { "type": "text", "data": { "text": text } },
+        ] },
+      })
+    };
+    let (chain, quote) = segments_to_chain(&json!([node("a"), node("b")])).unwrap();
+    assert_eq!(quote, None);
+    assert_eq!(chain.len(), 1);
+    assert_eq!(chain[0]["type"], "Forward");
+    let nodes = chain[0]["nodeList"].as_array().unwrap();
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(
+      nodes[1]["messageChain"],
+      json!([{ "type": "Plain", "text": "b" }])
+    );
+  }
+
+  #[test]
+  fn rejects_unsupported_segments() {
+    assert!(
+      segments_to_chain(&json!([{ "type": "record", "data": { "file": "a.silk" } }])).is_err()
+    );
+    assert!(segments_to_chain(&json!([{ "type": "reply", "data": { "id": "abc" } }])).is_err());
+    assert_eq!(as_i64(&json!("12")).unwrap(), 12);
+    assert_eq!(as_i64(&json!(12)).unwrap(), 12);
+    assert!(as_i64(&json!(null)).is_err());
+  }
+
+  #[test]
+  fn builds_image_url_from_id() {
+    assert_eq!(
+      image_url("{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.jpg").as_deref(),
+      Some("https://gchat.qpic.cn/gchatpic_new/0/0-0-01E9451B70EDEAE3B37C101F1EEBF5B5/0?term=2")
+    );
+    assert_eq!(image_url("{0123}.jpg"), None);
+    assert_eq!(image_url("a.jpg"), None);
+  }
+
+  #[test]
+  fn translates_events() {
+    let mut memory = Memory::new();
+    let message = json!({
+      "type": "GroupMessage",
+      "sender": {
+        "id": 10002,
+        "memberName": "Alice",
+        "permission": "ADMINISTRATOR",
+        "group": { "id": 20001, "name": "group" },
+      },
+      "messageChain": [
+        { "type": "Source", "id": 42, "time": 1700000000 },
+        { "type": "Plain", "text": "hi" },
+      ],
+    });
+    let event = event_to_onebot(1, &message, &mut memory).unwrap();
+    assert_eq!(event["message_type"], "group");
+    assert_eq!(event["group_id"], 20001);
+    assert_eq!(event["message_id"], 42);
+    assert_eq!(event["sender"]["role"], "admin");
+    // 之后撤回消息时要用到群号。
+    assert_eq!(memory.groups.get(&42), Some(20001));
+
+    let recall = json!({
+      "type": "GroupRecallEvent",
+      "authorId": 10002,
+      "messageId": 42,
+      "time": 1700000001,
+      "group": { "id": 20001 },
+      "operator": null,
+    });
+    let event = event_to_onebot(1, &recall, &mut memory).unwrap();
+    assert_eq!(event["notice_type"], "group_recall");
+    assert_eq!(event["operator_id"], 1);
+
+    let online = event_to_onebot(1, &json!({ "type": "BotOnlineEvent" }), &mut memory).unwrap();
+    assert_eq!(online["sub_type"], "enable");
+    let offline = json!({ "type": "BotOfflineEventDropped" });
+    assert_eq!(
+      event_to_onebot(1, &offline, &mut memory).unwrap()["sub_type"],
+      "disable"
+    );
+    let ignored = json!({ "type": "NudgeEvent" });
+    assert!(event_to_onebot(1, &ignored, &mut memory).is_none());
+  }
+}
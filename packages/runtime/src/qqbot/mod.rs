@@ -1,35 +1,42 @@
 use std::{
+  collections::HashMap,
   fmt::Debug,
-  str::FromStr,
-  sync::{Arc, atomic::AtomicBool},
+  sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU64},
+  },
 };
 
-use anyhow::{Context, bail};
-use async_broadcast::InactiveReceiver;
-use hostport::HostPort;
-use itertools::Itertools;
+use anyhow::Context;
 use napi::{
   threadsafe_function::ThreadsafeFunction,
   tokio::{
     self,
-    sync::{Mutex, OnceCell, RwLock, broadcast, oneshot},
+    sync::{Mutex, broadcast, watch},
   },
 };
 use napi_derive::napi;
-use onebot_v11::{
-  api::{
-    payload::{ApiPayload, GetFriendList},
-    resp::{ApiResp, ApiRespData},
-  },
-  connect::ws::{WsConfig, WsConnect},
-};
-use secrecy::{ExposeSecret, SecretBox, SecretString};
-use tracing::{debug, info, instrument, trace};
+use onebot_v11::api::resp::ApiResp;
+use secrecy::SecretString;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument, warn};
 
 use crate::qqbot::{
-  client_proxy::ClientProxy,
-  event::Event,
+  bus::{EventBus, EventBusConfig, EventBusMetrics, ReplayOptions},
+  client_proxy::{ApiTimeouts, CallPolicy, ClientProxy},
+  connection::{Connection, Recent, Transport},
+  dialect::Dialect,
+  error::BridgeError,
+  event::{Event, SequencedEvent},
   export::{GroupMemberInfo, message_to_msgchain},
+  health::{HealthState, WatchdogConfig},
+  lifecycle::EndpointState,
+  media::{ImageUrls, MediaConfig, remember_images},
+  media_cache::{MediaCache, MediaCacheConfig},
+  outbox::{Outbox, OutboxConfig},
+  scheduler::{SchedulerConfig, SendScheduler},
+  subscription::{EventFilter, EventStream, SubscribeOptions, Subscription},
+  ws::TlsConfig,
 };
 
 #[derive(Debug)]
@@ -37,19 +44,62 @@ use crate::qqbot::{
 pub struct QQBotConfig {
   addr: String,
   access_token: SecretString,
-  download_image_baseurl: String,
-  download_image_authorization_header: SecretString,
+  /// 握手时附加的请求头。
+  headers: HashMap<String, SecretString>,
+  tls: TlsConfig,
+  transport: Transport,
+  /// mirai-api-http 绑定的账号。
+  qq: Option<String>,
+  media: MediaConfig,
+  /// 为 `None` 时不缓存。
+  media_cache: Option<MediaCacheConfig>,
+  send_scheduler: SchedulerConfig,
+  outbox: OutboxConfig,
+  api_timeouts: ApiTimeouts,
+  event_bus: EventBusConfig,
+  watchdog: WatchdogConfig,
 }
 
+pub mod bus;
 pub mod client_proxy;
+pub mod connection;
+pub mod dialect;
+pub mod error;
 pub mod event;
+pub mod health;
+pub mod lifecycle;
+pub mod media;
+pub mod media_cache;
+pub mod mirai;
+pub mod outbox;
+pub mod pool;
+pub mod satori;
+pub mod scheduler;
+pub mod signal;
+pub mod subscription;
+pub mod ws;
 
 pub struct QQBotEndpoint {
   config: QQBotConfig,
-  started: Mutex<Option<oneshot::Receiver<()>>>,
-  terminated: Mutex<Option<(oneshot::Sender<()>, InactiveReceiver<event::Event>)>>,
-  client: OnceCell<Arc<WsConnect>>,
-  event_tx: async_broadcast::Sender<event::Event>,
+  state: watch::Sender<EndpointState>,
+  /// 当前这次运行的取消令牌，`Idle`/`Stopped` 时为空。
+  run: Mutex<Option<CancellationToken>>,
+  client: std::sync::RwLock<Option<Arc<Connection>>>,
+  /// 最近一次登录的 QQ 号。
+  self_id: std::sync::RwLock<Option<String>>,
+  /// 当前连接的 OneBot 实现，重连时重新识别。
+  dialect: std::sync::RwLock<Arc<Dialect>>,
+  image_urls: ImageUrls,
+  events: EventBus,
+  scheduler: SendScheduler,
+  outbox: Arc<Outbox>,
+  media_cache: Option<Arc<MediaCache>>,
+  outbox_running: AtomicBool,
+  health: std::sync::Mutex<HealthState>,
+  /// JS 每次调用 `start`/`terminate` 时加一，自动重连看到变化就放弃。
+  generation: AtomicU64,
+  /// 本次运行是被 `terminate` 停下的，`Closed` 里带上。
+  terminated: AtomicBool,
 }
 impl Debug for QQBotEndpoint {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -61,39 +111,83 @@ impl Debug for QQBotEndpoint {
 
 impl QQBotEndpoint {
   pub fn new(config: QQBotConfig) -> anyhow::Result<Arc<Self>> {
-    let (shutdown_tx, shutdown_rx) = oneshot::channel();
-    let (event_tx, event_rx) = async_broadcast::broadcast(1024);
+    let events = EventBus::new(config.event_bus.clone())?;
+    let scheduler = SendScheduler::new(config.send_scheduler.clone());
+    let outbox = Arc::new(Outbox::open(config.outbox.clone())?);
+    let media_cache = config
+      .media_cache
+      .clone()
+      .map(MediaCache::open)
+      .transpose()?
+      .map(Arc::new);
     let instance = Self {
       config,
-      started: Mutex::new(Some(shutdown_rx)),
-      terminated: Mutex::new(Some((shutdown_tx, event_rx.deactivate()))),
-      client: OnceCell::new(),
-      event_tx,
+      state: watch::Sender::new(EndpointState::Idle),
+      run: Mutex::new(None),
+      client: std::sync::RwLock::new(None),
+      self_id: std::sync::RwLock::new(None),
+      dialect: std::sync::RwLock::new(Arc::default()),
+      image_urls: ImageUrls::new(Recent::new()),
+      events,
+      scheduler,
+      outbox,
+      media_cache,
+      outbox_running: AtomicBool::new(false),
+      health: std::sync::Mutex::new(HealthState::default()),
+      generation: AtomicU64::new(0),
+      terminated: AtomicBool::new(false),
     };
 
     Ok(Arc::new(instance))
   }
 
-  pub fn get_client(&self) -> anyhow::Result<ClientProxy<WsConnect>> {
+  pub fn self_id(&self) -> Option<String> {
+    self.self_id.read().unwrap().clone()
+  }
+
+  pub fn dialect(&self) -> Arc<Dialect> {
+    self.dialect.read().unwrap().clone()
+  }
+
+  pub fn get_client(&self) -> anyhow::Result<ClientProxy<Connection>> {
     let client = self
       .client
-      .get()
-      .ok_or(anyhow::anyhow!("Client not initialized"))?;
-    Ok(ClientProxy::new(client.clone()))
+      .read()
+      .unwrap()
+      .clone()
+      .ok_or(BridgeError::NotConnected)?;
+    Ok(ClientProxy::new(client).with_policy(CallPolicy {
+      timeouts: self.config.api_timeouts,
+      ..Default::default()
+    }))
   }
 
-  async fn main_loop(&self, mut terminate: oneshot::Receiver<()>) -> anyhow::Result<()> {
+  async fn main_loop(&self, cancel: CancellationToken) -> anyhow::Result<()> {
     let client = self.get_client()?;
-    let mut subscriber = client.0.subscribe().await;
+    let mut subscriber = client.0.subscribe();
     'main: loop {
       tokio::select! {
           biased;
           maybe_event = subscriber.recv() => {
-            let ev = maybe_event?;
+            let ev = match maybe_event {
+              Ok(ev) => ev,
+              // 连接的缓冲区满了，丢的是还没读到的 OneBot 事件，连接本身没问题。
+              Err(broadcast::error::RecvError::Lagged(dropped)) => {
+                warn!(dropped, "OneBot events were dropped");
+                self
+                  .events
+                  .publish(Event::Lagged {
+                    dropped: dropped.min(u32::MAX as u64) as u32,
+                  })
+                  .await?;
+                continue;
+              }
+              Err(err) => return Err(err.into()),
+            };
+            self.health.lock().unwrap().record_event();
             self.handle_onebot_event(ev).await?;
           }
-          _ = &mut terminate => {
-            info!("QQBot terminating...");
+          _ = cancel.cancelled() => {
             break 'main;
           }
       }
@@ -107,12 +201,15 @@ impl QQBotEndpoint {
     match ev {
       onebot_v11::Event::Message(message) => match &message {
         onebot_v11::event::message::Message::GroupMessage(m) => 'handle: {
-          let Ok(mock_message) = message_to_msgchain(self.get_client()?, &message).await else {
+          let Ok(mock_message) =
+            message_to_msgchain(self.get_client()?, self.dialect(), &message).await
+          else {
             break 'handle;
           };
+          remember_images(&self.image_urls, &mock_message.1);
           self
-            .event_tx
-            .broadcast_direct(Event::GroupMessage {
+            .events
+            .publish(Event::GroupMessage {
               self_id: m.self_id.to_string(),
               group_id: m.group_id.to_string(),
               sender: GroupMemberInfo {
@@ -131,24 +228,42 @@ impl QQBotEndpoint {
         match meta {
           onebot_v11::event::meta::Meta::Lifecycle(lifecycle) => {
             debug!("Lifecycle: {lifecycle:?}");
+            let event = serde_json::to_value(&lifecycle)
+              .ok()
+              .and_then(|x| Event::from_lifecycle(&x));
+            if let Some(event) = event {
+              self.record_bot_status(&event);
+              self.events.publish(event).await?;
+            }
           }
           onebot_v11::event::meta::Meta::Heartbeat(heartbeat) => {
             debug!("Heartbeat: {heartbeat:?}");
+            if let Ok(heartbeat) = serde_json::to_value(&heartbeat) {
+              self.health.lock().unwrap().record_heartbeat(&heartbeat);
+            }
           }
         }
       }
       onebot_v11::Event::Notice(notice) => match &notice {
         onebot_v11::event::notice::Notice::GroupMessageRecall(m) => {
           self
-            .event_tx
-            .broadcast_direct(Event::GroupMessageDeleted {
+            .events
+            .publish(Event::GroupMessageDeleted {
               self_id: m.self_id.to_string(),
               group_id: m.group_id.to_string(),
               message_id: m.message_id.to_string(),
             })
             .await?;
         }
-        _ => {}
+        _ => {
+          let event = serde_json::to_value(&notice).ok().and_then(|x| {
+            Event::from_bot_offline_notice(&x).or_else(|| Event::from_member_notice(&x))
+          });
+          if let Some(event) = event {
+            self.record_bot_status(&event);
+            self.events.publish(event).await?;
+          }
+        }
       },
       onebot_v11::Event::Request(request) => {
         // requests. should not care.
@@ -159,83 +274,24 @@ impl QQBotEndpoint {
     }
     Ok(())
   }
-
-  pub async fn terminate(&self) -> anyhow::Result<()> {
-    let Some((shutdown_tx, _)) = self.terminated.lock().await.take() else {
-      bail!("already terminated!");
-    };
-    let core::result::Result::Ok(()) = shutdown_tx.send(()) else {
-      bail!("main thread terminated by error!");
-    };
-    Ok(())
-  }
 }
 
 impl QQBotEndpoint {
-  #[instrument]
-  pub async fn start(self: Arc<Self>) -> anyhow::Result<()> {
-    let Some(terminate) = self.started.lock().await.take() else {
-      bail!("Already started!");
-    };
-
-    info!("QQBot starting, connecting to {}", self.config.addr);
-
-    let parsed_addr = HostPort::from_str(&self.config.addr)?;
-
-    let onebot_config = WsConfig {
-      host: parsed_addr.host().to_owned(),
-      port: parsed_addr.port() as u16,
-      access_token: Some(self.config.access_token.expose_secret().to_owned()),
-      ..Default::default()
-    };
-
-    let client = WsConnect::new(onebot_config).await?;
-    self
-      .client
-      .set(client.clone())
-      .map_err(|_| anyhow::anyhow!("Failed to set client"))?;
-
-    // do a whoami.
-    let ApiRespData::GetLoginInfoResponse(login_info) = client
-      .call_api(ApiPayload::GetLoginInfo(
-        onebot_v11::api::payload::GetLoginInfo {},
-      ))
-      .await?
-      .data
-    else {
-      bail!("Failed to get login info");
-    };
-    self
-      .event_tx
-      .broadcast_direct(event::Event::Connected {
-        name: login_info.nickname,
-        qq: login_info.user_id.to_string(),
-      })
-      .await?;
-    tokio::spawn(async move { self.main_loop(terminate).await.expect("Main loop failed") });
-
-    info!("QQBot started!");
-
-    Ok(())
-  }
   pub fn register_callback(
     &self,
-    callback: ThreadsafeFunction<event::Event>,
-  ) -> anyhow::Result<()> {
-    let mut rx = self.event_tx.new_receiver();
-    tokio::spawn(async move {
-      while let Ok(next) = rx.recv().await {
-        callback.call(
-          Ok(next),
-          napi::threadsafe_function::ThreadsafeFunctionCallMode::Blocking,
-        );
-      }
-      callback.call(
-        Ok(event::Event::Closed),
-        napi::threadsafe_function::ThreadsafeFunctionCallMode::Blocking,
-      );
-    });
-    Ok(())
+    callback: ThreadsafeFunction<SequencedEvent>,
+    options: SubscribeOptions,
+  ) -> Subscription {
+    let rx = self
+      .events
+      .subscribe(&options.replay.clone().unwrap_or_default());
+    subscription::subscribe(rx, callback, options)
+  }
+  pub fn event_bus_metrics(&self) -> EventBusMetrics {
+    self.events.metrics()
+  }
+  pub fn events(&self, filter: EventFilter, replay: ReplayOptions) -> EventStream {
+    EventStream::new(self.events.subscribe(&replay), filter)
   }
 }
 
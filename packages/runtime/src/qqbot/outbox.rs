@@ -0,0 +1,400 @@
+use std::{
+  path::PathBuf,
+  sync::{Arc, Mutex, Weak},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use futures_util::future::join_all;
+use napi::tokio::{self, sync::Notify};
+use onebot_v11::{MessageSegment, api::payload::SendMsg};
+use rusqlite::{Connection, params};
+use tracing::{info, warn};
+
+use super::{QQBotEndpoint, error::BridgeError, event::Event, scheduler::SendPriority};
+
+pub const DEFAULT_PATH: &str = "outbox.sqlite3";
+
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+  /// SQLite 文件，默认在当前目录下。写成 `:memory:` 时只保存在内存里，进程重启后队列丢失。
+  pub path: PathBuf,
+  pub max_attempts: u32,
+  pub ttl: Duration,
+  pub retry_base: Duration,
+  pub retry_max: Duration,
+}
+
+impl Default for OutboxConfig {
+  fn default() -> Self {
+    Self {
+      path: DEFAULT_PATH.into(),
+      max_attempts: 10,
+      ttl: Duration::from_secs(60 * 60),
+      retry_base: Duration::from_secs(2),
+      retry_max: Duration::from_secs(5 * 60),
+    }
+  }
+}
+
+impl OutboxConfig {
+  fn backoff(&self, attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    self.retry_base.saturating_mul(factor).min(self.retry_max)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+  pub local_id: i64,
+  pub group_id: i64,
+  pub priority: SendPriority,
+  pub segments: Vec<MessageSegment>,
+  pub attempts: u32,
+  pub expires_at: i64,
+  pub next_attempt_at: i64,
+}
+
+pub(crate) fn now_ms() -> i64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis() as i64)
+    .unwrap_or_default()
+}
+
+/// 出站消息日志。消息先落盘再发送，发送成功后删除；失败的消息按退避策略重试，直到成功、过期或次数用尽。
+///
+/// 多个账号可以共用一个文件，每条消息记下入队时登录的账号，只由这个账号重放。
+pub struct Outbox {
+  config: OutboxConfig,
+  conn: Arc<Mutex<Connection>>,
+  /// 最近一次登录的账号。还没登录过时为空，这期间入队的消息归第一次登录的账号。
+  owner: Mutex<Option<String>>,
+  notify: Notify,
+}
+
+impl std::fmt::Debug for Outbox {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Outbox")
+      .field("config", &self.config)
+      .finish_non_exhaustive()
+  }
+}
+
+impl Outbox {
+  pub fn open(config: OutboxConfig) -> anyhow::Result<Self> {
+    let conn = Connection::open(&config.path)
+      .with_context(|| format!("Failed to open outbox journal {}", config.path.display()))?;
+    conn.execute_batch(
+      "PRAGMA journal_mode = WAL;
+      CREATE TABLE IF NOT EXISTS outbox (
+        local_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        group_id INTEGER NOT NULL,
+        priority TEXT NOT NULL,
+        segments TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL,
+        next_attempt_at INTEGER NOT NULL,
+        last_error TEXT
+      );",
+    )?;
+    // 早期的日志没有 `self_id` 列。
+    let has_owner = conn
+      .prepare("SELECT 1 FROM pragma_table_info('outbox') WHERE name = 'self_id'")?
+      .exists([])?;
+    if !has_owner {
+      conn.execute_batch("ALTER TABLE outbox ADD COLUMN self_id TEXT;")?;
+    }
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS outbox_owner ON outbox (self_id, group_id);")?;
+    Ok(Self {
+      config,
+      conn: Arc::new(Mutex::new(conn)),
+      owner: Mutex::new(None),
+      notify: Notify::new(),
+    })
+  }
+
+  fn owner(&self) -> Option<String> {
+    self.owner.lock().unwrap().clone()
+  }
+
+  /// SQLite 的调用会阻塞，放到阻塞线程池里执行，不占 tokio 的工作线程。
+  async fn with_conn<T: Send + 'static>(
+    &self,
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+  ) -> anyhow::Result<T> {
+    let conn = self.conn.clone();
+    tokio::task::spawn_blocking(move || f(&conn.lock().expect("outbox journal poisoned"))).await?
+  }
+
+  /// 登录成功后调用，之后只重放这个账号的消息。还没登录时入队的消息也归它。
+  pub async fn claim(&self, self_id: &str) -> anyhow::Result<()> {
+    *self.owner.lock().unwrap() = Some(self_id.to_owned());
+    let self_id = self_id.to_owned();
+    self
+      .with_conn(move |conn| {
+        conn.execute(
+          "UPDATE outbox SET self_id = ?1 WHERE self_id IS NULL",
+          params![self_id],
+        )?;
+        Ok(())
+      })
+      .await
+  }
+
+  pub async fn push(
+    &self,
+    group_id: i64,
+    priority: SendPriority,
+    segments: &[MessageSegment],
+  ) -> anyhow::Result<i64> {
+    let now = now_ms();
+    let priority = match priority {
+      SendPriority::Normal => "Normal",
+      SendPriority::Admin => "Admin",
+    };
+    let segments = serde_json::to_string(segments)?;
+    let expires_at = now.saturating_add(self.config.ttl.as_millis() as i64);
+    let owner = self.owner();
+    let local_id = self
+      .with_conn(move |conn| {
+        conn.execute(
+          "INSERT INTO outbox (group_id, priority, segments, created_at, expires_at, next_attempt_at, self_id)
+          VALUES (?1, ?2, ?3, ?4, ?5, ?4, ?6)",
+          params![group_id, priority, segments, now, expires_at, owner],
+        )?;
+        Ok(conn.last_insert_rowid())
+      })
+      .await?;
+    self.wake();
+    Ok(local_id)
+  }
+
+  /// 这个群还有没送达的消息。新消息要排在它们后面，不能直接发出去。
+  pub async fn has_pending(&self, group_id: i64) -> anyhow::Result<bool> {
+    let owner = self.owner();
+    self
+      .with_conn(move |conn| {
+        let pending = conn
+          .prepare("SELECT 1 FROM outbox WHERE self_id IS ?1 AND group_id = ?2")?
+          .exists(params![owner, group_id])?;
+        Ok(pending)
+      })
+      .await
+  }
+
+  /// 当前账号每个群最早的一条待发消息。同群后面的消息要等它成功或放弃后才会被取出，以保证顺序。
+  async fn heads(&self) -> anyhow::Result<Vec<OutboxEntry>> {
+    let Some(owner) = self.owner() else {
+      return Ok(vec![]);
+    };
+    self.with_conn(move |conn| heads(conn, &owner)).await
+  }
+
+  async fn remove(&self, local_id: i64) -> anyhow::Result<()> {
+    self
+      .with_conn(move |conn| {
+        conn.execute("DELETE FROM outbox WHERE local_id = ?1", params![local_id])?;
+        Ok(())
+      })
+      .await
+  }
+
+  async fn reschedule(
+    &self,
+    local_id: i64,
+    attempts: u32,
+    next_attempt_at: i64,
+    error: String,
+  ) -> anyhow::Result<()> {
+    self
+      .with_conn(move |conn| {
+        conn.execute(
+          "UPDATE outbox SET attempts = ?2, next_attempt_at = ?3, last_error = ?4 WHERE local_id = ?1",
+          params![local_id, attempts, next_attempt_at, error],
+        )?;
+        Ok(())
+      })
+      .await
+  }
+
+  /// 有新消息入队或者重新连上时调用，让重放任务立刻检查队列。
+  pub fn wake(&self) {
+    self.notify.notify_one();
+  }
+}
+
+fn heads(conn: &Connection, owner: &str) -> anyhow::Result<Vec<OutboxEntry>> {
+  let mut stmt = conn.prepare(
+    "SELECT local_id, group_id, priority, segments, attempts, expires_at, next_attempt_at
+    FROM outbox WHERE local_id IN (
+      SELECT MIN(local_id) FROM outbox WHERE self_id = ?1 GROUP BY group_id
+    )
+    ORDER BY local_id",
+  )?;
+  let rows = stmt.query_map(params![owner], |row| {
+    Ok((
+      row.get::<_, i64>(0)?,
+      row.get::<_, i64>(1)?,
+      row.get::<_, String>(2)?,
+      row.get::<_, String>(3)?,
+      row.get::<_, u32>(4)?,
+      row.get::<_, i64>(5)?,
+      row.get::<_, i64>(6)?,
+    ))
+  })?;
+  let mut entries = vec![];
+  for row in rows {
+    let (local_id, group_id, priority, segments, attempts, expires_at, next_attempt_at) = row?;
+    entries.push(OutboxEntry {
+      local_id,
+      group_id,
+      priority: match priority.as_str() {
+        "Admin" => SendPriority::Admin,
+        _ => SendPriority::Normal,
+      },
+      segments: serde_json::from_str(&segments)
+        .with_context(|| format!("Corrupted outbox entry {local_id}"))?,
+      attempts,
+      expires_at,
+      next_attempt_at,
+    });
+  }
+  Ok(entries)
+}
+
+impl QQBotEndpoint {
+  async fn deliver_outbox_entry(&self, entry: OutboxEntry) -> anyhow::Result<()> {
+    let outbox = &self.outbox;
+    let local_id = entry.local_id.to_string();
+    if entry.expires_at <= now_ms() {
+      outbox.remove(entry.local_id).await?;
+      self
+        .events
+        .publish(Event::OutboundFailed {
+          local_id,
+          code: "EXPIRED".to_owned(),
+          error: "expired before it could be delivered".to_owned(),
+        })
+        .await?;
+      return Ok(());
+    }
+    let client = self.get_client()?;
+    let policy = client.1.clone();
+    let result = policy
+      .run(
+        "send_msg",
+        self.scheduler.submit(
+          entry.group_id,
+          entry.priority,
+          client.send_msg(SendMsg {
+            message_type: onebot_v11::api::payload::MessageType::Group,
+            group_id: Some(entry.group_id),
+            auto_escape: false,
+            user_id: None,
+            message: entry.segments,
+          }),
+        ),
+      )
+      .await;
+    match result {
+      Ok(resp) => {
+        outbox.remove(entry.local_id).await?;
+        self
+          .events
+          .publish(Event::OutboundDelivered {
+            local_id,
+            message_id: resp.message_id.to_string(),
+          })
+          .await?;
+      }
+      Err(err) => {
+        let err = BridgeError::from(err);
+        let attempts = entry.attempts + 1;
+        let error = err.to_string();
+        if !err.is_retryable() || attempts >= outbox.config.max_attempts {
+          warn!(%local_id, attempts, %error, "giving up outbound message");
+          outbox.remove(entry.local_id).await?;
+          self
+            .events
+            .publish(Event::OutboundFailed {
+              local_id,
+              code: err.code().to_owned(),
+              error,
+            })
+            .await?;
+        } else {
+          let delay = outbox.config.backoff(attempts);
+          info!(%local_id, attempts, %error, ?delay, "outbound message will be retried");
+          outbox
+            .reschedule(
+              entry.local_id,
+              attempts,
+              now_ms().saturating_add(delay.as_millis() as i64),
+              error,
+            )
+            .await?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// 重放出站日志。只持有弱引用，endpoint 被释放后自动退出。
+  pub(crate) async fn outbox_loop(this: Weak<Self>, outbox: Arc<Outbox>) {
+    loop {
+      let next_wakeup = {
+        let Some(this) = this.upgrade() else {
+          break;
+        };
+        match this.replay_outbox().await {
+          Ok(next) => next,
+          Err(err) => {
+            warn!("outbox replay failed: {err:#}");
+            Some(now_ms().saturating_add(outbox.config.retry_base.as_millis() as i64))
+          }
+        }
+      };
+      let sleep = async {
+        match next_wakeup {
+          Some(at) => {
+            let delay = at.saturating_sub(now_ms()).max(0) as u64;
+            tokio::time::sleep(Duration::from_millis(delay)).await
+          }
+          None => std::future::pending().await,
+        }
+      };
+      tokio::select! {
+        _ = outbox.notify.notified() => {}
+        _ = sleep => {}
+      }
+    }
+  }
+
+  /// 发送所有到期的队首消息，返回下一条消息的到期时间。
+  async fn replay_outbox(&self) -> anyhow::Result<Option<i64>> {
+    loop {
+      if self.client.read().unwrap().is_none() {
+        // 等重连后 `wake` 再来。
+        return Ok(None);
+      }
+      let now = now_ms();
+      let (due, waiting): (Vec<_>, Vec<_>) = self
+        .outbox
+        .heads()
+        .await?
+        .into_iter()
+        .partition(|entry| entry.next_attempt_at <= now);
+      if due.is_empty() {
+        return Ok(waiting.iter().map(|entry| entry.next_attempt_at).min());
+      }
+      let deliveries = due
+        .into_iter()
+        .map(|entry| self.deliver_outbox_entry(entry));
+      for result in join_all(deliveries).await {
+        result?;
+      }
+    }
+  }
+}
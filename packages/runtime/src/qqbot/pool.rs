@@ -0,0 +1,256 @@
+use std::{
+  collections::{HashMap, HashSet},
+  sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::bail;
+use futures_util::future::join_all;
+use napi::{threadsafe_function::ThreadsafeFunction, tokio};
+use napi_derive::napi;
+use onebot_v11::api::payload::GetGroupList;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::qqbot::{
+  QQBotEndpoint as Inner,
+  bus::{EventBus, ReplayOptions, Subscriber},
+  error::{BridgeError, Coded},
+  event::SequencedEvent,
+  export::{
+    CallOptions, EventBusConfig, Mockv2MessageChain, QQBotConfig, QQBotEndpoint, SendGroupMsgResp,
+    bus_config, parse_qq_id,
+  },
+  scheduler::SendPriority,
+  subscription::{self, EventFilter, EventStream, SubscribeOptions, Subscription},
+};
+
+/// 多个 QQ 账号的连接池，按 self id 管理。所有账号的事件合并到连接池自己的事件总线上，
+/// 序号也由连接池重新分配。
+#[napi]
+pub struct QQBotPool {
+  /// (self id, 连接)，按加入的顺序。
+  members: RwLock<Vec<(String, Arc<Inner>)>>,
+  /// self id → 所在的群，由 `get_group_list` 得到。
+  groups: RwLock<HashMap<String, HashSet<i64>>>,
+  events: Arc<EventBus>,
+  /// self id → 停止转发这个账号事件的令牌。
+  forwarders: Mutex<HashMap<String, CancellationToken>>,
+}
+
+#[napi]
+impl QQBotPool {
+  /// 参数和 `QQBotConfig` 的 `eventBus`、`eventReplay` 含义相同，作用于连接池合并后的总线。
+  #[napi(constructor)]
+  pub fn new(event_bus: Option<EventBusConfig>, event_replay: Option<u32>) -> anyhow::Result<Self> {
+    Ok(Self {
+      members: Default::default(),
+      groups: Default::default(),
+      events: Arc::new(EventBus::new(bus_config(event_bus, event_replay))?),
+      forwarders: Default::default(),
+    })
+  }
+
+  /// 连接一个账号，连上后返回它的 self id。同一个账号不能重复加入。
+  #[napi]
+  pub async fn add(
+    &self,
+    config: QQBotConfig,
+    options: Option<CallOptions>,
+  ) -> anyhow::Result<String> {
+    let endpoint = QQBotEndpoint::new(config)?;
+    let inner = endpoint.inner().clone();
+    inner.clone().start(endpoint.policy(options)).await?;
+    let Some(self_id) = inner.self_id() else {
+      bail!("Connected without a self id");
+    };
+    let duplicate = {
+      let mut members = self.members.write().unwrap();
+      let duplicate = members.iter().any(|(id, _)| *id == self_id);
+      if !duplicate {
+        members.push((self_id.clone(), inner.clone()));
+      }
+      duplicate
+    };
+    if duplicate {
+      inner.terminate().await?;
+      bail!("Account {self_id} is already in the pool");
+    }
+    // 从头回放，连上之前的 `Connected` 也要转发。
+    let rx = inner.events.subscribe(&ReplayOptions {
+      since_seq: Some(0),
+      ..Default::default()
+    });
+    let stop = CancellationToken::new();
+    self
+      .forwarders
+      .lock()
+      .unwrap()
+      .insert(self_id.clone(), stop.clone());
+    tokio::spawn(forward_events(rx, self.events.clone(), stop));
+    if let Err(err) = self.refresh_member_groups(&self_id, &inner).await {
+      warn!("Failed to fetch group list of {self_id}: {err:#}");
+    }
+    info!("Account {self_id} joined the pool");
+    Ok(self_id)
+  }
+
+  /// 断开并移出一个账号。
+  #[napi]
+  pub async fn remove(&self, self_id: String) -> anyhow::Result<()> {
+    let endpoint = {
+      let mut members = self.members.write().unwrap();
+      let Some(index) = members.iter().position(|(id, _)| *id == self_id) else {
+        bail!("Account {self_id} is not in the pool");
+      };
+      members.remove(index).1
+    };
+    self.groups.write().unwrap().remove(&self_id);
+    let result = endpoint.terminate().await;
+    // 连接的后台任务还持有它，账号的事件总线不会自己关闭，要显式停下转发任务。
+    if let Some(stop) = self.forwarders.lock().unwrap().remove(&self_id) {
+      stop.cancel();
+    }
+    result
+  }
+
+  /// 按加入的顺序。
+  #[napi]
+  pub fn self_ids(&self) -> Vec<String> {
+    let members = self.members.read().unwrap();
+    members.iter().map(|(self_id, _)| self_id.clone()).collect()
+  }
+
+  /// 取出某个账号的连接，用来调用连接池没有代理的方法。
+  #[napi]
+  pub fn get(&self, self_id: String) -> Option<QQBotEndpoint> {
+    let members = self.members.read().unwrap();
+    let (_, endpoint) = members.iter().find(|(id, _)| *id == self_id)?;
+    Some(QQBotEndpoint::from_inner(endpoint.clone()))
+  }
+
+  /// 断开所有账号。
+  #[napi]
+  pub async fn terminate(&self) -> anyhow::Result<()> {
+    let endpoints = self.endpoints();
+    for result in join_all(endpoints.iter().map(|x| x.terminate())).await {
+      result?;
+    }
+    Ok(())
+  }
+
+  #[napi(
+    ts_args_type = "callback: ((err: Error | null, arg: SequencedEvent) => any), options?: SubscribeOptions | undefined | null"
+  )]
+  pub fn register_callback(
+    &self,
+    callback: ThreadsafeFunction<SequencedEvent>,
+    options: Option<SubscribeOptions>,
+  ) -> Subscription {
+    let options = options.unwrap_or_default();
+    let rx = self
+      .events
+      .subscribe(&options.replay.clone().unwrap_or_default());
+    subscription::subscribe(rx, callback, options)
+  }
+
+  /// 所有账号合并后的事件流。某个账号的 `Closed` 不会结束迭代，`for await` 需要自己 `break`。
+  #[napi]
+  pub fn events(&self, filter: Option<EventFilter>, replay: Option<ReplayOptions>) -> EventStream {
+    EventStream::merged(
+      self.events.subscribe(&replay.unwrap_or_default()),
+      filter.unwrap_or_default(),
+    )
+  }
+
+  /// 重新拉取所有账号的群列表。拉取失败的账号（比如断线了）跳过，保留上次的结果。
+  #[napi]
+  pub async fn refresh_groups(&self) {
+    let members = self.members.read().unwrap().clone();
+    for (self_id, endpoint) in members {
+      if let Err(err) = self.refresh_member_groups(&self_id, &endpoint).await {
+        warn!("Failed to fetch group list of {self_id}: {err:#}");
+      }
+    }
+  }
+
+  /// 用所在群里的账号发送。不知道哪个账号在这个群时先刷新一次群列表。
+  #[napi(ts_return_type = "Promise<SendGroupMsgResp>")]
+  pub async fn send_group_message(
+    &self,
+    group_id: String,
+    message: Vec<Mockv2MessageChain>,
+    priority: Option<SendPriority>,
+    options: Option<CallOptions>,
+  ) -> Coded<SendGroupMsgResp> {
+    let endpoint = match self.route(&group_id).await {
+      Ok(endpoint) => endpoint,
+      Err(err) => return Coded(Err(err)),
+    };
+    QQBotEndpoint::from_inner(endpoint)
+      .send_group_message(group_id, message, priority, options)
+      .await
+  }
+}
+
+impl QQBotPool {
+  fn endpoints(&self) -> Vec<Arc<Inner>> {
+    let members = self.members.read().unwrap();
+    members
+      .iter()
+      .map(|(_, endpoint)| endpoint.clone())
+      .collect()
+  }
+
+  async fn refresh_member_groups(&self, self_id: &str, endpoint: &Inner) -> anyhow::Result<()> {
+    let groups = endpoint
+      .get_client()?
+      .get_group_list(GetGroupList {})
+      .await?;
+    let groups = groups.into_iter().map(|group| group.group_id).collect();
+    // 拉取期间被移出的账号不再记录。
+    let members = self.members.read().unwrap();
+    if members.iter().any(|(id, _)| id == self_id) {
+      self
+        .groups
+        .write()
+        .unwrap()
+        .insert(self_id.to_owned(), groups);
+    }
+    Ok(())
+  }
+
+  async fn route(&self, group_id: &str) -> Result<Arc<Inner>, BridgeError> {
+    let group_id = parse_qq_id(group_id)?;
+    // 多个账号在同一个群时，先加入的账号优先。
+    let lookup = || {
+      let members = self.members.read().unwrap();
+      let groups = self.groups.read().unwrap();
+      members
+        .iter()
+        .find(|(self_id, _)| groups.get(self_id).is_some_and(|x| x.contains(&group_id)))
+        .map(|(_, endpoint)| endpoint.clone())
+    };
+    if let Some(endpoint) = lookup() {
+      return Ok(endpoint);
+    }
+    self.refresh_groups().await;
+    lookup().ok_or(BridgeError::NotMember { group_id })
+  }
+}
+
+/// 账号移出连接池时由 `stop` 结束。已经到达的事件（包括 `terminate` 发出的 `Closed`）先转发完。
+async fn forward_events(mut rx: Subscriber, pool: Arc<EventBus>, stop: CancellationToken) {
+  loop {
+    let event = tokio::select! {
+      biased;
+      event = rx.recv() => event,
+      _ = stop.cancelled() => return,
+    };
+    let Some(SequencedEvent { event, .. }) = event else {
+      return;
+    };
+    if pool.publish(event).await.is_err() {
+      return;
+    }
+  }
+}
@@ -0,0 +1,1354 @@
+use std::{
+  sync::{Arc, Mutex as StdMutex},
+  time::Duration,
+};
+
+use anyhow::{Context, anyhow, bail};
+use base64::Engine;
+use napi::tokio::sync::{broadcast, oneshot};
+use onebot_v11::api::{payload::ApiPayload, resp::ApiResp};
+use reqwest::{Url, header::CONTENT_TYPE};
+use secrecy::ExposeSecret;
+use serde_json::{Value, json};
+use tokio_tungstenite::tungstenite::http::{HeaderValue, header::AUTHORIZATION};
+use tracing::debug;
+
+use super::{
+  connection::{Recent, Rejected, respond, unsupported},
+  outbox::now_ms,
+  ws::{ConnectOptions, Socket, extra_headers, parse_addr, tls_config},
+};
+use crate::imaging::sniff::{self, SNIFF_LEN, UNKNOWN_MIME};
+
+/// Satori 信令。
+const OP_EVENT: i64 = 0;
+const OP_PING: i64 = 1;
+const OP_IDENTIFY: i64 = 3;
+const OP_READY: i64 = 4;
+/// 协议要求每 10 秒发一次 PING。
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Satori 的消息 id 是字符串，上层按 OneBot 用整数，这里分配本地 id 并记下对应关系。
+struct Memory {
+  next_id: i64,
+  /// 本地 id → (频道 id, Satori 消息 id)。
+  messages: Recent<i64, (String, String)>,
+  /// Satori 消息 id → 本地 id。
+  local_ids: Recent<String, i64>,
+}
+
+impl Memory {
+  fn new() -> Self {
+    Self {
+      next_id: 0,
+      messages: Recent::new(),
+      local_ids: Recent::new(),
+    }
+  }
+
+  fn local_id(&mut self, channel_id: &str, message_id: &str) -> i64 {
+    if let Some(id) = self.local_ids.get(&message_id.to_owned()) {
+      return id;
+    }
+    self.next_id += 1;
+    let id = self.next_id;
+    self
+      .messages
+      .insert(id, (channel_id.to_owned(), message_id.to_owned()));
+    self.local_ids.insert(message_id.to_owned(), id);
+    id
+  }
+
+  fn remote_id(&self, id: i64) -> anyhow::Result<(String, String)> {
+    self
+      .messages
+      .get(&id)
+      .with_context(|| format!("Message {id} is too old or unknown"))
+  }
+}
+
+/// Satori 协议：事件走 `/v1/events` 的 WebSocket，API 走 `POST /v1/{资源}.{方法}`。
+/// 和 mirai 一样在这里翻译成 OneBot 的格式。
+///
+/// 地址填 Satori 服务的根路径，比如 `ws://127.0.0.1:5140/satori`，本地的假服务用 `ws://` 即可。
+pub struct SatoriWs {
+  // 只为了让连接活着，断开后事件的发送端随读取任务释放。
+  _socket: Socket,
+  events: broadcast::Receiver<onebot_v11::Event>,
+  http: reqwest::Client,
+  api_base: Url,
+  platform: String,
+  self_id: String,
+  memory: Arc<StdMutex<Memory>>,
+}
+
+/// `http://host:port/satori` → `/satori/v1/`，已经带了 `/v1` 的不重复加。
+fn api_path(path: &str) -> String {
+  let path = path.trim_end_matches('/');
+  if path.ends_with("/v1") {
+    format!("{path}/")
+  } else {
+    format!("{path}/v1/")
+  }
+}
+
+impl SatoriWs {
+  pub async fn connect(options: ConnectOptions<'_>) -> anyhow::Result<Self> {
+    let url = parse_addr(options.addr)?;
+    let base_path = api_path(url.path());
+    let mut events_url = url.clone();
+    events_url.set_path(&format!("{base_path}events"));
+    let mut api_base = url;
+    api_base.set_path(&base_path);
+    api_base.set_query(None);
+    let scheme = if api_base.scheme() == "wss" {
+      "https"
+    } else {
+      "http"
+    };
+    api_base
+      .set_scheme(scheme)
+      .map_err(|_| anyhow!("Invalid Satori address"))?;
+
+    let mut headers = extra_headers(options.headers)?;
+    let token = options.access_token.expose_secret();
+    if !token.is_empty() {
+      headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {token}"))?,
+      );
+    }
+    let http = reqwest::Client::builder()
+      .default_headers(headers.clone())
+      .use_preconfigured_tls((*tls_config(options.tls)?).clone())
+      .build()?;
+
+    let memory = Arc::new(StdMutex::new(Memory::new()));
+    let (events_tx, events) = broadcast::channel(1024);
+    let (ready_tx, ready) = oneshot::channel();
+    let socket = Socket::open(&events_url, headers, options.tls, {
+      let memory = memory.clone();
+      let mut ready_tx = Some(ready_tx);
+      move |value| {
+        match value["op"].as_i64()? {
+          OP_READY => {
+            if let Some(tx) = ready_tx.take() {
+              let _ = tx.send(value["body"].clone());
+            }
+          }
+          OP_EVENT => {
+            let body = &value["body"];
+            let Some(event) = event_to_onebot(body, &mut memory.lock().unwrap()) else {
+              debug!(kind = ?body["type"], "Ignored Satori event");
+              return None;
+            };
+            match serde_json::from_value(event) {
+              Ok(event) => {
+                let _ = events_tx.send(event);
+              }
+              Err(err) => debug!("Failed to translate Satori event: {err}"),
+            }
+          }
+          // PONG 之类的不用处理。
+          _ => {}
+        }
+        None
+      }
+    })
+    .await?;
+    socket.send(json!({ "op": OP_IDENTIFY, "body": { "token": token } }))?;
+    socket.keepalive(PING_INTERVAL, json!({ "op": OP_PING }));
+
+    let ready = ready
+      .await
+      .context("Satori closed the connection before READY")?;
+    let logins = ready["logins"].as_array().cloned().unwrap_or_default();
+    let login = logins
+      .iter()
+      .find(|login| options.qq.is_none() || login_id(login) == options.qq)
+      .context("No matching login in Satori READY")?;
+    let self_id = login_id(login).context("Satori login has no user id")?;
+    let platform = login["platform"].as_str().unwrap_or_default().to_owned();
+    debug!(self_id, platform, "Satori ready");
+
+    Ok(Self {
+      self_id: self_id.to_owned(),
+      platform,
+      _socket: socket,
+      events,
+      http,
+      api_base,
+      memory,
+    })
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<onebot_v11::Event> {
+    self.events.resubscribe()
+  }
+
+  pub async fn call_api(&self, payload: ApiPayload) -> anyhow::Result<ApiResp> {
+    let request = serde_json::to_value(&payload)?;
+    let action = request["action"].as_str().unwrap_or_default();
+    respond(self.translate(action, &request["params"]).await)
+  }
+
+  async fn api(&self, method: &str, body: Value) -> anyhow::Result<Value> {
+    let resp = self
+      .http
+      .post(self.api_base.join(method)?)
+      .header(CONTENT_TYPE, "application/json")
+      // 新旧两版协议的请求头都带上。
+      .header("Satori-Platform", &self.platform)
+      .header("X-Platform", &self.platform)
+      .header("Satori-User-ID", &self.self_id)
+      .header("X-Self-ID", &self.self_id)
+      .body(body.to_string())
+      .send()
+      .await?;
+    let status = resp.status();
+    let text = resp.text().await?;
+    if !status.is_success() {
+      bail!(Rejected {
+        code: status.as_u16().into(),
+        msg: text,
+      });
+    }
+    if text.is_empty() {
+      return Ok(Value::Null);
+    }
+    Ok(serde_json::from_str(&text)?)
+  }
+
+  fn qq(&self) -> Value {
+    qq_id(&Value::from(self.self_id.as_str()))
+  }
+
+  /// 把一个 OneBot action 翻译成 Satori API，返回 OneBot 响应里的 `data`。
+  async fn translate(&self, action: &str, params: &Value) -> anyhow::Result<Value> {
+    Ok(match action {
+      "get_login_info" => {
+        let login = self.api("login.get", json!({})).await?;
+        json!({ "user_id": self.qq(), "nickname": login["user"]["name"] })
+      }
+      "get_status" => {
+        let login = self.api("login.get", json!({})).await?;
+        json!({ "online": login["status"] == 1, "good": true })
+      }
+      // 平台名比如 `chronocat`、`onebot`，当作版本号给出去。
+      "get_version_info" => json!({
+        "app_name": "satori",
+        "app_version": self.platform,
+        "protocol_version": "v11",
+      }),
+      "get_friend_list" => self
+        .paginate("friend.list", json!({}))
+        .await?
+        .iter()
+        .map(|friend| {
+          let user = if friend["user"].is_object() {
+            &friend["user"]
+          } else {
+            friend
+          };
+          json!({
+            "user_id": qq_id(&user["id"]),
+            "nickname": user["name"],
+            "remark": friend["nick"].as_str().unwrap_or_default(),
+          })
+        })
+        .collect(),
+      "get_group_list" => self
+        .paginate("guild.list", json!({}))
+        .await?
+        .iter()
+        .map(|guild| {
+          json!({
+            "group_id": qq_id(&guild["id"]),
+            "group_name": guild["name"],
+            "member_count": 0,
+            "max_member_count": 0,
+          })
+        })
+        .collect(),
+      "get_group_member_info" => {
+        let member = self
+          .api(
+            "guild.member.get",
+            json!({
+              "guild_id": id_string(&params["group_id"]),
+              "user_id": id_string(&params["user_id"]),
+            }),
+          )
+          .await?;
+        let user = &member["user"];
+        json!({
+          "group_id": params["group_id"],
+          "user_id": params["user_id"],
+          "nickname": user["name"],
+          "card": member["nick"].as_str().or(user["name"].as_str()).unwrap_or_default(),
+          "sex": "unknown",
+          "age": 0,
+          "area": "",
+          "join_time": member["joined_at"].as_i64().unwrap_or_default() / 1000,
+          "last_sent_time": 0,
+          "level": "",
+          "role": "member",
+          "unfriendly": false,
+          "title": "",
+          "title_expire_time": 0,
+          "card_changeable": false,
+        })
+      }
+      "delete_msg" => {
+        let message_id = as_i64(&params["message_id"])?;
+        let (channel_id, message_id) = self.memory.lock().unwrap().remote_id(message_id)?;
+        self
+          .api(
+            "message.delete",
+            json!({ "channel_id": channel_id, "message_id": message_id }),
+          )
+          .await?;
+        Value::Null
+      }
+      "get_msg" => {
+        let local_id = as_i64(&params["message_id"])?;
+        let (channel_id, message_id) = self.memory.lock().unwrap().remote_id(local_id)?;
+        let message = self
+          .api(
+            "message.get",
+            json!({ "channel_id": channel_id, "message_id": message_id }),
+          )
+          .await?;
+        let content = message["content"].as_str().unwrap_or_default();
+        let segments = {
+          let mut memory = self.memory.lock().unwrap();
+          content_to_segments(&self.qq(), &channel_id, content, &mut memory)
+        };
+        json!({
+          "time": message["created_at"].as_i64().map_or(now_ms(), |x| x) / 1000,
+          "message_type": "group",
+          "message_id": local_id,
+          "real_id": local_id,
+          "sender": sender(&message["user"], &message["member"]),
+          "message": segments,
+        })
+      }
+      // 图片段的 file 就是 Satori 给的 src。
+      "get_image" => {
+        let file = params["file"].as_str().context("No file")?;
+        json!({ "file": file, "url": file })
+      }
+      "send_msg" | "send_group_msg" | "send_private_msg" => {
+        let private = action == "send_private_msg"
+          || (action == "send_msg" && params["message_type"] == "private");
+        let channel_id = if private {
+          let user_id = id_string(&params["user_id"]);
+          let channel = self
+            .api("user.channel.create", json!({ "user_id": user_id }))
+            .await?;
+          channel["id"]
+            .as_str()
+            .context("No channel id for private chat")?
+            .to_owned()
+        } else {
+          id_string(&params["group_id"])
+        };
+        let content = {
+          let memory = self.memory.lock().unwrap();
+          segments_to_content(&params["message"], &memory)?
+        };
+        let sent = self
+          .api(
+            "message.create",
+            json!({ "channel_id": channel_id, "content": content }),
+          )
+          .await?;
+        // 一条消息可能被拆成多条发出，以第一条为准。
+        let message_id = sent[0]["id"].as_str().context("No message id")?;
+        let message_id = self
+          .memory
+          .lock()
+          .unwrap()
+          .local_id(&channel_id, message_id);
+        json!({ "message_id": message_id })
+      }
+      other => return Err(unsupported(other)),
+    })
+  }
+
+  /// 翻页取完一个列表接口。
+  async fn paginate(&self, method: &str, mut body: Value) -> anyhow::Result<Vec<Value>> {
+    let mut items = vec![];
+    loop {
+      let page = self.api(method, body.clone()).await?;
+      items.extend(list(&page["data"]).cloned());
+      match page["next"].as_str() {
+        Some(next) => body["next"] = next.into(),
+        None => return Ok(items),
+      }
+    }
+  }
+}
+
+fn list(value: &Value) -> impl Iterator<Item = &Value> {
+  value.as_array().into_iter().flatten()
+}
+
+fn login_id(login: &Value) -> Option<&str> {
+  login["user"]["id"].as_str().or(login["self_id"].as_str())
+}
+
+/// QQ 号是数字，其他平台的 id 原样保留。
+fn qq_id(id: &Value) -> Value {
+  match id.as_str().map(|x| (x, x.parse::<i64>())) {
+    Some((_, Ok(x))) => x.into(),
+    Some((x, Err(_))) => x.into(),
+    None => id.clone(),
+  }
+}
+
+fn id_string(id: &Value) -> String {
+  match id {
+    Value::String(x) => x.clone(),
+    other => other.to_string(),
+  }
+}
+
+/// OneBot 的 id 有时是字符串，有时是数字。
+fn as_i64(value: &Value) -> anyhow::Result<i64> {
+  match value {
+    Value::String(x) => Ok(x.parse()?),
+    other => other
+      .as_i64()
+      .with_context(|| format!("Not an id: {other}")),
+  }
+}
+
+fn sender(user: &Value, member: &Value) -> Value {
+  json!({
+    "user_id": qq_id(&user["id"]),
+    "nickname": user["name"],
+    "card": member["nick"].as_str().unwrap_or_default(),
+    "role": "member",
+  })
+}
+
+/// Satori 事件转 OneBot 事件，不关心的事件返回 `None`。
+fn event_to_onebot(event: &Value, memory: &mut Memory) -> Option<Value> {
+  let self_id = qq_id(&Value::from(
+    event["self_id"]
+      .as_str()
+      .or(event["login"]["user"]["id"].as_str())?,
+  ));
+  let time = event["timestamp"].as_i64().unwrap_or_else(now_ms) / 1000;
+  let group_id = qq_id(&event["guild"]["id"]);
+  let user = if event["message"]["user"].is_object() {
+    &event["message"]["user"]
+  } else {
+    &event["user"]
+  };
+  let member = if event["message"]["member"].is_object() {
+    &event["message"]["member"]
+  } else {
+    &event["member"]
+  };
+  let member_notice = |notice_type: &str, sub_type: &str| {
+    json!({
+      "post_type": "notice",
+      "notice_type": notice_type,
+      "sub_type": sub_type,
+      "time": time,
+      "self_id": self_id,
+      "group_id": group_id,
+      "user_id": qq_id(&user["id"]),
+      "operator_id": qq_id(&event["operator"]["id"]),
+    })
+  };
+  let lifecycle = |sub_type: &str| {
+    json!({
+      "post_type": "meta_event",
+      "meta_event_type": "lifecycle",
+      "sub_type": sub_type,
+      "time": time,
+      "self_id": self_id,
+    })
+  };
+  Some(match event["type"].as_str()? {
+    "message-created" => {
+      // 私聊没有 guild，上层只转发群消息。
+      if group_id.is_null() {
+        return None;
+      }
+      let channel_id = event["channel"]["id"].as_str()?;
+      let message = &event["message"];
+      let message_id = memory.local_id(channel_id, message["id"].as_str()?);
+      let content = message["content"].as_str().unwrap_or_default();
+      json!({
+        "post_type": "message",
+        "message_type": "group",
+        "sub_type": "normal",
+        "time": time,
+        "self_id": self_id,
+        "group_id": group_id,
+        "user_id": qq_id(&user["id"]),
+        "message_id": message_id,
+        "message": content_to_segments(&self_id, channel_id, content, memory),
+        "raw_message": "",
+        "font": 0,
+        "sender": sender(user, member),
+      })
+    }
+    "message-deleted" => {
+      if group_id.is_null() {
+        return None;
+      }
+      let channel_id = event["channel"]["id"].as_str()?;
+      let message_id = memory.local_id(channel_id, event["message"]["id"].as_str()?);
+      let user_id = qq_id(&user["id"]);
+      let operator_id = match qq_id(&event["operator"]["id"]) {
+        Value::Null => user_id.clone(),
+        x => x,
+      };
+      json!({
+        "post_type": "notice",
+        "notice_type": "group_recall",
+        "time": time,
+        "self_id": self_id,
+        "group_id": group_id,
+        "user_id": user_id,
+        "operator_id": operator_id,
+        "message_id": message_id,
+      })
+    }
+    "guild-member-added" => member_notice("group_increase", "approve"),
+    "guild-member-removed" => member_notice("group_decrease", "leave"),
+    "guild-member-updated" => {
+      let mut notice = member_notice("group_card", "");
+      notice["card_new"] = member["nick"].as_str().unwrap_or_default().into();
+      notice["card_old"] = "".into();
+      notice
+    }
+    // 状态 1 是 ONLINE。
+    "login-added" | "login-updated" if event["login"]["status"] == 1 => lifecycle("enable"),
+    "login-added" | "login-updated" | "login-removed" => lifecycle("disable"),
+    _ => return None,
+  })
+}
+
+/// Satori 消息元素，XML 的一个子集。
+#[derive(Debug, PartialEq)]
+enum Node {
+  Text(String),
+  Element {
+    tag: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+  },
+}
+
+impl Node {
+  fn attr(attrs: &[(String, String)], name: &str) -> Option<String> {
+    attrs
+      .iter()
+      .find(|(key, _)| key == name)
+      .map(|(_, value)| value.clone())
+  }
+}
+
+/// 认识的实体和 `&#123;`、`&#x1F600;` 这样的字符引用，其余的原样保留。
+fn unescape(text: &str) -> String {
+  let mut result = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('&') {
+    result.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let decoded = rest.find(';').and_then(|end| {
+      let c = match &rest[1..end] {
+        "quot" => '"',
+        "apos" => '\'',
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        entity => {
+          let code = entity.strip_prefix('#')?;
+          let code = match code.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => code.parse(),
+          };
+          char::from_u32(code.ok()?)?
+        }
+      };
+      Some((c, end))
+    });
+    match decoded {
+      Some((c, end)) => {
+        result.push(c);
+        rest = &rest[end + 1..];
+      }
+      None => {
+        result.push('&');
+        rest = &rest[1..];
+      }
+    }
+  }
+  result.push_str(rest);
+  result
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('"', "&quot;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
+
+/// 还没闭合的元素：(标签, 属性, 子节点)。
+type Open = (String, Vec<(String, String)>, Vec<Node>);
+
+/// 解析消息内容。不追求严格，标签没闭合的按到结尾处理，多余的闭合标签忽略。
+fn parse_content(content: &str) -> Vec<Node> {
+  // 栈底是根节点。
+  let mut stack: Vec<Open> = vec![(String::new(), vec![], vec![])];
+  let mut rest = content;
+  while !rest.is_empty() {
+    let Some(start) = rest.find('<') else {
+      stack.last_mut().unwrap().2.push(Node::Text(unescape(rest)));
+      break;
+    };
+    if start > 0 {
+      stack
+        .last_mut()
+        .unwrap()
+        .2
+        .push(Node::Text(unescape(&rest[..start])));
+    }
+    let Some(end) = rest[start..].find('>').map(|x| start + x) else {
+      stack
+        .last_mut()
+        .unwrap()
+        .2
+        .push(Node::Text(unescape(&rest[start..])));
+      break;
+    };
+    let tag = &rest[start + 1..end];
+    rest = &rest[end + 1..];
+
+    if let Some(name) = tag.strip_prefix('/') {
+      let name = name.trim();
+      if stack.len() > 1 && stack.iter().skip(1).any(|(tag, _, _)| tag == name) {
+        while let Some((tag, attrs, children)) = stack.pop() {
+          let closed = tag == name;
+          stack.last_mut().unwrap().2.push(Node::Element {
+            tag,
+            attrs,
+            children,
+          });
+          if closed {
+            break;
+          }
+        }
+      }
+      continue;
+    }
+    let (tag, self_closing) = match tag.strip_suffix('/') {
+      Some(tag) => (tag, true),
+      None => (tag, false),
+    };
+    let (name, attrs) = parse_tag(tag);
+    if self_closing {
+      stack.last_mut().unwrap().2.push(Node::Element {
+        tag: name,
+        attrs,
+        children: vec![],
+      });
+    } else {
+      stack.push((name, attrs, vec![]));
+    }
+  }
+  while stack.len() > 1 {
+    let (tag, attrs, children) = stack.pop().unwrap();
+    stack.last_mut().unwrap().2.push(Node::Element {
+      tag,
+      attrs,
+      children,
+    });
+  }
+  stack.pop().unwrap().2
+}
+
+/// `img src="..." cache` → (`img`, [(`src`, ...), (`cache`, "")])。
+fn parse_tag(tag: &str) -> (String, Vec<(String, String)>) {
+  let tag = tag.trim();
+  let (name, mut rest) = tag.split_at(tag.find(char::is_whitespace).unwrap_or(tag.len()));
+  let mut attrs = vec![];
+  loop {
+    rest = rest.trim_start();
+    if rest.is_empty() {
+      break;
+    }
+    let key_end = rest
+      .find(|c: char| c == '=' || c.is_whitespace())
+      .unwrap_or(rest.len());
+    let key = rest[..key_end].to_owned();
+    rest = &rest[key_end..];
+    let value = match rest.strip_prefix('=') {
+      Some(after) => {
+        let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'');
+        match quote {
+          Some(quote) => {
+            let after = &after[1..];
+            let end = after.find(quote).unwrap_or(after.len());
+            rest = after.get(end + 1..).unwrap_or_default();
+            unescape(&after[..end])
+          }
+          None => {
+            let end = after.find(char::is_whitespace).unwrap_or(after.len());
+            rest = &after[end..];
+            unescape(&after[..end])
+          }
+        }
+      }
+      None => String::new(),
+    };
+    attrs.push((key, value));
+  }
+  (name.to_owned(), attrs)
+}
+
+/// Satori 消息内容转 OneBot 消息段。
+fn content_to_segments(
+  self_id: &Value,
+  channel_id: &str,
+  content: &str,
+  memory: &mut Memory,
+) -> Vec<Value> {
+  let mut segments = vec![];
+  nodes_to_segments(
+    self_id,
+    channel_id,
+    &parse_content(content),
+    memory,
+    &mut segments,
+  );
+  segments
+}
+
+fn nodes_to_segments(
+  self_id: &Value,
+  channel_id: &str,
+  nodes: &[Node],
+  memory: &mut Memory,
+  segments: &mut Vec<Value>,
+) {
+  let text = |text: &str| json!({ "type": "text", "data": { "text": text } });
+  for node in nodes {
+    let (tag, attrs, children) = match node {
+      Node::Text(x) => {
+        segments.push(text(x));
+        continue;
+      }
+      Node::Element {
+        tag,
+        attrs,
+        children,
+      } => (tag.as_str(), attrs.as_slice(), children),
+    };
+    let attr = |name| Node::attr(attrs, name);
+    match tag {
+      "at" => {
+        let qq = match (attr("type").as_deref(), attr("id")) {
+          (Some("all" | "here"), _) => "all".to_owned(),
+          (_, Some(id)) => id,
+          _ => continue,
+        };
+        segments.push(json!({ "type": "at", "data": { "qq": qq } }));
+      }
+      "img" | "image" => {
+        let Some(src) = attr("src").or_else(|| attr("url")) else {
+          continue;
+        };
+        segments.push(json!({ "type": "image", "data": { "file": src, "url": src } }));
+      }
+      "quote" => match attr("id") {
+        Some(id) => {
+          let id = memory.local_id(channel_id, &id);
+          segments.push(json!({ "type": "reply", "data": { "id": id.to_string() } }));
+        }
+        // 没有 id 的引用把内容当成被引用的消息，这里没法对应，跳过。
+        None => continue,
+      },
+      "face" => {
+        let Some(id) = attr("id") else { continue };
+        segments.push(json!({ "type": "face", "data": { "id": id } }));
+      }
+      "message" if attr("forward").is_some() => {
+        let content = children
+          .iter()
+          .filter_map(|node| match node {
+            Node::Element {
+              tag,
+              attrs,
+              children,
+            } if tag == "message" => Some((attrs, children)),
+            _ => None,
+          })
+          .map(|(attrs, children)| {
+            let author = children.iter().find_map(|node| match node {
+              Node::Element { tag, attrs, .. } if tag == "author" => Some(attrs.as_slice()),
+              _ => None,
+            });
+            let author_attr = |name| {
+              author
+                .and_then(|attrs| Node::attr(attrs, name))
+                .or_else(|| Node::attr(attrs, name))
+            };
+            let user_id = qq_id(&author_attr("id").into());
+            let mut message = vec![];
+            let body = children
+              .iter()
+              .filter(|node| !matches!(node, Node::Element { tag, .. } if tag == "author"))
+              .collect::<Vec<_>>();
+            for node in body {
+              nodes_to_segments(
+                self_id,
+                channel_id,
+                std::slice::from_ref(node),
+                memory,
+                &mut message,
+              );
+            }
+            json!({
+              "post_type": "message",
+              "message_type": "private",
+              "sub_type": "friend",
+              "time": now_ms() / 1000,
+              "self_id": self_id,
+              "user_id": user_id,
+              "message_id": 0,
+              "message": message,
+              "raw_message": "",
+              "font": 0,
+              "sender": { "user_id": user_id, "nickname": author_attr("name") },
+            })
+          })
+          .collect::<Vec<_>>();
+        segments.push(json!({ "type": "forward", "data": { "id": "", "content": content } }));
+      }
+      "br" => segments.push(text("\n")),
+      "p" => {
+        nodes_to_segments(self_id, channel_id, children, memory, segments);
+        segments.push(text("\n"));
+      }
+      // 样式之类的标签只保留内容。
+      _ => nodes_to_segments(self_id, channel_id, children, memory, segments),
+    }
+  }
+}
+
+/// `base64://` 的图片转成 data URL，类型按内容判断。
+fn data_url(base64: &str) -> String {
+  // 解码开头一段就够判断类型，截在 4 的倍数上。
+  let head = base64::engine::general_purpose::STANDARD
+    .decode(base64.get(..SNIFF_LEN.div_ceil(3) * 4).unwrap_or(base64))
+    .unwrap_or_default();
+  let mime = sniff::sniff_mime(&head).unwrap_or(UNKNOWN_MIME);
+  format!("data:{mime};base64,{base64}")
+}
+
+/// OneBot 消息段转 Satori 消息内容。
+fn segments_to_content(segments: &Value, memory: &Memory) -> anyhow::Result<String> {
+  let mut content = String::new();
+  let mut nodes = String::new();
+  for segment in list(segments) {
+    let data = &segment["data"];
+    match segment["type"].as_str().unwrap_or_default() {
+      "text" => content.push_str(&escape(data["text"].as_str().unwrap_or_default())),
+      "at" => match id_string(&data["qq"]).as_str() {
+        "all" => content.push_str(r#"<at type="all"/>"#),
+        qq => content.push_str(&format!(r#"<at id="{}"/>"#, escape(qq))),
+      },
+      "face" => content.push_str(&format!(
+        r#"<face id="{}"/>"#,
+        escape(&id_string(&data["id"]))
+      )),
+      "reply" => {
+        let (_, message_id) = memory.remote_id(as_i64(&data["id"])?)?;
+        content.push_str(&format!(r#"<quote id="{}"/>"#, escape(&message_id)));
+      }
+      "image" => {
+        let file = data["file"].as_str().unwrap_or_default();
+        let src = match file.strip_prefix("base64://") {
+          Some(base64) => data_url(base64),
+          None => file.to_owned(),
+        };
+        content.push_str(&format!(r#"<img src="{}"/>"#, escape(&src)));
+      }
+      "node" => {
+        let inner = segments_to_content(&data["content"], memory)?;
+        nodes.push_str(&format!(
+          r#"<message><author id="{}" name="{}"/>{inner}</message>"#,
+          escape(&id_string(&data["uin"])),
+          escape(data["name"].as_str().unwrap_or_default()),
+        ));
+      }
+      other => bail!("Unsupported message segment {other} over Satori"),
+    }
+  }
+  if !nodes.is_empty() {
+    content.push_str(&format!("<message forward>{nodes}</message>"));
+  }
+  Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::HashMap, net::SocketAddr};
+
+  use futures_util::{SinkExt, StreamExt};
+  use secrecy::SecretString;
+  use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+  };
+  use tokio_tungstenite::tungstenite::Message;
+
+  use super::*;
+  use crate::qqbot::ws::TlsConfig;
+
+  fn text(text: &str) -> Node {
+    Node::Text(text.to_owned())
+  }
+
+  fn element(tag: &str, attrs: &[(&str, &str)], children: Vec<Node>) -> Node {
+    Node::Element {
+      tag: tag.to_owned(),
+      attrs: attrs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect(),
+      children,
+    }
+  }
+
+  fn text_segment(text: &str) -> Value {
+    json!({ "type": "text", "data": { "text": text } })
+  }
+
+  #[test]
+  fn unescapes_named_and_numeric_entities() {
+    assert_eq!(
+      unescape("&lt;a&gt; &amp;amp; &quot;&apos; &#123;&#x1F600;&#X41;"),
+      "<a> &amp; \"' {😀A"
+    );
+    // 不认识的、不合法的都原样保留。
+    assert_eq!(
+      unescape("&nbsp; a & b &#xZZ; &#1114112; &#55296; &"),
+      "&nbsp; a & b &#xZZ; &#1114112; &#55296; &"
+    );
+    assert_eq!(unescape(&escape(r#"<"&'>"#)), r#"<"&'>"#);
+  }
+
+  #[test]
+  fn parses_nested_elements_and_attributes() {
+    let nodes = parse_content(
+      r#"hi <b>bold <i>x</i></b><img src="a.png?x=1&amp;y=2" cache/><at id=123 name='A &#38; B'/>"#,
+    );
+    assert_eq!(
+      nodes,
+      vec![
+        text("hi "),
+        element(
+          "b",
+          &[],
+          vec![text("bold "), element("i", &[], vec![text("x")])]
+        ),
+        element("img", &[("src", "a.png?x=1&y=2"), ("cache", "")], vec![]),
+        element("at", &[("id", "123"), ("name", "A & B")], vec![]),
+      ]
+    );
+  }
+
+  #[test]
+  fn tolerates_unclosed_and_stray_tags() {
+    assert_eq!(
+      parse_content("a</b>c<p>d"),
+      vec![text("a"), text("c"), element("p", &[], vec![text("d")])]
+    );
+    assert_eq!(
+      parse_content("<b><i>x</b>y"),
+      vec![
+        element("b", &[], vec![element("i", &[], vec![text("x")])]),
+        text("y"),
+      ]
+    );
+    assert_eq!(parse_content("1 < 2"), vec![text("1 "), text("< 2")]);
+  }
+
+  #[test]
+  fn translates_content_to_segments() {
+    let mut memory = Memory::new();
+    let segments = content_to_segments(
+      &json!(42),
+      "c1",
+      r#"<quote id="m-9"/><at id="10001"/><at type="all"/> hi<img src="https://x/a.png"/><p>line</p>tail<br/><face id="14"/>"#,
+      &mut memory,
+    );
+    assert_eq!(
+      segments,
+      vec![
+        json!({ "type": "reply", "data": { "id": "1" } }),
+        json!({ "type": "at", "data": { "qq": "10001" } }),
+        json!({ "type": "at", "data": { "qq": "all" } }),
+        text_segment(" hi"),
+        json!({ "type": "image", "data": { "file": "https://x/a.png", "url": "https://x/a.png" } }),
+        text_segment("line"),
+        text_segment("\n"),
+        text_segment("tail"),
+        text_segment("\n"),
+        json!({ "type": "face", "data": { "id": "14" } }),
+      ]
+    );
+    assert_eq!(
+      memory.remote_id(1).unwrap(),
+      ("c1".to_owned(), "m-9".to_owned())
+    );
+  }
+
+  #[test]
+  fn translates_forwarded_messages() {
+    let segments = content_to_segments(
+      &json!(42),
+      "c1",
+      r#"<message forward><message><author id="10" name="A"/>hello</message><message id="x">bye</message></message>"#,
+      &mut Memory::new(),
+    );
+    assert_eq!(segments.len(), 1);
+    let content = &segments[0]["data"]["content"];
+    assert_eq!(content[0]["user_id"], 10);
+    assert_eq!(content[0]["sender"]["nickname"], "A");
+    assert_eq!(content[0]["message"], json!([text_segment("hello")]));
+    assert_eq!(content[1]["message"], json!([text_segment("bye")]));
+  }
+
+  #[test]
+  fn translates_segments_to_content() {
+    let mut memory = Memory::new();
+    let id = memory.local_id("c1", "m-9");
+    let png =
+      base64::engine::general_purpose::STANDARD.encode(b"\x89PNG\r\n\x1a\n0123456789abcdef");
+    let content = segments_to_content(
+      &json!([
+        { "type": "reply", "data": { "id": id.to_string() } },
+        { "type": "at", "data": { "qq": 10001 } },
+        { "type": "at", "data": { "qq": "all" } },
+        { "type": "text", "data": { "text": " a<b & \"c\"" } },
+        { "type": "image", "data": { "file": format!("base64://{png}") } },
+        { "type": "image", "data": { "file": "https://x/a.png?a=1&b=2" } },
+      ]),
+      &memory,
+    )
+    .unwrap();
+    assert_eq!(
+      content,
+      format!(
+        r#"<quote id="m-9"/><at id="10001"/><at type="all"/> a&lt;b &amp; &quot;c&quot;<img src="data:image/png;base64,{png}"/><img src="https://x/a.png?a=1&amp;b=2"/>"#
+      )
+    );
+
+    let forward = segments_to_content(
+      &json!([{ "type": "node", "data": { "uin": 10, "name": "A", "content": [
+        { "type": "text", "data": { "text": "hello" } },
+      ] } }]),
+      &memory,
+    )
+    .unwrap();
+    assert_eq!(
+      forward,
+      r#"<message forward><message><author id="10" name="A"/>hello</message></message>"#
+    );
+    assert!(
+      segments_to_content(&json!([{ "type": "reply", "data": { "id": 99 } }]), &memory).is_err()
+    );
+  }
+
+  #[test]
+  fn sniffs_data_url_mime() {
+    let encode = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+    assert!(data_url(&encode(b"GIF89a")).starts_with("data:image/gif;base64,"));
+    assert!(
+      data_url(&encode(b"\xff\xd8\xff\xe0 a longer jpeg body"))
+        .starts_with("data:image/jpeg;base64,")
+    );
+    assert!(data_url(&encode(b"plain text")).starts_with("data:application/octet-stream;base64,"));
+    assert!(data_url("not base64!").starts_with("data:application/octet-stream;base64,"));
+  }
+
+  fn satori_event(kind: &str, body: Value) -> Value {
+    let mut event = json!({
+      "id": 1,
+      "type": kind,
+      "platform": "chronocat",
+      "self_id": "42",
+      "timestamp": 1_700_000_000_000i64,
+      "channel": { "id": "123", "type": 0 },
+      "guild": { "id": "123" },
+      "user": { "id": "10001", "name": "Name" },
+      "member": { "nick": "Nick" },
+    });
+    for (key, value) in body.as_object().unwrap() {
+      event[key] = value.clone();
+    }
+    event
+  }
+
+  #[test]
+  fn translates_events() {
+    let mut memory = Memory::new();
+    let message = event_to_onebot(
+      &satori_event(
+        "message-created",
+        json!({ "message": { "id": "m-1", "content": "hi <at id=\"42\"/>" } }),
+      ),
+      &mut memory,
+    )
+    .unwrap();
+    assert_eq!(message["post_type"], "message");
+    assert_eq!(message["message_type"], "group");
+    assert_eq!(message["self_id"], 42);
+    assert_eq!(message["group_id"], 123);
+    assert_eq!(message["user_id"], 10001);
+    assert_eq!(message["message_id"], 1);
+    assert_eq!(message["time"], 1_700_000_000);
+    assert_eq!(message["sender"]["card"], "Nick");
+    assert_eq!(message["sender"]["nickname"], "Name");
+    assert_eq!(
+      message["message"],
+      json!([text_segment("hi "), { "type": "at", "data": { "qq": "42" } }])
+    );
+
+    let recall = event_to_onebot(
+      &satori_event("message-deleted", json!({ "message": { "id": "m-1" } })),
+      &mut memory,
+    )
+    .unwrap();
+    assert_eq!(recall["notice_type"], "group_recall");
+    assert_eq!(recall["message_id"], 1);
+    assert_eq!(recall["operator_id"], 10001);
+
+    let joined =
+      event_to_onebot(&satori_event("guild-member-added", json!({})), &mut memory).unwrap();
+    assert_eq!(joined["notice_type"], "group_increase");
+    assert_eq!(joined["user_id"], 10001);
+
+    let online = json!({ "login": { "status": 1, "user": { "id": "42" } } });
+    let offline = json!({ "login": { "status": 0, "user": { "id": "42" } } });
+    let lifecycle =
+      |body| event_to_onebot(&satori_event("login-updated", body), &mut Memory::new());
+    assert_eq!(lifecycle(online).unwrap()["sub_type"], "enable");
+    assert_eq!(lifecycle(offline).unwrap()["sub_type"], "disable");
+
+    // 私聊和不关心的事件不转。
+    let mut private = satori_event(
+      "message-created",
+      json!({ "message": { "id": "m-2", "content": "hi" } }),
+    );
+    private["guild"] = Value::Null;
+    assert!(event_to_onebot(&private, &mut memory).is_none());
+    assert!(event_to_onebot(&satori_event("reaction-added", json!({})), &mut memory).is_none());
+  }
+
+  /// 本地的假 Satori 服务：`GET` 是事件的 WebSocket，`POST` 是 API。
+  struct FakeSatori {
+    addr: SocketAddr,
+    /// 收到的 API 请求：(路径, 请求头, 请求体)。
+    requests: mpsc::UnboundedReceiver<(String, HashMap<String, String>, Value)>,
+    /// 推给客户端的事件。
+    events: mpsc::UnboundedSender<Value>,
+  }
+
+  impl FakeSatori {
+    async fn start() -> Self {
+      let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+      let addr = listener.local_addr().unwrap();
+      let (requests_tx, requests) = mpsc::unbounded_channel();
+      let (events, events_rx) = mpsc::unbounded_channel();
+      tokio::spawn(async move {
+        let mut events_rx = Some(events_rx);
+        loop {
+          let (stream, _) = listener.accept().await.unwrap();
+          let mut method = [0; 4];
+          stream.peek(&mut method).await.unwrap();
+          if &method == b"GET " {
+            tokio::spawn(Self::serve_events(stream, events_rx.take().unwrap()));
+          } else {
+            tokio::spawn(Self::serve_api(stream, requests_tx.clone()));
+          }
+        }
+      });
+      Self {
+        addr,
+        requests,
+        events,
+      }
+    }
+
+    async fn serve_events(stream: TcpStream, mut events: mpsc::UnboundedReceiver<Value>) {
+      let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+      while let Some(Ok(message)) = ws.next().await {
+        let Message::Text(text) = message else {
+          continue;
+        };
+        let frame: Value = serde_json::from_str(&text).unwrap();
+        if frame["op"] == OP_IDENTIFY {
+          assert_eq!(frame["body"]["token"], "token");
+          break;
+        }
+      }
+      let ready = json!({ "op": OP_READY, "body": { "logins": [
+        { "user": { "id": "7" }, "platform": "other" },
+        { "user": { "id": "42" }, "platform": "chronocat", "status": 1 },
+      ] } });
+      ws.send(Message::text(ready.to_string())).await.unwrap();
+      while let Some(event) = events.recv().await {
+        ws.send(Message::text(event.to_string())).await.unwrap();
+      }
+    }
+
+    async fn serve_api(
+      stream: TcpStream,
+      requests: mpsc::UnboundedSender<(String, HashMap<String, String>, Value)>,
+    ) {
+      let mut stream = BufReader::new(stream);
+      loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap() == 0 {
+          return;
+        }
+        let path = line.split(' ').nth(1).unwrap().to_owned();
+        let mut headers = HashMap::new();
+        loop {
+          let mut line = String::new();
+          stream.read_line(&mut line).await.unwrap();
+          let Some((key, value)) = line.trim_end().split_once(": ") else {
+            break;
+          };
+          headers.insert(key.to_ascii_lowercase(), value.to_owned());
+        }
+        let length = headers
+          .get("content-length")
+          .map_or(0, |x| x.parse().unwrap());
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+        let (status, response) = match path.rsplit('/').next().unwrap() {
+          "message.create" => ("200 OK", json!([{ "id": "m-1" }]).to_string()),
+          "message.delete" => ("200 OK", String::new()),
+          "guild.member.get" => (
+            "200 OK",
+            json!({
+              "user": { "id": "10001", "name": "Name" },
+              "nick": "Nick",
+              "joined_at": 1_700_000_000_000i64,
+            })
+            .to_string(),
+          ),
+          _ => ("404 Not Found", "no such method".to_owned()),
+        };
+        requests
+          .send((path, headers, serde_json::from_slice(&body).unwrap()))
+          .unwrap();
+        let response = format!(
+          "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{response}",
+          response.len()
+        );
+        stream
+          .get_mut()
+          .write_all(response.as_bytes())
+          .await
+          .unwrap();
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn talks_to_a_fake_satori_server() {
+    let mut server = FakeSatori::start().await;
+    let token = SecretString::from("token");
+    let satori = SatoriWs::connect(ConnectOptions {
+      addr: &format!("ws://{}/satori", server.addr),
+      access_token: &token,
+      headers: &HashMap::new(),
+      tls: &TlsConfig::default(),
+      qq: Some("42"),
+    })
+    .await
+    .unwrap();
+    let mut events = satori.subscribe();
+
+    let sent = satori
+      .translate(
+        "send_group_msg",
+        &json!({ "group_id": 123, "message": [{ "type": "text", "data": { "text": "a<b" } }] }),
+      )
+      .await
+      .unwrap();
+    assert_eq!(sent, json!({ "message_id": 1 }));
+    let (path, headers, body) = server.requests.recv().await.unwrap();
+    assert_eq!(path, "/satori/v1/message.create");
+    assert_eq!(headers["authorization"], "Bearer token");
+    assert_eq!(headers["satori-platform"], "chronocat");
+    assert_eq!(headers["satori-user-id"], "42");
+    assert_eq!(body, json!({ "channel_id": "123", "content": "a&lt;b" }));
+
+    let deleted = satori
+      .translate("delete_msg", &json!({ "message_id": 1 }))
+      .await
+      .unwrap();
+    assert_eq!(deleted, Value::Null);
+    let (path, _, body) = server.requests.recv().await.unwrap();
+    assert_eq!(path, "/satori/v1/message.delete");
+    assert_eq!(body, json!({ "channel_id": "123", "message_id": "m-1" }));
+
+    let member = satori
+      .translate(
+        "get_group_member_info",
+        &json!({ "group_id": 123, "user_id": 10001 }),
+      )
+      .await
+      .unwrap();
+    assert_eq!(member["card"], "Nick");
+    assert_eq!(member["nickname"], "Name");
+    assert_eq!(member["join_time"], 1_700_000_000);
+    let (path, _, body) = server.requests.recv().await.unwrap();
+    assert_eq!(path, "/satori/v1/guild.member.get");
+    assert_eq!(body, json!({ "guild_id": "123", "user_id": "10001" }));
+
+    // 服务端拒绝的请求带着状态码报错。
+    let err = satori
+      .translate("get_status", &json!({}))
+      .await
+      .unwrap_err();
+    assert_eq!(err.downcast_ref::<Rejected>().unwrap().code, 404);
+
+    server
+      .events
+      .send(json!({ "op": OP_EVENT, "body": satori_event(
+        "message-created",
+        json!({ "message": { "id": "m-2", "content": "hello" } }),
+      ) }))
+      .unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+      .await
+      .unwrap()
+      .unwrap();
+    let onebot_v11::Event::Message(onebot_v11::event::message::Message::GroupMessage(message)) =
+      event
+    else {
+      panic!("Not a group message: {event:?}");
+    };
+    assert_eq!(message.group_id, 123);
+    assert_eq!(message.user_id, 10001);
+    assert_eq!(i64::from(message.message_id), 2);
+  }
+}
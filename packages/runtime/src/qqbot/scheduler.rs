@@ -0,0 +1,487 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  future::Future,
+  time::Duration,
+};
+
+use anyhow::Context;
+use futures_util::future::BoxFuture;
+use napi::tokio::{
+  self,
+  sync::{mpsc, oneshot},
+  time::Instant,
+};
+use napi_derive::napi;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, trace};
+
+/// 出站消息优先级。管理员消息会插队到其他群的普通消息之前，但不会打乱同一个群内的顺序。
+#[napi(string_enum)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SendPriority {
+  #[default]
+  Normal,
+  Admin,
+}
+
+/// 令牌桶参数：每 `interval` 补充一个令牌，最多攒 `burst` 个。
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+  pub burst: u32,
+  pub interval: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+  pub per_group: RateLimit,
+  pub global: RateLimit,
+  pub max_in_flight: usize,
+}
+
+impl Default for SchedulerConfig {
+  fn default() -> Self {
+    Self {
+      per_group: RateLimit {
+        burst: 3,
+        interval: Duration::from_millis(1000),
+      },
+      global: RateLimit {
+        burst: 5,
+        interval: Duration::from_millis(300),
+      },
+      max_in_flight: 4,
+    }
+  }
+}
+
+struct TokenBucket {
+  limit: RateLimit,
+  tokens: f64,
+  last: Instant,
+}
+
+impl TokenBucket {
+  fn new(limit: RateLimit, now: Instant) -> Self {
+    Self {
+      limit,
+      tokens: limit.burst.max(1) as f64,
+      last: now,
+    }
+  }
+  fn refill(&mut self, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.last);
+    let refilled = elapsed.as_secs_f64() / self.limit.interval.as_secs_f64().max(f64::EPSILON);
+    self.tokens = (self.tokens + refilled).min(self.limit.burst.max(1) as f64);
+    self.last = now;
+  }
+  /// 距离下一个令牌可用还需要等待的时间。
+  fn wait_time(&mut self, now: Instant) -> Duration {
+    self.refill(now);
+    if self.tokens >= 1.0 {
+      Duration::ZERO
+    } else {
+      self.limit.interval.mul_f64(1.0 - self.tokens)
+    }
+  }
+  fn take(&mut self) {
+    self.tokens -= 1.0;
+  }
+  /// 补满了的桶和新建的没有区别。
+  fn is_full(&mut self, now: Instant) -> bool {
+    self.refill(now);
+    self.tokens >= self.limit.burst.max(1) as f64
+  }
+}
+
+struct Job {
+  priority: SendPriority,
+  run: BoxFuture<'static, ()>,
+  /// 调用方超时或者取消后不再等结果，还在排队的消息就不发了。
+  abandoned: CancellationToken,
+}
+
+struct GroupQueue {
+  jobs: VecDeque<Job>,
+  bucket: TokenBucket,
+  in_flight: bool,
+}
+
+impl GroupQueue {
+  fn priority(&self) -> SendPriority {
+    self
+      .jobs
+      .iter()
+      .map(|job| job.priority)
+      .max()
+      .unwrap_or_default()
+  }
+}
+
+struct Worker {
+  config: SchedulerConfig,
+  rx: mpsc::UnboundedReceiver<(i64, Job)>,
+  groups: HashMap<i64, GroupQueue>,
+  // 轮转顺序，保证同优先级的群之间公平。
+  rotation: VecDeque<i64>,
+  global: TokenBucket,
+  in_flight: usize,
+}
+
+impl Worker {
+  fn enqueue(&mut self, group_id: i64, job: Job) {
+    let per_group = self.config.per_group;
+    let queue = self.groups.entry(group_id).or_insert_with(|| GroupQueue {
+      jobs: VecDeque::new(),
+      bucket: TokenBucket::new(per_group, Instant::now()),
+      in_flight: false,
+    });
+    queue.jobs.push_back(job);
+    if !self.rotation.contains(&group_id) {
+      self.rotation.push_back(group_id);
+    }
+  }
+
+  /// 丢掉调用方已经放弃的消息，以及没有待发消息、令牌也已补满的群，否则发过消息的群会一直留在表里。
+  fn prune(&mut self) {
+    let now = Instant::now();
+    for queue in self.groups.values_mut() {
+      queue.jobs.retain(|job| !job.abandoned.is_cancelled());
+    }
+    let groups = &self.groups;
+    self
+      .rotation
+      .retain(|group_id| !groups[group_id].jobs.is_empty());
+    self
+      .groups
+      .retain(|_, queue| queue.in_flight || !queue.jobs.is_empty() || !queue.bucket.is_full(now));
+  }
+
+  fn is_idle(&self) -> bool {
+    self.in_flight == 0 && self.rotation.is_empty()
+  }
+
+  /// 尽可能多地派发任务，返回下一次需要醒来的时间。
+  fn dispatch(&mut self, done_tx: &mpsc::UnboundedSender<i64>) -> Option<Instant> {
+    loop {
+      if self.in_flight >= self.config.max_in_flight {
+        // 等待有任务完成。
+        return None;
+      }
+      let now = Instant::now();
+      // 按优先级从高到低、同优先级按轮转顺序挑选第一个令牌充足的群。
+      let mut candidates = self
+        .rotation
+        .iter()
+        .enumerate()
+        .filter(|(_, group_id)| !self.groups[*group_id].in_flight)
+        .map(|(index, group_id)| (self.groups[group_id].priority(), index, *group_id))
+        .collect::<Vec<_>>();
+      candidates.sort_by_key(|(priority, index, _)| (std::cmp::Reverse(*priority), *index));
+
+      let mut earliest: Option<Duration> = None;
+      let mut picked = None;
+      for (_, index, group_id) in candidates {
+        let wait = self
+          .groups
+          .get_mut(&group_id)
+          .expect("group in rotation")
+          .bucket
+          .wait_time(now);
+        if wait.is_zero() {
+          picked = Some((index, group_id));
+          break;
+        }
+        earliest = Some(earliest.map_or(wait, |e| e.min(wait)));
+      }
+      let Some((index, group_id)) = picked else {
+        return earliest.map(|wait| now + wait);
+      };
+
+      let global_wait = self.global.wait_time(now);
+      if !global_wait.is_zero() {
+        return Some(now + global_wait);
+      }
+      self.global.take();
+
+      let queue = self.groups.get_mut(&group_id).expect("group in rotation");
+      queue.bucket.take();
+      queue.in_flight = true;
+      let job = queue.jobs.pop_front().expect("non-empty queue");
+      self.rotation.remove(index);
+      self.in_flight += 1;
+      trace!(group_id, priority = ?job.priority, "dispatching outbound message");
+
+      let done_tx = done_tx.clone();
+      tokio::spawn(async move {
+        job.run.await;
+        let _ = done_tx.send(group_id);
+      });
+    }
+  }
+
+  fn complete(&mut self, group_id: i64) {
+    self.in_flight -= 1;
+    let Some(queue) = self.groups.get_mut(&group_id) else {
+      return;
+    };
+    queue.in_flight = false;
+    if !queue.jobs.is_empty() && !self.rotation.contains(&group_id) {
+      self.rotation.push_back(group_id);
+    }
+  }
+
+  async fn run(mut self) {
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let mut closed = false;
+    let mut wakeup: Option<Instant> = None;
+    loop {
+      let sleep = async {
+        match wakeup {
+          Some(deadline) => tokio::time::sleep_until(deadline).await,
+          None => std::future::pending().await,
+        }
+      };
+      tokio::select! {
+        job = self.rx.recv(), if !closed => match job {
+          Some((group_id, job)) => self.enqueue(group_id, job),
+          None => closed = true,
+        },
+        Some(group_id) = done_rx.recv() => self.complete(group_id),
+        _ = sleep => {}
+      }
+      self.prune();
+      if closed && self.is_idle() {
+        debug!("send scheduler stopped");
+        break;
+      }
+      wakeup = self.dispatch(&done_tx);
+    }
+  }
+}
+
+/// 出站消息调度器。
+///
+/// 每个群有自己的令牌桶，所有群共享一个全局令牌桶和并发上限；同一个群的消息严格按提交顺序逐条发送。
+pub struct SendScheduler {
+  tx: mpsc::UnboundedSender<(i64, Job)>,
+  worker: std::sync::Mutex<Option<Worker>>,
+}
+
+impl SendScheduler {
+  pub fn new(config: SchedulerConfig) -> Self {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let global = TokenBucket::new(config.global, Instant::now());
+    Self {
+      tx,
+      worker: std::sync::Mutex::new(Some(Worker {
+        config,
+        rx,
+        groups: HashMap::new(),
+        rotation: VecDeque::new(),
+        global,
+        in_flight: 0,
+      })),
+    }
+  }
+
+  // 构造时可能还不在 tokio 运行时里，第一次提交时再启动 worker。
+  fn ensure_running(&self) {
+    if let Some(worker) = self.worker.lock().expect("poisoned").take() {
+      tokio::spawn(worker.run());
+    }
+  }
+
+  /// 排队等待发送。调用方不再等待（比如套在超时里、超时了）时，还没轮到的消息会被丢掉，不会再发出去。
+  pub async fn submit<T: Send + 'static>(
+    &self,
+    group_id: i64,
+    priority: SendPriority,
+    task: impl Future<Output = anyhow::Result<T>> + Send + 'static,
+  ) -> anyhow::Result<T> {
+    self.ensure_running();
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let run = Box::pin(async move {
+      let _ = reply_tx.send(task.await);
+    });
+    let abandoned = CancellationToken::new();
+    let _guard = abandoned.clone().drop_guard();
+    self
+      .tx
+      .send((
+        group_id,
+        Job {
+          priority,
+          run,
+          abandoned,
+        },
+      ))
+      .map_err(|_| anyhow::anyhow!("send scheduler stopped"))?;
+    reply_rx.await.context("send scheduler dropped the job")?
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use super::*;
+
+  fn limit(burst: u32, interval_ms: u64) -> RateLimit {
+    RateLimit {
+      burst,
+      interval: Duration::from_millis(interval_ms),
+    }
+  }
+
+  fn scheduler(
+    per_group: RateLimit,
+    global: RateLimit,
+    max_in_flight: usize,
+  ) -> Arc<SendScheduler> {
+    Arc::new(SendScheduler::new(SchedulerConfig {
+      per_group,
+      global,
+      max_in_flight,
+    }))
+  }
+
+  /// 发出顺序，以及相对测试开始的毫秒数。
+  type Log = Arc<Mutex<Vec<(&'static str, u64)>>>;
+
+  fn send(
+    scheduler: &Arc<SendScheduler>,
+    log: &Log,
+    group_id: i64,
+    priority: SendPriority,
+    name: &'static str,
+  ) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+    let scheduler = scheduler.clone();
+    let log = log.clone();
+    let start = Instant::now();
+    tokio::spawn(async move {
+      scheduler
+        .submit(group_id, priority, async move {
+          let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+          log.lock().unwrap().push((name, elapsed.round() as u64));
+          Ok(())
+        })
+        .await
+    })
+  }
+
+  async fn join(handles: Vec<tokio::task::JoinHandle<anyhow::Result<()>>>) {
+    for handle in handles {
+      handle.await.unwrap().unwrap();
+    }
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn limits_each_group_separately() {
+    let scheduler = scheduler(limit(2, 1000), limit(100, 1), 4);
+    let log = Log::default();
+    join(vec![
+      send(&scheduler, &log, 1, SendPriority::Normal, "a"),
+      send(&scheduler, &log, 1, SendPriority::Normal, "b"),
+      send(&scheduler, &log, 1, SendPriority::Normal, "c"),
+      send(&scheduler, &log, 1, SendPriority::Normal, "d"),
+      send(&scheduler, &log, 2, SendPriority::Normal, "e"),
+    ])
+    .await;
+    let mut log = log.lock().unwrap().clone();
+    log.sort();
+    // 先用掉攒下的两个令牌，之后每秒一条；别的群不受影响。
+    assert_eq!(
+      log,
+      [("a", 0), ("b", 0), ("c", 1000), ("d", 2000), ("e", 0)]
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn shares_the_global_burst() {
+    let scheduler = scheduler(limit(10, 1), limit(2, 500), 4);
+    let log = Log::default();
+    join(vec![
+      send(&scheduler, &log, 1, SendPriority::Normal, "a"),
+      send(&scheduler, &log, 2, SendPriority::Normal, "b"),
+      send(&scheduler, &log, 3, SendPriority::Normal, "c"),
+    ])
+    .await;
+    assert_eq!(*log.lock().unwrap(), [("a", 0), ("b", 0), ("c", 500)]);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn admin_messages_jump_other_groups() {
+    let scheduler = scheduler(limit(10, 1), limit(1, 1000), 4);
+    let log = Log::default();
+    join(vec![
+      send(&scheduler, &log, 1, SendPriority::Normal, "a"),
+      send(&scheduler, &log, 2, SendPriority::Normal, "b"),
+      send(&scheduler, &log, 3, SendPriority::Admin, "c"),
+      // 同群的普通消息不会被后来的管理员消息超过。
+      send(&scheduler, &log, 1, SendPriority::Normal, "d"),
+      send(&scheduler, &log, 1, SendPriority::Admin, "e"),
+    ])
+    .await;
+    let order = log
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|(name, _)| *name)
+      .collect::<Vec<_>>();
+    assert_eq!(order, ["a", "c", "d", "e", "b"]);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn drops_abandoned_jobs() {
+    let scheduler = scheduler(limit(1, 10_000), limit(100, 1), 4);
+    let log = Log::default();
+    join(vec![send(&scheduler, &log, 1, SendPriority::Normal, "a")]).await;
+    let log2 = log.clone();
+    let abandoned = tokio::time::timeout(
+      Duration::from_millis(1000),
+      scheduler.submit(1, SendPriority::Normal, async move {
+        log2.lock().unwrap().push(("b", 0));
+        Ok(())
+      }),
+    )
+    .await;
+    assert!(abandoned.is_err());
+    join(vec![send(&scheduler, &log, 1, SendPriority::Normal, "c")]).await;
+    let order = log
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|(name, _)| *name)
+      .collect::<Vec<_>>();
+    assert_eq!(order, ["a", "c"]);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn prunes_idle_groups() {
+    let scheduler = SendScheduler::new(SchedulerConfig {
+      per_group: limit(2, 1000),
+      global: limit(100, 1),
+      max_in_flight: 4,
+    });
+    let mut worker = scheduler.worker.lock().unwrap().take().unwrap();
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    worker.enqueue(
+      1,
+      Job {
+        priority: SendPriority::Normal,
+        run: Box::pin(async {}),
+        abandoned: CancellationToken::new(),
+      },
+    );
+    worker.dispatch(&done_tx);
+    let group_id = done_rx.recv().await.unwrap();
+    worker.complete(group_id);
+    worker.prune();
+    // 令牌还没补满，留着才能继续限速。
+    assert!(worker.groups.contains_key(&1));
+    tokio::time::advance(Duration::from_millis(1000)).await;
+    worker.prune();
+    assert!(worker.groups.is_empty());
+    assert!(worker.is_idle());
+  }
+}
@@ -0,0 +1,127 @@
+use std::{ffi::c_void, ptr};
+
+use napi::{
+  Error,
+  bindgen_prelude::{FromNapiValue, ToNapiValue},
+  check_status, sys,
+};
+use tokio_util::sync::CancellationToken;
+
+/// JS 的 `AbortSignal`，转换成 Rust 侧的 `CancellationToken`。
+///
+/// napi 自带的 `AbortSignal` 只能配合 `AsyncTask` 使用，这里在参数转换时直接往 signal 上挂一个
+/// `abort` 监听器。
+#[derive(Debug, Clone)]
+pub struct CancelSignal(pub CancellationToken);
+
+unsafe extern "C" fn on_abort(
+  env: sys::napi_env,
+  info: sys::napi_callback_info,
+) -> sys::napi_value {
+  let mut data = ptr::null_mut();
+  unsafe {
+    sys::napi_get_cb_info(
+      env,
+      info,
+      ptr::null_mut(),
+      ptr::null_mut(),
+      ptr::null_mut(),
+      &mut data,
+    );
+    if let Some(token) = (data as *const CancellationToken).as_ref() {
+      token.cancel();
+    }
+  }
+  ptr::null_mut()
+}
+
+unsafe extern "C" fn finalize_token(_env: sys::napi_env, data: *mut c_void, _hint: *mut c_void) {
+  drop(unsafe { Box::from_raw(data as *mut CancellationToken) });
+}
+
+impl FromNapiValue for CancelSignal {
+  unsafe fn from_napi_value(env: sys::napi_env, napi_val: sys::napi_value) -> Result<Self, Error> {
+    let token = CancellationToken::new();
+    unsafe {
+      let mut aborted = ptr::null_mut();
+      check_status!(
+        sys::napi_get_named_property(env, napi_val, c"aborted".as_ptr(), &mut aborted),
+        "Expected an AbortSignal"
+      )?;
+      if bool::from_napi_value(env, aborted).unwrap_or_default() {
+        token.cancel();
+        return Ok(CancelSignal(token));
+      }
+
+      // 监听器持有一份 token，随监听器函数一起被回收。
+      let data = Box::into_raw(Box::new(token.clone())) as *mut c_void;
+      let mut listener = ptr::null_mut();
+      check_status!(
+        sys::napi_create_function(
+          env,
+          c"onAbort".as_ptr(),
+          -1,
+          Some(on_abort),
+          data,
+          &mut listener,
+        ),
+        "Failed to create abort listener"
+      )?;
+      check_status!(
+        sys::napi_add_finalizer(
+          env,
+          listener,
+          data,
+          Some(finalize_token),
+          ptr::null_mut(),
+          ptr::null_mut(),
+        ),
+        "Failed to attach abort listener finalizer"
+      )?;
+
+      let mut add_event_listener = ptr::null_mut();
+      check_status!(
+        sys::napi_get_named_property(
+          env,
+          napi_val,
+          c"addEventListener".as_ptr(),
+          &mut add_event_listener,
+        ),
+        "Expected an AbortSignal"
+      )?;
+      // `once` 让监听器触发后自动移除；没触发的随 signal 一起回收。
+      let mut options = ptr::null_mut();
+      check_status!(
+        sys::napi_create_object(env, &mut options),
+        "Failed to create listener options"
+      )?;
+      check_status!(
+        sys::napi_set_named_property(
+          env,
+          options,
+          c"once".as_ptr(),
+          bool::to_napi_value(env, true)?
+        ),
+        "Failed to create listener options"
+      )?;
+      let args = [
+        String::to_napi_value(env, "abort".to_owned())?,
+        listener,
+        options,
+      ];
+      let mut result = ptr::null_mut();
+      check_status!(
+        sys::napi_call_function(
+          env,
+          napi_val,
+          add_event_listener,
+          args.len(),
+          args.as_ptr(),
+          &mut result,
+        ),
+        "Failed to listen on AbortSignal"
+      )?;
+    }
+    Ok(CancelSignal(token))
+  }
+}
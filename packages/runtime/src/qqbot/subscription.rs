@@ -0,0 +1,299 @@
+use std::{
+  collections::VecDeque,
+  sync::{
+    Arc,
+    atomic::{AtomicU32, Ordering},
+  },
+};
+
+use napi::{
+  Status,
+  bindgen_prelude::AsyncGenerator,
+  threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode},
+  tokio::{
+    self,
+    sync::{Mutex, mpsc},
+  },
+};
+use napi_derive::napi;
+use tokio_util::sync::CancellationToken;
+
+use crate::qqbot::{
+  bus::{ReplayOptions, Subscriber},
+  event::{Event, SequencedEvent},
+};
+
+/// 事件过滤条件。各字段之间是“且”的关系；不带群号或 self id 的事件（如 `Connected`）不受对应条件限制。
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+  /// 事件类型，即 `Event` 的 `type` 字段，如 `GroupMessage`。
+  pub types: Option<Vec<String>>,
+  pub group_ids: Option<Vec<String>>,
+  pub self_id: Option<String>,
+}
+
+impl EventFilter {
+  pub fn matches(&self, event: &Event) -> bool {
+    if let Some(types) = &self.types
+      && !types.iter().any(|x| x == event.type_name())
+    {
+      return false;
+    }
+    if let (Some(group_ids), Some(group_id)) = (&self.group_ids, event.group_id())
+      && !group_ids.iter().any(|x| x == group_id)
+    {
+      return false;
+    }
+    if let (Some(expected), Some(self_id)) = (&self.self_id, event.self_id())
+      && expected != self_id
+    {
+      return false;
+    }
+    true
+  }
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+  /// 收到就转发给 JS，回调处理慢会拖慢整个事件总线。
+  #[default]
+  Blocking,
+  /// 先放进订阅自己的缓冲区，JS 回调返回后再投递下一条；缓冲区满时按 `overflow` 丢弃。
+  Queued,
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+  #[default]
+  DropOldest,
+  DropNewest,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct SubscribeOptions {
+  pub filter: Option<EventFilter>,
+  pub mode: Option<DeliveryMode>,
+  /// `Queued` 模式下的缓冲区大小，默认 256。
+  pub capacity: Option<u32>,
+  pub overflow: Option<OverflowPolicy>,
+  pub replay: Option<ReplayOptions>,
+}
+
+/// `register_callback` 返回的订阅句柄。
+#[napi]
+pub struct Subscription {
+  cancel: CancellationToken,
+  dropped: Arc<AtomicU32>,
+}
+
+#[napi]
+impl Subscription {
+  /// 停止投递。已经交给 JS 的事件仍会执行，之后不会再收到任何事件。
+  #[napi]
+  pub fn unsubscribe(&self) {
+    self.cancel.cancel();
+  }
+  #[napi(getter)]
+  pub fn active(&self) -> bool {
+    !self.cancel.is_cancelled()
+  }
+  /// 因缓冲区溢出被丢弃的事件数。
+  #[napi(getter)]
+  pub fn dropped(&self) -> u32 {
+    self.dropped.load(Ordering::Relaxed)
+  }
+}
+
+pub fn subscribe(
+  rx: Subscriber,
+  callback: ThreadsafeFunction<SequencedEvent>,
+  options: SubscribeOptions,
+) -> Subscription {
+  let cancel = CancellationToken::new();
+  let dropped = Arc::new(AtomicU32::new(0));
+  let filter = options.filter.unwrap_or_default();
+  match options.mode.unwrap_or_default() {
+    DeliveryMode::Blocking => {
+      tokio::spawn(forward_blocking(rx, callback, filter, cancel.clone()));
+    }
+    DeliveryMode::Queued => {
+      tokio::spawn(forward_queued(
+        rx,
+        callback,
+        filter,
+        cancel.clone(),
+        QueueLimit {
+          capacity: options.capacity.unwrap_or(256).max(1) as usize,
+          overflow: options.overflow.unwrap_or_default(),
+          dropped: dropped.clone(),
+        },
+      ));
+    }
+  }
+  Subscription { cancel, dropped }
+}
+
+async fn forward_blocking(
+  mut rx: Subscriber,
+  callback: ThreadsafeFunction<SequencedEvent>,
+  filter: EventFilter,
+  cancel: CancellationToken,
+) {
+  loop {
+    tokio::select! {
+      _ = cancel.cancelled() => return,
+      next = rx.recv() => match next {
+        Some(event) => {
+          if filter.matches(&event.event) {
+            callback.call(Ok(event), ThreadsafeFunctionCallMode::Blocking);
+          }
+        }
+        None => return,
+      },
+    }
+  }
+}
+
+struct QueueLimit {
+  capacity: usize,
+  overflow: OverflowPolicy,
+  dropped: Arc<AtomicU32>,
+}
+
+async fn forward_queued(
+  mut rx: Subscriber,
+  callback: ThreadsafeFunction<SequencedEvent>,
+  filter: EventFilter,
+  cancel: CancellationToken,
+  limit: QueueLimit,
+) {
+  let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+  let mut buffer = VecDeque::new();
+  let mut in_flight = false;
+  let mut closed = false;
+  loop {
+    if !in_flight {
+      match buffer.pop_front() {
+        Some(event) => {
+          let done_tx = done_tx.clone();
+          let status = callback.call_with_return_value(
+            Ok(event),
+            ThreadsafeFunctionCallMode::NonBlocking,
+            move |_, _| {
+              let _ = done_tx.send(());
+              Ok(())
+            },
+          );
+          if status != Status::Ok {
+            // JS 侧已经在关闭了。
+            return;
+          }
+          in_flight = true;
+        }
+        None if closed => break,
+        None => {}
+      }
+    }
+    tokio::select! {
+      _ = cancel.cancelled() => return,
+      Some(()) = done_rx.recv() => in_flight = false,
+      next = rx.recv(), if !closed => match next {
+        Some(event) => {
+          if !filter.matches(&event.event) {
+            continue;
+          }
+          if buffer.len() >= limit.capacity {
+            limit.dropped.fetch_add(1, Ordering::Relaxed);
+            match limit.overflow {
+              OverflowPolicy::DropOldest => {
+                buffer.pop_front();
+              }
+              OverflowPolicy::DropNewest => continue,
+            }
+          }
+          buffer.push_back(event);
+        }
+        None => closed = true,
+      },
+    }
+  }
+}
+
+/// `for await (const ev of endpoint.events())` 用的事件流。`terminate` 引起的 `Closed` 产出后迭代结束；
+/// 断线、自动重连时的 `Closed` 不会结束迭代。连接池合并后的流一直开着。
+#[napi(async_iterator)]
+pub struct EventStream {
+  rx: Arc<Mutex<Option<Subscriber>>>,
+  filter: Arc<EventFilter>,
+  /// 连接池里每个账号都会发 `Closed`，合并后的流不能因此结束。
+  end_on_terminate: bool,
+}
+
+impl EventStream {
+  pub fn new(rx: Subscriber, filter: EventFilter) -> Self {
+    Self {
+      rx: Arc::new(Mutex::new(Some(rx))),
+      filter: Arc::new(filter),
+      end_on_terminate: true,
+    }
+  }
+
+  pub fn merged(rx: Subscriber, filter: EventFilter) -> Self {
+    Self {
+      end_on_terminate: false,
+      ..Self::new(rx, filter)
+    }
+  }
+}
+
+impl AsyncGenerator for EventStream {
+  type Yield = SequencedEvent;
+  type Next = ();
+  type Return = ();
+
+  fn next(
+    &mut self,
+    _value: Option<Self::Next>,
+  ) -> impl Future<Output = napi::Result<Option<Self::Yield>>> + Send + 'static {
+    let rx = self.rx.clone();
+    let filter = self.filter.clone();
+    let end_on_terminate = self.end_on_terminate;
+    async move {
+      let mut rx = rx.lock().await;
+      let Some(receiver) = rx.as_mut() else {
+        return Ok(None);
+      };
+      while let Some(event) = receiver.recv().await {
+        let end = end_on_terminate && matches!(event.event, Event::Closed { terminated: true });
+        if end {
+          // 先把 `Closed` 交出去，下一次再结束；不再占用总线的位置。
+          *rx = None;
+        }
+        if filter.matches(&event.event) {
+          return Ok(Some(event));
+        }
+        if end {
+          return Ok(None);
+        }
+      }
+      *rx = None;
+      Ok(None)
+    }
+  }
+
+  fn complete(
+    &mut self,
+    value: Option<Self::Return>,
+  ) -> impl Future<Output = napi::Result<Option<Self::Return>>> + Send + 'static {
+    // `break` 跳出循环时释放接收端，否则没人读的接收端会让总线写满后卡住。
+    let rx = self.rx.clone();
+    async move {
+      rx.lock().await.take();
+      Ok(value)
+    }
+  }
+}
@@ -0,0 +1,375 @@
+use std::{
+  collections::HashMap,
+  path::PathBuf,
+  sync::{
+    Arc, Mutex as StdMutex,
+    atomic::{AtomicU64, Ordering},
+  },
+  time::Duration,
+};
+
+use anyhow::{Context, anyhow, bail};
+use futures_util::{SinkExt, StreamExt};
+use napi::tokio::{
+  self,
+  sync::{broadcast, mpsc, oneshot},
+};
+use onebot_v11::api::{payload::ApiPayload, resp::ApiResp};
+use reqwest::Url;
+use rustls::{
+  ClientConfig, RootCertStore,
+  pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+use secrecy::{ExposeSecret, SecretString};
+use tokio_tungstenite::{
+  Connector,
+  tungstenite::{
+    Message,
+    client::IntoClientRequest,
+    http::{HeaderMap, HeaderName, HeaderValue, header::AUTHORIZATION},
+  },
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use super::error::BridgeError;
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+  /// 额外信任的 CA 证书（PEM），和内置的根证书一起使用。
+  pub ca_file: Option<PathBuf>,
+  /// 客户端证书链（PEM），要和 `client_key` 一起设置。
+  pub client_cert: Option<PathBuf>,
+  pub client_key: Option<PathBuf>,
+}
+
+/// 连接参数。`addr` 可以是完整的 URL，也可以是旧配置里的 `host:port`。
+#[derive(Debug, Clone)]
+pub struct ConnectOptions<'a> {
+  pub addr: &'a str,
+  pub access_token: &'a SecretString,
+  pub headers: &'a HashMap<String, SecretString>,
+  pub tls: &'a TlsConfig,
+  /// mirai-api-http 绑定的账号，OneBot 用不到。
+  pub qq: Option<&'a str>,
+}
+
+/// `http(s)://` 按 `ws(s)://` 处理，方便沿用 HTTP 风格的配置。
+pub fn parse_addr(addr: &str) -> anyhow::Result<Url> {
+  let mut url = if addr.contains("://") {
+    Url::parse(addr)
+  } else {
+    Url::parse(&format!("ws://{addr}"))
+  }
+  .with_context(|| format!("Invalid OneBot address {addr}"))?;
+  let scheme = match url.scheme() {
+    "ws" | "http" => "ws",
+    "wss" | "https" => "wss",
+    other => bail!("Unsupported OneBot address scheme {other}"),
+  };
+  url
+    .set_scheme(scheme)
+    .map_err(|_| anyhow!("Invalid OneBot address {addr}"))?;
+  Ok(url)
+}
+
+/// 日志里用的地址，去掉 query 和密码，它们可能带着 token。
+pub fn redacted_addr(addr: &str) -> String {
+  match parse_addr(addr) {
+    Ok(mut url) => {
+      url.set_query(None);
+      let _ = url.set_password(None);
+      url.to_string()
+    }
+    Err(_) => addr.to_owned(),
+  }
+}
+
+pub(crate) fn tls_config(tls: &TlsConfig) -> anyhow::Result<Arc<ClientConfig>> {
+  let mut roots = RootCertStore {
+    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+  };
+  if let Some(path) = &tls.ca_file {
+    for cert in CertificateDer::pem_file_iter(path)
+      .with_context(|| format!("Failed to read CA file {}", path.display()))?
+    {
+      roots.add(cert?)?;
+    }
+  }
+  // reqwest 已经带了 ring，这里显式指定，避免依赖全局默认的 provider。
+  let builder =
+    ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+      .with_safe_default_protocol_versions()?
+      .with_root_certificates(roots);
+  let config = match (&tls.client_cert, &tls.client_key) {
+    (Some(cert), Some(key)) => {
+      let chain = CertificateDer::pem_file_iter(cert)
+        .with_context(|| format!("Failed to read client certificate {}", cert.display()))?
+        .collect::<Result<Vec<_>, _>>()?;
+      let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Failed to read client key {}", key.display()))?;
+      builder.with_client_auth_cert(chain, key)?
+    }
+    (None, None) => builder.with_no_client_auth(),
+    _ => bail!("clientCert and clientKey must be set together"),
+  };
+  Ok(Arc::new(config))
+}
+
+type Pending = StdMutex<HashMap<String, oneshot::Sender<serde_json::Value>>>;
+
+/// 把 `ConnectOptions` 里的请求头转成握手用的格式。
+pub(crate) fn extra_headers(headers: &HashMap<String, SecretString>) -> anyhow::Result<HeaderMap> {
+  let mut map = HeaderMap::new();
+  for (name, value) in headers {
+    map.insert(
+      HeaderName::from_bytes(name.as_bytes())?,
+      HeaderValue::from_str(value.expose_secret())?,
+    );
+  }
+  Ok(map)
+}
+
+/// 一条 JSON over WebSocket 连接：读写各一个任务，请求按 id 和响应配对。
+///
+/// 连接断开后读取任务退出，它持有的东西（比如事件的发送端）随之释放。请求没发出去时以 `NotConnected`
+/// 失败；已经发出去、等不到响应的请求可能已经生效，报别的错误。
+pub(crate) struct Socket {
+  outgoing: mpsc::UnboundedSender<Message>,
+  pending: Arc<Pending>,
+  closed: CancellationToken,
+}
+
+impl Socket {
+  /// `on_frame` 处理收到的每一帧，是响应时返回 `(id, 响应)`。
+  pub(crate) async fn open<F>(
+    url: &Url,
+    headers: HeaderMap,
+    tls: &TlsConfig,
+    mut on_frame: F,
+  ) -> anyhow::Result<Self>
+  where
+    F: FnMut(serde_json::Value) -> Option<(String, serde_json::Value)> + Send + 'static,
+  {
+    let mut request = url.as_str().into_client_request()?;
+    request.headers_mut().extend(headers);
+    let connector = match url.scheme() {
+      "wss" => Some(Connector::Rustls(tls_config(tls)?)),
+      _ => None,
+    };
+    let (stream, _) =
+      tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector)
+        .await
+        .with_context(|| format!("Failed to connect to {}", redacted_addr(url.as_str())))?;
+
+    let (mut sink, mut stream) = stream.split();
+    let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+    let pending = Arc::new(Pending::default());
+    let closed = CancellationToken::new();
+
+    tokio::spawn({
+      let closed = closed.clone();
+      async move {
+        loop {
+          tokio::select! {
+            _ = closed.cancelled() => break,
+            Some(message) = outgoing_rx.recv() => {
+              if let Err(err) = sink.send(message).await {
+                warn!("Failed to write to WebSocket: {err:#}");
+                break;
+              }
+            }
+          }
+        }
+        let _ = sink.close().await;
+        closed.cancel();
+      }
+    });
+    tokio::spawn({
+      let pending = pending.clone();
+      let closed = closed.clone();
+      async move {
+        loop {
+          let message = tokio::select! {
+            _ = closed.cancelled() => break,
+            message = stream.next() => message,
+          };
+          let text = match message {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(frame))) => {
+              debug!(?frame, "Server closed the WebSocket");
+              break;
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(err)) => {
+              warn!("WebSocket error: {err:#}");
+              break;
+            }
+            None => break,
+          };
+          let value = match serde_json::from_str(&text) {
+            Ok(value) => value,
+            Err(err) => {
+              warn!("Invalid WebSocket frame: {err}");
+              continue;
+            }
+          };
+          if let Some((id, resp)) = on_frame(value)
+            && let Some(tx) = pending.lock().unwrap().remove(&id)
+          {
+            let _ = tx.send(resp);
+          }
+        }
+        closed.cancel();
+        // 让等待中的调用立即失败。
+        pending.lock().unwrap().clear();
+      }
+    });
+
+    Ok(Self {
+      outgoing,
+      pending,
+      closed,
+    })
+  }
+
+  pub(crate) async fn request(
+    &self,
+    id: String,
+    frame: serde_json::Value,
+  ) -> anyhow::Result<serde_json::Value> {
+    if self.closed.is_cancelled() {
+      return Err(BridgeError::NotConnected.into());
+    }
+    let (tx, rx) = oneshot::channel();
+    self.pending.lock().unwrap().insert(id.clone(), tx);
+    // 超时或取消时 future 会被直接丢掉，靠它清理登记。
+    let _guard = PendingGuard {
+      pending: &self.pending,
+      id,
+    };
+    self
+      .outgoing
+      .send(Message::Text(frame.to_string()))
+      .map_err(|_| BridgeError::NotConnected)?;
+    rx.await
+      .map_err(|_| anyhow!("Connection closed before the response arrived"))
+  }
+
+  /// 发出一帧，不等响应。
+  pub(crate) fn send(&self, frame: serde_json::Value) -> anyhow::Result<()> {
+    self
+      .outgoing
+      .send(Message::Text(frame.to_string()))
+      .map_err(|_| BridgeError::NotConnected)?;
+    Ok(())
+  }
+
+  /// 按固定间隔发送应用层心跳，随连接关闭结束。
+  pub(crate) fn keepalive(&self, interval: Duration, frame: serde_json::Value) {
+    let outgoing = self.outgoing.clone();
+    let closed = self.closed.clone();
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+      loop {
+        tokio::select! {
+          _ = closed.cancelled() => return,
+          _ = ticker.tick() => {
+            if outgoing.send(Message::Text(frame.to_string())).is_err() {
+              return;
+            }
+          }
+        }
+      }
+    });
+  }
+}
+
+impl Drop for Socket {
+  fn drop(&mut self) {
+    self.closed.cancel();
+  }
+}
+
+struct PendingGuard<'a> {
+  pending: &'a Pending,
+  id: String,
+}
+
+impl Drop for PendingGuard<'_> {
+  fn drop(&mut self) {
+    self.pending.lock().unwrap().remove(&self.id);
+  }
+}
+
+/// OneBot v11 正向 WebSocket 连接。API 调用按 `echo` 配对，其余帧按事件解析后广播。
+///
+/// 连接断开后 `subscribe` 得到的接收端会收到 `Closed`。
+pub struct OneBotWs {
+  socket: Socket,
+  // 只留一个接收端用来 `resubscribe`，发送端只在读取任务里，断线时随之释放。
+  events: broadcast::Receiver<onebot_v11::Event>,
+  next_echo: AtomicU64,
+}
+
+impl OneBotWs {
+  pub async fn connect(options: ConnectOptions<'_>) -> anyhow::Result<Self> {
+    let url = parse_addr(options.addr)?;
+    let mut headers = extra_headers(options.headers)?;
+    let token = options.access_token.expose_secret();
+    if !token.is_empty() {
+      headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {token}"))?,
+      );
+    }
+    let (events_tx, events) = broadcast::channel(1024);
+    let socket = Socket::open(&url, headers, options.tls, move |value| {
+      dispatch(value, &events_tx)
+    })
+    .await?;
+    Ok(Self {
+      socket,
+      events,
+      next_echo: AtomicU64::new(1),
+    })
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<onebot_v11::Event> {
+    self.events.resubscribe()
+  }
+
+  pub async fn call_api(&self, payload: ApiPayload) -> anyhow::Result<ApiResp> {
+    let echo = self.next_echo.fetch_add(1, Ordering::Relaxed).to_string();
+    // `ApiPayload` 序列化成 `{ action, params }`，补上 `echo` 就是完整的请求帧。
+    let mut frame = serde_json::to_value(&payload)?;
+    let Some(object) = frame.as_object_mut() else {
+      bail!("ApiPayload is not serialized as an object");
+    };
+    object.insert("echo".to_owned(), echo.clone().into());
+    let resp = self.socket.request(echo, frame).await?;
+    Ok(serde_json::from_value(resp)?)
+  }
+}
+
+fn dispatch(
+  value: serde_json::Value,
+  events: &broadcast::Sender<onebot_v11::Event>,
+) -> Option<(String, serde_json::Value)> {
+  if let Some(echo) = value.get("echo") {
+    // 有的实现会把字符串 echo 原样转成数字。
+    let echo = match echo {
+      serde_json::Value::String(x) => x.clone(),
+      other => other.to_string(),
+    };
+    return Some((echo, value));
+  }
+  match serde_json::from_value(value) {
+    Ok(event) => {
+      // 没有订阅者时丢掉即可。
+      let _ = events.send(event);
+    }
+    Err(err) => debug!("Unrecognized OneBot frame: {err}"),
+  }
+  None
+}